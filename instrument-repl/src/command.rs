@@ -1,6 +1,6 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, fmt::Write as _, path::PathBuf, time::Duration};
 
-use crate::TspError;
+use crate::{transcript::Transcript, tsp_error::Severity, TspError};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum SaveMethod {
@@ -8,6 +8,8 @@ pub enum SaveMethod {
         file: PathBuf,
     },
     Start,
+    /// Record a JSON-lines transcript until `End`
+    Transcript,
     End,
     Buffers {
         names: Vec<String>,
@@ -20,6 +22,138 @@ pub enum SaveMethod {
 pub struct Save {
     pub method: SaveMethod,
     pub output: PathBuf,
+    /// The transcript that is being recorded, once `.save --transcript` has started
+    pub transcript: Option<Transcript>,
+}
+
+/// An action to perform on the scripts saved on the instrument.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ScriptsAction {
+    List,
+    Show { name: String },
+    Pull { names: Vec<String>, output: PathBuf },
+    Delete { name: String },
+    Autorun { name: String, enable: bool },
+    Run { name: String },
+}
+
+/// An action on the errors reported by the instrument.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ErrorsAction {
+    /// Explain an error code using the error catalog
+    Explain { code: i64 },
+    /// Hide errors below the given severity
+    Filter { severity: Severity },
+}
+
+/// A lookup in the command reference.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DocAction {
+    /// Show the documentation of a command
+    Show { command: String },
+    /// List the commands that best match a query
+    Search { query: String },
+}
+
+/// An action on the expressions that are watched.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WatchAction {
+    /// List the watched expressions and their last values
+    List,
+    /// Evaluate an expression every `every`, logging the values to `log`
+    Add {
+        expression: String,
+        every: Duration,
+        log: Option<PathBuf>,
+    },
+    /// Stop watching the expression with the given number, or all expressions
+    Remove { number: Option<usize> },
+}
+
+/// An action on the macros defined by the user.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MacroAction {
+    /// List the macros and their bodies
+    List,
+    /// Define a macro, replacing any macro with the same name
+    Define { name: String, body: String },
+    /// Remove a macro
+    Remove { name: String },
+}
+
+/// The part of the instrument that TSP commands are routed to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    /// A TSP-Link node, `node[n]`
+    Node(u16),
+    /// A slot of a mainframe, `slot[n]`
+    Slot(u16),
+}
+
+impl Target {
+    /// The TSP expression that refers to this target.
+    #[must_use]
+    pub fn table(self) -> String {
+        match self {
+            Self::Node(n) => format!("node[{n}]"),
+            Self::Slot(n) => format!("slot[{n}]"),
+        }
+    }
+
+    /// Wrap a TSP chunk so that names are looked up in this target before the global
+    /// environment, e.g. `smu.source.level` refers to `slot[n].smu.source.level`.
+    #[must_use]
+    pub fn wrap(self, chunk: &str) -> String {
+        format!("_KIC.run_on({}, {})", self.table(), quoted_string(chunk))
+    }
+
+    /// The TSP that runs `chunk` on this target from a script, which is only possible
+    /// for TSP-Link nodes.
+    #[must_use]
+    pub fn export(self, chunk: &str) -> Option<String> {
+        match self {
+            Self::Node(n) => Some(format!("node[{n}].execute({})", quoted_string(chunk))),
+            Self::Slot(_) => None,
+        }
+    }
+
+    /// A TSP command that prints a warning if this target is not available.
+    #[must_use]
+    pub fn check(self) -> String {
+        let table = self.table();
+        format!(
+            "if not pcall(function() local t = {table} if t == nil then error() end end) then print(\"{table} is not available\") end"
+        )
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.table())
+    }
+}
+
+/// Quote `s` as a Lua string. Long strings can't be used since they nest in Lua 5.0,
+/// so the `]]` in `t[b[1]]` would end one.
+fn quoted_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len().saturating_add(2));
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_ascii_control() => {
+                let _ = write!(quoted, "\\{:03}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
 
 /// A request from a user that is to be dispatched within the program.
@@ -31,13 +165,47 @@ pub enum Request {
     GetError,
     Script {
         file: PathBuf,
+        /// Whether the script's syntax is checked before it is uploaded
+        check_syntax: bool,
+        /// Whether the directives of the script are expanded before it is uploaded
+        preprocess: bool,
+        /// The names defined with `-D`, which override their definitions in the script
+        defines: BTreeMap<String, String>,
     },
     TspLinkNodes {
         json_file: PathBuf,
     },
+    Scripts(ScriptsAction),
     Info {
         slot: Option<usize>,
     },
+    Errors(ErrorsAction),
+    Doc(DocAction),
+    /// List the global variables, or the entries of the table at `path`
+    Vars {
+        path: Option<String>,
+        all: bool,
+    },
+    /// Plot the readings of a buffer or table, or sample an expression every
+    /// `interval` if `live`
+    Plot {
+        target: String,
+        live: bool,
+        interval: Duration,
+        samples: usize,
+        spark: bool,
+        unit: Option<String>,
+    },
+    Watch(WatchAction),
+    Macro(MacroAction),
+    /// Write the TSP commands that ran without errors to a script, wrapped in the
+    /// function `function` if given
+    Export {
+        file: PathBuf,
+        function: Option<String>,
+    },
+    /// Route TSP commands to the given target, or back to the local node if `None`
+    Route(Option<Target>),
     Upgrade {
         file: PathBuf,
         slot: Option<u16>,
@@ -54,6 +222,36 @@ pub enum Request {
     None,
 }
 
+impl Request {
+    /// Whether the request runs TSP on the instrument that a transcript does not record,
+    /// e.g. to load a script, so that a transcript with it can't be replayed.
+    #[must_use]
+    pub const fn runs_tsp(&self) -> bool {
+        matches!(
+            self,
+            Self::Script { .. }
+                | Self::Scripts(
+                    ScriptsAction::Delete { .. }
+                        | ScriptsAction::Autorun { .. }
+                        | ScriptsAction::Run { .. }
+                )
+                | Self::Upgrade { .. }
+                | Self::Reset
+                | Self::Save(Save {
+                    method: SaveMethod::Script { .. },
+                    ..
+                })
+        )
+    }
+}
+
+/// A [`Request`] and the line the user entered for it, after macros were expanded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UserInput {
+    pub line: String,
+    pub request: Request,
+}
+
 /// Responses from the program or instrument that a [`Request`] was sent to.
 pub enum Response {
     /// A response to be displayed to the user as text
@@ -72,3 +270,34 @@ pub enum Notification {
     /// instead of being directly displayed to the user.
     InternalApi(String),
 }
+
+#[cfg(test)]
+mod unit {
+    use super::{quoted_string, Target};
+    use kic_lib::tsp::check_syntax;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quoted_string("print(1)"), "\"print(1)\"");
+        assert_eq!(quoted_string("a = t[b[1]]"), "\"a = t[b[1]]\"");
+        assert_eq!(
+            quoted_string("print(\"a\\b\")\nx = 1\t"),
+            r#""print(\"a\\b\")\nx = 1\009""#
+        );
+    }
+
+    #[test]
+    fn wrap_target() {
+        assert_eq!(
+            Target::Slot(2).wrap("print(smu[1].source.level)"),
+            "_KIC.run_on(slot[2], \"print(smu[1].source.level)\")"
+        );
+        assert_eq!(check_syntax(&Target::Node(2).wrap("a = t[b[1]]")), Ok(()));
+        assert_eq!(Target::Node(3).to_string(), "node[3]");
+        assert_eq!(
+            Target::Node(3).export("print(1)").as_deref(),
+            Some("node[3].execute(\"print(1)\")")
+        );
+        assert_eq!(Target::Slot(1).export("print(1)"), None);
+    }
+}
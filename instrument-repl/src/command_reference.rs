@@ -0,0 +1,201 @@
+//! A reference of TSP commands, with their usage, valid values, defaults and examples
+//! for each model family, so that commands can be looked up without the manual.
+
+use std::{fmt::Display, sync::LazyLock};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use kic_lib::model::Family;
+
+const EMBEDDED_COMMANDS: &str = include_str!("./resources/commands.json");
+
+static REFERENCE: LazyLock<CommandReference> = LazyLock::new(|| {
+    serde_json::from_str(EMBEDDED_COMMANDS).expect("embedded command reference should be valid")
+});
+
+/// The SMU channel of 2600-series instruments, which is written `smuX` in the reference.
+static SMU_CHANNEL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^smu[a-d]\.").expect("SMU channel regex should be valid"));
+
+/// An index, which is written `[N]` in the reference.
+static INDEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[\d+\]").expect("index regex should be valid"));
+
+/// Get the command reference.
+#[must_use]
+pub fn reference() -> &'static CommandReference {
+    &REFERENCE
+}
+
+/// The documentation of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CommandEntry {
+    /// The command, with `smuX` standing for an SMU channel and `[N]` for an index
+    pub command: String,
+    /// Whether the command is an attribute or a function
+    pub kind: String,
+    /// What the command does
+    pub description: String,
+    /// How the command is written
+    pub usage: Vec<String>,
+    /// The values the command accepts
+    pub range: Option<String>,
+    /// The value after a reset
+    pub default: Option<String>,
+    /// Examples of the command
+    #[serde(default)]
+    pub examples: Vec<String>,
+    /// The model families that have the command, all families if empty
+    #[serde(default)]
+    pub families: Vec<Family>,
+}
+
+impl CommandEntry {
+    /// Whether the command is available on `family`. All commands are considered
+    /// available if the family is not known.
+    #[must_use]
+    pub fn is_available(&self, family: Option<&Family>) -> bool {
+        self.families.is_empty() || family.is_none_or(|f| self.families.contains(f))
+    }
+
+    /// The names of the families that have the command.
+    #[must_use]
+    pub fn family_names(&self) -> Vec<String> {
+        self.families
+            .iter()
+            .filter_map(|f| serde_json::to_value(f).ok())
+            .filter_map(|v| v.as_str().map(ToString::to_string))
+            .collect()
+    }
+}
+
+impl Display for CommandEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.command, self.kind)?;
+        if !self.families.is_empty() {
+            write!(f, " [{}]", self.family_names().join(", "))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  {}", self.description)?;
+        writeln!(f, "  Usage:")?;
+        for usage in &self.usage {
+            writeln!(f, "    {usage}")?;
+        }
+        if let Some(range) = &self.range {
+            writeln!(f, "  Valid values: {range}")?;
+        }
+        if let Some(default) = &self.default {
+            writeln!(f, "  Default: {default}")?;
+        }
+        if !self.examples.is_empty() {
+            write!(f, "  Example:")?;
+            for line in self.examples.iter().flat_map(|e| e.lines()) {
+                write!(f, "\n    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A collection of [`CommandEntry`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct CommandReference {
+    commands: Vec<CommandEntry>,
+}
+
+impl CommandReference {
+    /// Look up a command, e.g. `smua.measure.nplc`. An entry that is available on
+    /// `family` is preferred over one that is not.
+    #[must_use]
+    pub fn get(&self, command: &str, family: Option<&Family>) -> Option<&CommandEntry> {
+        let command = normalize(command);
+        let mut entries = self.commands.iter().filter(|e| e.command == command);
+        let available = entries.clone().find(|e| e.is_available(family));
+        available.or_else(|| entries.next())
+    }
+
+    /// Find the commands available on `family` that best match `query`, best match
+    /// first. The characters of `query` have to appear in the command in order, and
+    /// commands that contain `query` as a whole rank highest.
+    #[must_use]
+    pub fn search(&self, query: &str, family: Option<&Family>, limit: usize) -> Vec<&CommandEntry> {
+        let query = normalize(query).to_lowercase();
+        let mut matches: Vec<_> = self
+            .commands
+            .iter()
+            .filter(|e| e.is_available(family))
+            .filter_map(|e| fuzzy_score(&e.command.to_lowercase(), &query).map(|s| (s, e)))
+            .collect();
+        matches.sort_by(|(a, ea), (b, eb)| b.cmp(a).then_with(|| ea.command.cmp(&eb.command)));
+        matches.into_iter().take(limit).map(|(_, e)| e).collect()
+    }
+}
+
+/// Write `command` the way it is written in the reference.
+fn normalize(command: &str) -> String {
+    let command = command.trim().trim_end_matches("()");
+    let command = SMU_CHANNEL.replace(command, "smuX.");
+    INDEX.replace_all(&command, "[N]").into_owned()
+}
+
+/// Score how well `candidate` matches `query`, or `None` if it does not match. Whole
+/// matches score highest, then matches of consecutive characters, then matches near
+/// the end of the command, which is usually the most specific part.
+fn fuzzy_score(candidate: &str, query: &str) -> Option<i64> {
+    let length = i64::try_from(candidate.len()).unwrap_or(i64::MAX);
+    if let Some(position) = candidate.rfind(query) {
+        let from_end = length
+            .saturating_sub(i64::try_from(position).unwrap_or(i64::MAX))
+            .saturating_sub(i64::try_from(query.len()).unwrap_or(i64::MAX));
+        return Some(1000_i64.saturating_sub(from_end));
+    }
+
+    let mut score = 0_i64;
+    let mut previous: Option<usize> = None;
+    let mut chars = candidate.char_indices();
+    for q in query.chars() {
+        let (index, _) = chars.find(|(_, c)| *c == q)?;
+        score = match previous {
+            Some(p) if p.saturating_add(1) == index => score.saturating_add(10),
+            _ => score.saturating_add(1),
+        };
+        previous = Some(index);
+    }
+    Some(score.saturating_sub(length / 10))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::reference;
+    use kic_lib::model::Family;
+
+    #[test]
+    fn lookup() {
+        let r = reference();
+        let entry = r.get("smub.measure.nplc", Some(&Family::_26xx)).unwrap();
+        assert_eq!(entry.command, "smuX.measure.nplc");
+        assert_eq!(entry.range.as_deref(), Some("0.001 to 25"));
+        assert!(r.get("slot[2].model", None).is_some());
+        assert!(r.get("smu.reset()", Some(&Family::Tti)).is_some());
+
+        let tti = r.get("smu.measure.nplc", Some(&Family::_26xx)).unwrap();
+        assert!(!tti.is_available(Some(&Family::_26xx)));
+        assert!(r.get("not.a.command", None).is_none());
+    }
+
+    #[test]
+    fn search() {
+        let r = reference();
+        let found: Vec<_> = r
+            .search("nplc", Some(&Family::Tti), 10)
+            .into_iter()
+            .map(|e| e.command.as_str())
+            .collect();
+        assert_eq!(found, vec!["dmm.measure.nplc", "smu.measure.nplc"]);
+
+        let found = r.search("srclvl", Some(&Family::Tti), 1);
+        assert_eq!(found[0].command, "smu.source.level");
+        assert_eq!(r.search("zzz", None, 10).len(), 0);
+    }
+}
@@ -0,0 +1,307 @@
+//! Map the locations in errors reported by an instrument back to the lines of the local
+//! script file that was uploaded, and render them as source excerpts or as JSON
+//! diagnostics for editors.
+
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::Serialize;
+
+use kic_lib::{
+    instrument::{read_until, Instrument},
+    tsp::{Preprocessed, SyntaxError},
+};
+
+use crate::{
+    error::Result,
+    instrument::{ParsedResponse, ResponseParser},
+    resources::KIC_COMMON_TSP,
+    tsp_error::Severity,
+    TspError,
+};
+
+/// Locations given as `[string "chunk"]:line:` or `chunk:line:`.
+static CHUNK_LOCATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?:\[string "(?P<quoted>[^"]*)"\]|(?P<name>[A-Za-z_][A-Za-z0-9_]*)):(?P<line>\d+):"#,
+    )
+    .expect("chunk location regex should be valid")
+});
+
+/// The token an error was found at, given as `near 'token'`.
+static NEAR_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"near '(?P<token>[^']+)'").expect("near token regex should be valid")
+});
+
+/// A script that was uploaded to the instrument from a local file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    chunk: String,
+    path: PathBuf,
+    lines: Vec<String>,
+    /// The file and line that each line of the uploaded script came from
+    origins: Vec<(PathBuf, usize)>,
+}
+
+/// An error located in a local script file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// The local script file
+    pub file: PathBuf,
+    /// The line in the file, starting at 1
+    pub line: usize,
+    /// The column in the line, starting at 1
+    pub column: usize,
+    /// The severity of the error
+    pub severity: String,
+    /// The error code reported by the instrument
+    pub code: i64,
+    /// The message reported by the instrument
+    pub message: String,
+    /// The content of the line
+    #[serde(skip)]
+    pub source_line: String,
+}
+
+impl SourceMap {
+    /// Create the map for the script `chunk` that was loaded on the instrument from
+    /// `source`, the content of the file at `path`.
+    pub fn new(chunk: impl Into<String>, path: impl Into<PathBuf>, source: &str) -> Self {
+        let path = path.into();
+        let lines: Vec<String> = source.lines().map(ToString::to_string).collect();
+        let origins = (1..=lines.len()).map(|l| (path.clone(), l)).collect();
+        Self {
+            chunk: chunk.into(),
+            path,
+            lines,
+            origins,
+        }
+    }
+
+    /// Create the map for the script `chunk` that was loaded on the instrument from the
+    /// `preprocessed` script at `path`, so that errors are located in the file the line
+    /// came from, which may be an included file.
+    pub fn preprocessed(
+        chunk: impl Into<String>,
+        path: impl Into<PathBuf>,
+        preprocessed: &Preprocessed,
+    ) -> Self {
+        Self {
+            chunk: chunk.into(),
+            path: path.into(),
+            lines: preprocessed
+                .source
+                .lines()
+                .map(ToString::to_string)
+                .collect(),
+            origins: preprocessed.origins.clone(),
+        }
+    }
+
+    /// The local script file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Find the location of `error` in the local script file. Only errors that name
+    /// the chunk of the script, and refer to a line of it, are located.
+    #[must_use]
+    pub fn diagnostic(&self, error: &TspError) -> Option<Diagnostic> {
+        let message = error.message();
+        let line = CHUNK_LOCATION.captures_iter(message).find_map(|c| {
+            let chunk = c.name("quoted").or_else(|| c.name("name"))?.as_str();
+            (chunk == self.chunk).then(|| c["line"].parse::<usize>().ok())?
+        })?;
+        let source_line = self.lines.get(line.checked_sub(1)?)?.clone();
+        let (file, line) = self.origins.get(line.checked_sub(1)?)?.clone();
+
+        let column = NEAR_TOKEN
+            .captures(message)
+            .and_then(|c| source_line.find(&c["token"]))
+            .or_else(|| source_line.find(|c: char| !c.is_whitespace()))
+            .unwrap_or_default()
+            .saturating_add(1);
+
+        Some(Diagnostic {
+            file,
+            line,
+            column,
+            severity: error.severity().to_string(),
+            code: error.code(),
+            message: message.to_string(),
+            source_line,
+        })
+    }
+}
+
+impl Diagnostic {
+    /// The error code instruments report for syntax errors.
+    pub const SYNTAX_ERROR_CODE: i64 = -285;
+
+    /// Create the diagnostic for a syntax error found locally in `source`, the content
+    /// of the file at `path`.
+    #[must_use]
+    pub fn syntax_error(path: impl Into<PathBuf>, source: &str, error: &SyntaxError) -> Self {
+        Self {
+            file: path.into(),
+            line: error.line,
+            column: error.column,
+            severity: Severity::Error.to_string(),
+            code: Self::SYNTAX_ERROR_CODE,
+            message: error.message.clone(),
+            source_line: source
+                .lines()
+                .nth(error.line.saturating_sub(1))
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    /// Create the diagnostic for a syntax error found locally in the `preprocessed`
+    /// script at `path`, located in the file the line came from.
+    #[must_use]
+    pub fn preprocessed_syntax_error(
+        path: &Path,
+        preprocessed: &Preprocessed,
+        error: &SyntaxError,
+    ) -> Self {
+        let mut diagnostic = Self::syntax_error(path, &preprocessed.source, error);
+        if let Some((file, line)) = preprocessed.origin(error.line) {
+            diagnostic.file = file.to_path_buf();
+            diagnostic.line = line;
+        }
+        diagnostic
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.file.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{number} | {}", self.source_line)?;
+        write!(f, "{gutter} | {indent}^")
+    }
+}
+
+/// Read the errors of an instrument that is not running the REPL, e.g. after a script
+/// was loaded with `kic script`.
+///
+/// # Errors
+/// Returns an error if the instrument could not be written to or read from, or the
+/// errors could not be parsed.
+pub fn read_errors(inst: &mut Box<dyn Instrument>) -> Result<Vec<TspError>> {
+    inst.write_script(
+        b"_kic_common",
+        KIC_COMMON_TSP.to_string().as_bytes(),
+        false,
+        true,
+    )?;
+    inst.write_all(b"print(_KIC.error_message())\n")?;
+    inst.flush()?;
+    let output = read_until(
+        inst.as_mut(),
+        &["ERM>DONE".to_string()],
+        5000,
+        Duration::from_millis(1),
+    )?;
+    inst.write_all(b"_KIC.cleanup()\n")?;
+
+    let mut errors = Vec::new();
+    for response in ResponseParser::new(output.as_bytes()) {
+        if let ParsedResponse::TspError(e) = response {
+            errors.push(serde_json::from_str(e.trim())?);
+        }
+    }
+    Ok(errors)
+}
+
+/// Write `diagnostics` as a JSON array to `output`, or to stdout if `output` is `-`.
+///
+/// # Errors
+/// Returns an error if the diagnostics could not be written.
+pub fn write_json(diagnostics: &[Diagnostic], output: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(diagnostics)?;
+    if output == Path::new("-") {
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{json}")?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(output, json)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit {
+    use super::SourceMap;
+    use crate::TspError;
+
+    fn error(message: &str) -> TspError {
+        serde_json::from_value(serde_json::json!({
+            "error_code": -285,
+            "message": message,
+            "severity": 20,
+            "node_id": 1,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn map_locations() {
+        let map = SourceMap::new(
+            "kic_test",
+            "test.tsp",
+            "smua.reset()\nlocal x = = 1\n    foo()\n",
+        );
+
+        let d = map
+            .diagnostic(&error(
+                "[string \"kic_test\"]:2: unexpected symbol near '='",
+            ))
+            .unwrap();
+        assert_eq!((d.line, d.column), (2, 9));
+        assert_eq!(
+            d.to_string(),
+            " --> test.tsp:2:9\n  |\n2 | local x = = 1\n  |         ^"
+        );
+
+        let d = map
+            .diagnostic(&error(
+                "[string \"kic_test\"]:3: attempt to call global 'foo' (a nil value)",
+            ))
+            .unwrap();
+        assert_eq!((d.line, d.column), (3, 5));
+
+        assert!(map
+            .diagnostic(&error("kic_other:1: attempt to call a nil value"))
+            .is_none());
+        assert!(map
+            .diagnostic(&error("TSP Syntax error at line 2: unexpected symbol"))
+            .is_none());
+        assert!(map
+            .diagnostic(&error("kic_test:9: unexpected symbol"))
+            .is_none());
+        assert!(map.diagnostic(&error("Parameter too big")).is_none());
+    }
+}
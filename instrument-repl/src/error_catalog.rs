@@ -0,0 +1,88 @@
+//! A catalog of the errors that instruments report, with the likely cause of each error
+//! and how to fix it.
+
+use std::{fmt::Display, sync::LazyLock};
+
+use serde::Deserialize;
+
+use kic_lib::model::Family;
+
+const EMBEDDED_ERRORS: &str = include_str!("./resources/errors.json");
+
+static CATALOG: LazyLock<ErrorCatalog> = LazyLock::new(|| {
+    serde_json::from_str(EMBEDDED_ERRORS).expect("embedded error catalog should be valid")
+});
+
+/// Get the error catalog.
+#[must_use]
+pub fn catalog() -> &'static ErrorCatalog {
+    &CATALOG
+}
+
+/// An explanation of an error code.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ErrorEntry {
+    /// The error code
+    pub code: i64,
+    /// The short name of the error
+    pub title: String,
+    /// What the error means
+    pub description: String,
+    /// The likely cause of the error
+    pub cause: String,
+    /// How to fix the error
+    pub remedy: String,
+    /// The model families this entry applies to, all families if empty
+    #[serde(default)]
+    pub families: Vec<Family>,
+}
+
+impl Display for ErrorEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.code, self.title)?;
+        writeln!(f, "  {}", self.description)?;
+        writeln!(f, "  Likely cause: {}", self.cause)?;
+        write!(f, "  Remedy: {}", self.remedy)
+    }
+}
+
+/// A collection of [`ErrorEntry`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ErrorCatalog {
+    errors: Vec<ErrorEntry>,
+}
+
+impl ErrorCatalog {
+    /// Look up an error code. An entry specific to `family` is preferred over one that
+    /// applies to all families.
+    #[must_use]
+    pub fn get(&self, code: i64, family: Option<&Family>) -> Option<&ErrorEntry> {
+        let mut entries = self.errors.iter().filter(|e| e.code == code);
+        let specific = entries
+            .clone()
+            .find(|e| family.is_some_and(|f| e.families.contains(f)));
+        specific.or_else(|| entries.find(|e| e.families.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::catalog;
+    use kic_lib::model::Family;
+
+    #[test]
+    fn lookup() {
+        let c = catalog();
+        assert_eq!(
+            c.get(-285, None).map(|e| e.title.as_str()),
+            Some("TSP syntax error")
+        );
+        assert_eq!(
+            c.get(1102, Some(&Family::_26xx)).map(|e| e.title.as_str()),
+            Some("Parameter too big")
+        );
+        assert!(c.get(1102, Some(&Family::Tti)).is_none());
+        assert!(c.get(1102, None).is_none());
+        assert!(c.get(123_456, None).is_none());
+    }
+}
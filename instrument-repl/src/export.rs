@@ -0,0 +1,77 @@
+//! Turn the TSP commands of a REPL session into a script that can be run again.
+
+use std::fmt::Write as _;
+
+use kic_lib::tsp::is_name;
+
+/// Check that `name` can be used as the name of a TSP function.
+///
+/// # Errors
+/// Returns a description of the problem if `name` is not a Lua name.
+pub fn validate_function_name(name: &str) -> Result<(), String> {
+    if is_name(name) {
+        Ok(())
+    } else {
+        Err(format!("\"{name}\" is not a valid TSP function name"))
+    }
+}
+
+/// A script of `commands` with a header that has the `instrument` they were run on and
+/// the `time` they were exported.
+///
+/// If a `function` is given, the commands are wrapped in a function of that name, which
+/// is called at the end of the script.
+#[must_use]
+pub fn script(commands: &[String], instrument: &str, time: &str, function: Option<&str>) -> String {
+    let mut script = format!(
+        "--[[\n    Exported from a TSP Toolkit REPL session on {time}\n    Instrument: {instrument}\n]]\n\n"
+    );
+    match function {
+        Some(name) => {
+            let _ = writeln!(script, "function {name}()");
+            for command in commands {
+                let _ = writeln!(script, "    {command}");
+            }
+            let _ = writeln!(script, "end\n\n{name}()");
+        }
+        None => {
+            for command in commands {
+                let _ = writeln!(script, "{command}");
+            }
+        }
+    }
+    script
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{script, validate_function_name};
+    use kic_lib::tsp::check_syntax;
+
+    #[test]
+    fn export() {
+        let commands = vec![
+            "smu.source.level = 1".to_string(),
+            "print(smu.measure.read())".to_string(),
+        ];
+        let plain = script(
+            &commands,
+            "Keithley,MODEL 2450,1,1.7",
+            "2024-05-01 10:00",
+            None,
+        );
+        assert_eq!(check_syntax(&plain), Ok(()));
+        assert!(plain.contains("Instrument: Keithley,MODEL 2450,1,1.7\n"));
+        assert!(plain.ends_with("\n\nsmu.source.level = 1\nprint(smu.measure.read())\n"));
+
+        let wrapped = script(&commands, "", "", Some("bench"));
+        assert_eq!(check_syntax(&wrapped), Ok(()));
+        assert!(wrapped.ends_with(
+            "function bench()\n    smu.source.level = 1\n    print(smu.measure.read())\nend\n\nbench()\n"
+        ));
+
+        assert!(validate_function_name("run_1").is_ok());
+        assert!(validate_function_name("1run").is_err());
+        assert!(validate_function_name("a.b").is_err());
+    }
+}
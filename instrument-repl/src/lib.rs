@@ -11,12 +11,22 @@ use std::env;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub mod command;
+pub mod command_reference;
+pub mod diagnostics;
 pub mod error;
+pub mod error_catalog;
+pub mod export;
 pub mod instrument;
+pub mod macros;
+pub mod plot;
 pub mod repl;
 mod resources;
 mod state_machine;
+pub mod transcript;
 pub mod tsp_error;
+pub mod tui;
+pub mod watch;
 
 pub use error::InstrumentReplError;
+pub use resources::TSP_LINK_NODES_TSP;
 pub use tsp_error::{InstrumentTime, TspError};
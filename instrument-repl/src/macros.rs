@@ -0,0 +1,276 @@
+//! Commands that users define for the REPL. A macro expands to a line of TSP or to
+//! another REPL command, e.g. `.zero` for `smu.source.level=0 smu.source.output=smu.OFF`.
+//!
+//! Macros are loaded from the JSON file given by [`MACROS_FILE_ENV`], an object of
+//! names and bodies, and can be defined at runtime with `.macro def`.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use kic_lib::tsp::is_name;
+use tracing::{debug, warn};
+
+use crate::error::{InstrumentReplError, Result};
+
+/// The environment variable with the path of a JSON file of macros to load on start.
+pub const MACROS_FILE_ENV: &str = "TSP_TOOLKIT_MACROS_FILE";
+
+/// The most macros that are expanded for a single line, so that a macro that invokes
+/// itself does not expand forever.
+const MAX_DEPTH: usize = 16;
+
+/// The macros that are defined. Clones share the same definitions, so a macro that is
+/// defined by the REPL can be expanded on the thread that reads the user input.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    defined: Arc<Mutex<BTreeMap<String, String>>>,
+    /// The names of the built-in commands, without the `.`, which can't be redefined
+    builtins: Arc<Vec<String>>,
+}
+
+impl Macros {
+    /// The macros in the file given by [`MACROS_FILE_ENV`], or none if it is not set.
+    /// A file that can't be read, or that redefines one of the `builtins` commands, is
+    /// reported and ignored.
+    #[must_use]
+    pub fn load(builtins: impl IntoIterator<Item = String>) -> Self {
+        let macros = Self {
+            builtins: Arc::new(
+                builtins
+                    .into_iter()
+                    .map(|b| b.trim_start_matches('.').to_string())
+                    .collect(),
+            ),
+            ..Self::default()
+        };
+        if let Some(path) = std::env::var_os(MACROS_FILE_ENV) {
+            match macros.read_file(Path::new(&path)) {
+                Ok(loaded) => {
+                    debug!("Loaded macros from {}", Path::new(&path).display());
+                    *macros.defined() = loaded;
+                }
+                Err(e) => warn!(
+                    "Unable to read macros from {}: {e}",
+                    Path::new(&path).display()
+                ),
+            }
+        }
+        macros
+    }
+
+    fn read_file(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let json = std::fs::read_to_string(path)?;
+        let loaded: BTreeMap<String, String> = serde_json::from_str(&json)
+            .map_err(|e| InstrumentReplError::Other(format!("invalid macros file: {e}")))?;
+        loaded
+            .into_iter()
+            .map(|(name, body)| Ok((self.check_name(&name)?.to_string(), body)))
+            .collect()
+    }
+
+    /// Check that `name`, with or without a leading `.`, is a valid macro name that is
+    /// not the name of a built-in command, and return it without the `.`.
+    fn check_name<'a>(&self, name: &'a str) -> Result<&'a str> {
+        let name = validate_name(name)?;
+        if self.builtins.iter().any(|b| b == name) {
+            return Err(InstrumentReplError::Other(format!(
+                "`.{name}` is a built-in command and can't be redefined"
+            )));
+        }
+        Ok(name)
+    }
+
+    fn defined(&self) -> MutexGuard<'_, BTreeMap<String, String>> {
+        self.defined.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Define the macro `name`, replacing any macro with the same name.
+    ///
+    /// # Errors
+    /// Returns an error if `name` is not a valid macro name or is the name of a
+    /// built-in command.
+    pub fn define(&self, name: &str, body: String) -> Result<()> {
+        let name = self.check_name(name)?.to_string();
+        self.defined().insert(name, body);
+        Ok(())
+    }
+
+    /// Remove the macro `name`, returning whether it was defined.
+    #[must_use]
+    pub fn remove(&self, name: &str) -> bool {
+        self.defined()
+            .remove(name.strip_prefix('.').unwrap_or(name))
+            .is_some()
+    }
+
+    /// The names and bodies of the macros, sorted by name.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.defined()
+            .iter()
+            .map(|(n, b)| (n.clone(), b.clone()))
+            .collect()
+    }
+
+    /// The body of the macro `name`, if it is defined.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.defined()
+            .get(name.strip_prefix('.').unwrap_or(name))
+            .cloned()
+    }
+
+    /// Expand `input` if it invokes a macro, repeatedly if the expansion invokes
+    /// another macro. Returns `None` if `input` does not invoke a macro.
+    ///
+    /// # Errors
+    /// Returns an error if a macro is given fewer arguments than it uses, or the
+    /// expansion does not end.
+    pub fn expand(&self, input: &str) -> Result<Option<String>> {
+        let mut line = input.trim().to_string();
+        let mut expanded = false;
+        for _ in 0..MAX_DEPTH {
+            let Some(rest) = line.strip_prefix('.') else {
+                break;
+            };
+            let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let Some(body) = self.get(name) else {
+                break;
+            };
+            let Some(args) = shlex::split(args) else {
+                return Err(InstrumentReplError::Other(format!(
+                    "invalid arguments for `.{name}`"
+                )));
+            };
+            line = substitute(name, &body, &args)?;
+            expanded = true;
+        }
+        if !expanded {
+            return Ok(None);
+        }
+        if line.starts_with('.')
+            && line
+                .split_whitespace()
+                .next()
+                .is_some_and(|n| self.get(n).is_some())
+        {
+            return Err(InstrumentReplError::Other(format!(
+                "macros were expanded more than {MAX_DEPTH} times, does a macro invoke itself?"
+            )));
+        }
+        Ok(Some(line))
+    }
+}
+
+/// Check that `name`, with or without a leading `.`, is a Lua name, and return it
+/// without the `.`.
+fn validate_name(name: &str) -> Result<&str> {
+    let name = name.strip_prefix('.').unwrap_or(name);
+    if is_name(name) {
+        Ok(name)
+    } else {
+        Err(InstrumentReplError::Other(format!(
+            "\"{name}\" is not a valid macro name, use letters, digits and `_`, not starting with a digit"
+        )))
+    }
+}
+
+/// Replace the parameters in the body of the macro `name`: `$1` to `$9` with the
+/// arguments, `$*` with all of them and `$$` with `$`.
+fn substitute(name: &str, body: &str, args: &[String]) -> Result<String> {
+    let mut expanded = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('$') => expanded.push('$'),
+            Some('*') => expanded.push_str(&args.join(" ")),
+            Some(d @ '1'..='9') => {
+                let index = d.to_digit(10).map_or(0, |d| d as usize);
+                let Some(arg) = args.get(index.saturating_sub(1)) else {
+                    return Err(InstrumentReplError::Other(format!(
+                        "`.{name}` uses ${index} but was given {} argument(s)",
+                        args.len()
+                    )));
+                };
+                expanded.push_str(arg);
+            }
+            _ => {
+                expanded.push('$');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod unit {
+    use super::Macros;
+
+    #[test]
+    fn expansion() {
+        let macros = Macros::default();
+        macros
+            .define(
+                "zero",
+                "smu.source.level=0 smu.source.output=smu.OFF".to_string(),
+            )
+            .unwrap();
+        macros
+            .define(".level", "smu.source.level=$1 print(\"$$1\")".to_string())
+            .unwrap();
+        macros.define("e", ".errors $*".to_string()).unwrap();
+        macros.define("loop", ".loop".to_string()).unwrap();
+        assert!(macros.define("not valid", String::new()).is_err());
+
+        assert_eq!(
+            macros.expand(".zero").unwrap().as_deref(),
+            Some("smu.source.level=0 smu.source.output=smu.OFF")
+        );
+        assert_eq!(
+            macros.expand(".level 5\n").unwrap().as_deref(),
+            Some("smu.source.level=5 print(\"$1\")")
+        );
+        assert!(macros.expand(".level").is_err());
+        assert_eq!(
+            macros.expand(".e filter \"warning\"").unwrap().as_deref(),
+            Some(".errors filter warning")
+        );
+        assert!(macros.expand(".loop").is_err());
+        assert_eq!(macros.expand(".script x.tsp").unwrap(), None);
+        assert_eq!(macros.expand("print(1)").unwrap(), None);
+
+        assert!(macros.remove(".zero"));
+        assert_eq!(macros.expand(".zero").unwrap(), None);
+        assert_eq!(macros.list().len(), 3);
+    }
+
+    #[test]
+    fn builtins() {
+        let macros = Macros::load([".script".to_string(), ".exit".to_string()]);
+        assert!(macros.define(".script", "print(1)".to_string()).is_err());
+        assert!(macros.define("exit", "print(1)".to_string()).is_err());
+        assert!(macros.define("scripts2", "print(1)".to_string()).is_ok());
+
+        let file = std::env::temp_dir().join(format!("kic-macros-{}.json", std::process::id()));
+        std::fs::write(
+            &file,
+            r#"{"zero": "smu.source.level=0", "exit": "print(1)"}"#,
+        )
+        .unwrap();
+        let loaded = macros.read_file(&file);
+        std::fs::write(&file, r#"{"zero": "smu.source.level=0"}"#).unwrap();
+        let valid = macros.read_file(&file);
+        let _ = std::fs::remove_file(&file);
+        assert!(loaded.is_err());
+        assert_eq!(valid.unwrap().len(), 1);
+    }
+}
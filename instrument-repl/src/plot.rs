@@ -0,0 +1,512 @@
+//! Render readings as line charts or sparklines in the terminal.
+
+use std::fmt::Write;
+
+use crate::error::{InstrumentReplError, Result};
+
+/// The characters of a sparkline, from the lowest to the highest value.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The width of a line chart in characters, not counting the axis labels.
+pub const WIDTH: usize = 60;
+
+/// The height of a line chart in characters, not counting the title and x axis.
+pub const HEIGHT: usize = 12;
+
+/// The line that ends the output of a plot query.
+pub const PLOT_END: &str = "PLOT>END";
+
+/// The most values that a plot query prints. Longer buffers and tables are downsampled
+/// on the instrument, which keeps the query quick to run and read.
+pub const MAX_POINTS: usize = 500;
+
+/// Prints whether `$TARGET` is a buffer, followed by its readings as CSV, a table,
+/// followed by its numbered values, or a single value. Buffers are recognized the same
+/// way as by `.vars`. Every `step`th row is printed, one row at a time.
+const PLOT_QUERY: &str = r#"do
+local ok, v = pcall(function() return $TARGET end)
+local function step(n) return math.max(1, math.ceil(n / $MAX_POINTS)) end
+if not ok then print("PLOT>!" .. tostring(v))
+else
+local isBuffer = false
+pcall(function() isBuffer = type(v.n) == "number" and type(v.capacity) == "number" end)
+if isBuffer then
+print("PLOT>BUFFER")
+local columns, header = {}, "n"
+for _, f in ipairs({"readings", "sourcevalues", "units", "measurefunctions"}) do
+local found, column = pcall(function() return v[f] end)
+if found and column ~= nil then table.insert(columns, column) header = header .. "," .. f end
+end
+print(header)
+for r = 1, v.n, step(v.n) do
+local row = tostring(r)
+for _, column in ipairs(columns) do row = row .. "," .. tostring(column[r]) end
+print(row)
+end
+elseif type(v) == "table" then
+print("PLOT>TABLE")
+local n = table.getn(v)
+for i = 1, n, step(n) do print(i .. "," .. tostring(v[i])) end
+else print("PLOT>=" .. tostring(v)) end
+end
+print("PLOT>END")
+end"#;
+
+/// Prints the value of `$TARGET`.
+const SAMPLE_QUERY: &str = r#"do
+local ok, v = pcall(function() return $TARGET end)
+if ok then print("PLOT>=" .. tostring(v)) else print("PLOT>!" .. tostring(v)) end
+print("PLOT>END")
+end"#;
+
+/// The data of a plot target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlotData {
+    /// The target is a reading buffer
+    Buffer(BufferData),
+    /// The target is a table, with these values and their indices
+    Values(Vec<(f64, f64)>),
+    /// The target is a single value, as printed by the instrument
+    Value(String),
+}
+
+/// The TSP that prints the data of `target`, which is a buffer, a table or an
+/// expression. The output ends with [`PLOT_END`].
+#[must_use]
+pub fn plot_query(target: &str) -> String {
+    PLOT_QUERY
+        .replace("$TARGET", target)
+        .replace("$MAX_POINTS", &MAX_POINTS.to_string())
+        .replace('\n', " ")
+}
+
+/// The TSP that prints the current value of the expression `target`. The output ends
+/// with [`PLOT_END`].
+#[must_use]
+pub fn sample_query(target: &str) -> String {
+    SAMPLE_QUERY.replace("$TARGET", target).replace('\n', " ")
+}
+
+/// The error that `target` could not be evaluated, if `line` reports it.
+fn target_error(target: &str, line: &str) -> Option<InstrumentReplError> {
+    line.strip_prefix("PLOT>!")
+        .map(|e| InstrumentReplError::Other(format!("unable to evaluate \"{target}\": {e}")))
+}
+
+/// Parse the output of [`plot_query`] for `target`.
+///
+/// # Errors
+/// Returns an error if `target` could not be evaluated on the instrument.
+pub fn parse_plot(target: &str, lines: &[String]) -> Result<PlotData> {
+    let mut lines = lines.iter().map(|l| l.trim()).filter(|l| *l != PLOT_END);
+    let first = lines.next().unwrap_or_default();
+    if let Some(e) = target_error(target, first) {
+        return Err(e);
+    }
+    let rest: Vec<String> = lines.map(ToString::to_string).collect();
+    match first {
+        "PLOT>BUFFER" => Ok(PlotData::Buffer(parse_buffer_csv(&rest))),
+        "PLOT>TABLE" => Ok(PlotData::Values(
+            rest.iter()
+                .filter_map(|l| l.split_once(','))
+                .map(|(i, v)| {
+                    (
+                        i.trim().parse().unwrap_or(f64::NAN),
+                        v.trim().parse().unwrap_or(f64::NAN),
+                    )
+                })
+                .collect(),
+        )),
+        _ => Ok(PlotData::Value(
+            first.strip_prefix("PLOT>=").unwrap_or(first).to_string(),
+        )),
+    }
+}
+
+/// Parse the output of [`sample_query`] for `target`.
+///
+/// # Errors
+/// Returns an error if `target` could not be evaluated or is not a number.
+pub fn parse_sample(target: &str, lines: &[String]) -> Result<f64> {
+    let line = lines
+        .iter()
+        .map(|l| l.trim())
+        .find(|l| l.starts_with("PLOT>") && *l != PLOT_END)
+        .unwrap_or_default();
+    if let Some(e) = target_error(target, line) {
+        return Err(e);
+    }
+    let value = line.strip_prefix("PLOT>=").unwrap_or(line);
+    value
+        .parse()
+        .map_err(|_| InstrumentReplError::Other(format!("\"{target}\" is not a number: {value}")))
+}
+
+/// Render `points` as a line chart, or the y values as a sparkline followed by the
+/// last value if `spark`.
+#[must_use]
+pub fn render(points: &[(f64, f64)], axes: &Axes, spark: bool) -> String {
+    if !spark {
+        return line_chart(points, axes, WIDTH, HEIGHT);
+    }
+    let values: Vec<_> = points.iter().map(|p| p.1).collect();
+    let last = values
+        .iter()
+        .rev()
+        .find(|v| v.is_finite())
+        .map_or_else(String::new, |v| format_si(*v, &axes.y_unit));
+    format!("{} {} {last}", axes.y_label, sparkline(&values))
+}
+
+/// The names and units of the axes of a chart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Axes {
+    /// What is plotted on the x axis, e.g. `n` or `source`
+    pub x_label: String,
+    /// The unit of the x axis, e.g. `V`, or empty
+    pub x_unit: String,
+    /// What is plotted on the y axis, e.g. the name of a buffer
+    pub y_label: String,
+    /// The unit of the y axis, e.g. `A`, or empty
+    pub y_unit: String,
+}
+
+/// Format `value` with an SI prefix and `unit`, e.g. `1.5e-3` and `A` as `1.500 mA`.
+#[must_use]
+pub fn format_si(value: f64, unit: &str) -> String {
+    const PREFIXES: [(f64, &str); 9] = [
+        (1e9, "G"),
+        (1e6, "M"),
+        (1e3, "k"),
+        (1.0, ""),
+        (1e-3, "m"),
+        (1e-6, "µ"),
+        (1e-9, "n"),
+        (1e-12, "p"),
+        (1e-15, "f"),
+    ];
+    if value == 0.0 || !value.is_finite() {
+        return format!("{value:.3} {unit}").trim_end().to_string();
+    }
+    let (scale, prefix) = PREFIXES
+        .iter()
+        .find(|(scale, _)| value.abs() >= *scale)
+        .unwrap_or(&PREFIXES[PREFIXES.len().saturating_sub(1)]);
+    format!("{:.3} {prefix}{unit}", value / scale)
+        .trim_end()
+        .to_string()
+}
+
+/// The symbol of the unit a buffer reports, e.g. `Current` or `Amp DC` as `A`.
+#[must_use]
+pub fn unit_symbol(unit: &str) -> &'static str {
+    let unit = unit.to_lowercase();
+    [
+        ("amp", "A"),
+        ("current", "A"),
+        ("volt", "V"),
+        ("ohm", "Ω"),
+        ("resist", "Ω"),
+        ("watt", "W"),
+        ("power", "W"),
+    ]
+    .iter()
+    .find(|(name, _)| unit.contains(name))
+    .map_or("", |(_, symbol)| symbol)
+}
+
+/// The readings of a buffer, printed by [`plot_query`] or `_KIC.print_buffers_csv`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BufferData {
+    /// The indices of the readings in the buffer, which skip readings if the buffer was
+    /// downsampled
+    pub indices: Vec<f64>,
+    /// The readings
+    pub readings: Vec<f64>,
+    /// The source values of the readings, if the buffer has them
+    pub source_values: Option<Vec<f64>>,
+    /// The symbol of the unit of the readings, or empty if it is not known
+    pub unit: String,
+}
+
+/// Parse the output of [`plot_query`] or `_KIC.print_buffers_csv` for a single buffer.
+///
+/// The fields should be `readings`, `sourcevalues` and `units` or `measurefunctions`,
+/// delimited by `,`. Fields that the buffer does not have are missing from the output.
+#[must_use]
+pub fn parse_buffer_csv(lines: &[String]) -> BufferData {
+    let mut lines = lines
+        .iter()
+        .map(|l| l.trim())
+        .skip_while(|l| !l.starts_with("n,") && *l != "n");
+    let Some(header) = lines.next() else {
+        return BufferData::default();
+    };
+    let header: Vec<_> = header.split(',').collect();
+    let column = |name: &str| header.iter().position(|h| *h == name);
+    let (readings, sources, units) = (
+        column("readings"),
+        column("sourcevalues"),
+        column("units").or_else(|| column("measurefunctions")),
+    );
+
+    let mut data = BufferData {
+        source_values: sources.map(|_| Vec::new()),
+        ..BufferData::default()
+    };
+    for row in lines.take_while(|l| !l.is_empty()) {
+        let fields: Vec<_> = row.split(',').collect();
+        let value = |index: Option<usize>| {
+            index
+                .and_then(|i| fields.get(i))
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .unwrap_or(f64::NAN)
+        };
+        data.indices.push(value(Some(0)));
+        data.readings.push(value(readings));
+        if let Some(source_values) = data.source_values.as_mut() {
+            source_values.push(value(sources));
+        }
+        if data.unit.is_empty() {
+            if let Some(unit) = units.and_then(|i| fields.get(i)) {
+                data.unit = unit_symbol(unit).to_string();
+            }
+        }
+    }
+    // Source values are not numbers if the buffer did not collect them.
+    if data
+        .source_values
+        .as_ref()
+        .is_some_and(|s| !s.iter().any(|v| v.is_finite()))
+    {
+        data.source_values = None;
+    }
+    data
+}
+
+/// The range of `values`, widened if all values are equal so that it can be divided.
+fn bounds(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    let (min, max) =
+        values
+            .filter(|v| v.is_finite())
+            .fold(None, |acc: Option<(f64, f64)>, v| {
+                Some(acc.map_or((v, v), |(min, max)| (min.min(v), max.max(v))))
+            })?;
+    if (max - min).abs() < f64::EPSILON {
+        let pad = if min == 0.0 { 1.0 } else { min.abs() * 0.1 };
+        Some((min - pad, max + pad))
+    } else {
+        Some((min, max))
+    }
+}
+
+/// Map `value` in `min..=max` to a cell in `0..cells`.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn scale(value: f64, (min, max): (f64, f64), cells: usize) -> usize {
+    let last = cells.saturating_sub(1);
+    let cell = ((value - min) / (max - min) * last as f64).round();
+    (cell.max(0.0) as usize).min(last)
+}
+
+/// Render `values` as a sparkline, one character per value.
+#[must_use]
+pub fn sparkline(values: &[f64]) -> String {
+    let Some(range) = bounds(values.iter().copied()) else {
+        return String::new();
+    };
+    values
+        .iter()
+        .map(|v| {
+            if v.is_finite() {
+                SPARKS[scale(*v, range, SPARKS.len())]
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
+
+/// Render `points` as a line chart of `width` by `height` characters, not counting the
+/// axis labels. Points are sorted by their x value and connected by lines.
+#[must_use]
+pub fn line_chart(points: &[(f64, f64)], axes: &Axes, width: usize, height: usize) -> String {
+    let mut points: Vec<_> = points
+        .iter()
+        .copied()
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .collect();
+    let (Some(x_range), Some(y_range)) = (
+        bounds(points.iter().map(|p| p.0)),
+        bounds(points.iter().map(|p| p.1)),
+    ) else {
+        return format!("{}: no numeric readings to plot", axes.y_label);
+    };
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (width, height) = (width.max(2), height.max(2));
+
+    // Rows are numbered from the bottom of the chart.
+    let mut grid = vec![vec![' '; width]; height];
+    let cells: Vec<_> = points
+        .iter()
+        .map(|(x, y)| (scale(*x, x_range, width), scale(*y, y_range, height)))
+        .collect();
+    for pair in cells.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        // Join the points with a vertical line in the column where the value changes.
+        let (low, high) = (y0.min(y1), y0.max(y1));
+        for row in grid.iter_mut().take(high).skip(low.saturating_add(1)) {
+            row[x1] = '│';
+        }
+        for cell in grid[y0].iter_mut().take(x1).skip(x0.saturating_add(1)) {
+            if *cell == ' ' {
+                *cell = '─';
+            }
+        }
+    }
+    for (x, y) in &cells {
+        grid[*y][*x] = '•';
+    }
+
+    let labels = [
+        format_si(y_range.1, &axes.y_unit),
+        format_si(f64::midpoint(y_range.0, y_range.1), &axes.y_unit),
+        format_si(y_range.0, &axes.y_unit),
+    ];
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let middle = height.saturating_sub(1) / 2;
+
+    let mut chart = String::new();
+    let y_title = if axes.y_unit.is_empty() {
+        axes.y_label.clone()
+    } else {
+        format!("{} ({})", axes.y_label, axes.y_unit)
+    };
+    let _ = writeln!(chart, "{y_title}");
+    for (i, row) in grid.iter().rev().enumerate() {
+        let label = match i {
+            0 => labels[0].as_str(),
+            i if i == middle => labels[1].as_str(),
+            i if i == height.saturating_sub(1) => labels[2].as_str(),
+            _ => "",
+        };
+        let _ = writeln!(
+            chart,
+            "{label:>label_width$} ┤{}",
+            row.iter().collect::<String>().trim_end()
+        );
+    }
+    let _ = writeln!(chart, "{:>label_width$} └{}", "", "─".repeat(width));
+    let (x_min, x_max) = (
+        format_si(x_range.0, &axes.x_unit),
+        format_si(x_range.1, &axes.x_unit),
+    );
+    let gap = width
+        .saturating_sub(x_min.chars().count())
+        .saturating_sub(x_max.chars().count())
+        .max(1);
+    let _ = writeln!(
+        chart,
+        "{:>label_width$}  {x_min}{}{x_max}",
+        "",
+        " ".repeat(gap)
+    );
+    let _ = write!(
+        chart,
+        "{:>label_width$}  {}",
+        "",
+        format!("{:^width$}", axes.x_label).trim_end()
+    );
+    chart
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{
+        format_si, line_chart, parse_buffer_csv, parse_plot, parse_sample, plot_query,
+        sample_query, sparkline, unit_symbol, Axes, PlotData,
+    };
+    use kic_lib::tsp::check_syntax;
+
+    #[test]
+    fn rendering() {
+        assert_eq!(format_si(1.5e-3, "A"), "1.500 mA");
+        assert_eq!(format_si(-2.0e-7, "A"), "-200.000 nA");
+        assert_eq!(format_si(12.0, "V"), "12.000 V");
+        assert_eq!(format_si(0.0, ""), "0.000");
+
+        assert_eq!(
+            sparkline(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]),
+            "▁▂▃▄▅▆▇█"
+        );
+        assert_eq!(sparkline(&[1.0, 1.0]), "▄▄");
+        assert_eq!(sparkline(&[]), "");
+
+        let axes = Axes {
+            x_label: "source".to_string(),
+            x_unit: "V".to_string(),
+            y_label: "defbuffer1".to_string(),
+            y_unit: "A".to_string(),
+        };
+        let chart = line_chart(&[(0.0, 0.0), (1.0, 1e-3), (2.0, 2e-3)], &axes, 5, 3);
+        assert_eq!(
+            chart,
+            "\
+defbuffer1 (A)
+2.000 mA ┤    •
+1.000 mA ┤  •─
+ 0.000 A ┤•─
+         └─────
+          0.000 V 2.000 V
+          source"
+        );
+        assert!(line_chart(&[(0.0, f64::NAN)], &axes, 5, 3).contains("no numeric readings"));
+
+        assert_eq!(unit_symbol("Amp DC"), "A");
+        assert_eq!(unit_symbol("Voltage"), "V");
+        assert_eq!(unit_symbol("Seconds"), "");
+
+        let lines: Vec<String> = [
+            "Buffer 'plot'",
+            "n,readings,sourcevalues,units",
+            "1,1.0e-03,1,Amp DC",
+            "2,2.0e-03,2,Amp DC",
+            "",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let data = parse_buffer_csv(&lines);
+        assert_eq!(data.indices, vec![1.0, 2.0]);
+        assert_eq!(data.readings, vec![1.0e-3, 2.0e-3]);
+        assert_eq!(data.source_values, Some(vec![1.0, 2.0]));
+        assert_eq!(data.unit, "A");
+
+        let data =
+            parse_buffer_csv(&["n,readings,sourcevalues".to_string(), "1,5,nil".to_string()]);
+        assert_eq!((data.readings, data.source_values), (vec![5.0], None));
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(check_syntax(&plot_query("defbuffer1")), Ok(()));
+        assert_eq!(check_syntax(&sample_query("smu.measure.read()")), Ok(()));
+
+        let lines = |l: &[&str]| l.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            parse_plot("t", &lines(&["PLOT>TABLE", "1,1", "3,2.5", "PLOT>END"])).unwrap(),
+            PlotData::Values(vec![(1.0, 1.0), (3.0, 2.5)])
+        );
+        assert_eq!(
+            parse_plot("x", &lines(&["PLOT>=5", "PLOT>END"])).unwrap(),
+            PlotData::Value("5".to_string())
+        );
+        assert!(parse_plot("y", &lines(&["PLOT>!attempt to index a nil value"])).is_err());
+        let sample = parse_sample("x", &lines(&["PLOT>=1e-3", "PLOT>END"])).unwrap();
+        assert!((sample - 1e-3).abs() < f64::EPSILON);
+        assert!(parse_sample("x", &lines(&["PLOT>=nil", "PLOT>END"])).is_err());
+    }
+}
@@ -6,30 +6,53 @@
 //!    this is done by checking the `read_into` mpsc channel receiver and then checking
 //!    the instrument communication line for data coming back from the instrument.
 
-use chrono::Utc;
+use chrono::{Local, Utc};
 use clap::{arg, value_parser, Arg, ArgAction, Command};
 use colored::Colorize;
 use regex::Regex;
 use std::{
-    fmt::Display,
+    collections::{BTreeMap, VecDeque},
+    fmt::{Display, Write as _},
     fs::{self, File},
     io::{self, ErrorKind, Read, Write},
     path::{Path, PathBuf},
     process::exit,
-    sync::mpsc::{channel, Sender, TryRecvError},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Once,
+    },
     thread::JoinHandle,
     time::{Duration, Instant},
 };
 use tracing::{debug, error, info, instrument, trace, warn};
 
-use kic_lib::{instrument::Instrument, InstrumentError};
+use kic_lib::{
+    instrument::{read_until, tsplink::query_status, variables::list_variables, Instrument},
+    model::{Family, Model},
+    tsp::{check_syntax, lint::is_instrument_global, parse_define, preprocess, Preprocessed},
+    InstrumentError,
+};
 
 use crate::{
-    command::{Request, Save, SaveMethod},
+    command::{
+        DocAction, ErrorsAction, MacroAction, Request, Save, SaveMethod, ScriptsAction, Target,
+        UserInput, WatchAction,
+    },
+    command_reference::reference,
+    diagnostics::{Diagnostic, SourceMap},
     error::{InstrumentReplError, Result},
+    error_catalog::catalog,
+    export,
     instrument::{ParsedResponse, ResponseParser},
+    macros::Macros,
+    plot,
     resources::{KIC_COMMON_TSP, TSP_LINK_NODES_TSP},
     state_machine::ReadState,
+    transcript::{Difference, Entry, Step, Transcript, REPLAY_END},
+    tsp_error::Severity,
+    tui::{Input, Status, Tui},
+    watch::{self, parse_interval, Watch},
     TspError,
 };
 
@@ -37,6 +60,54 @@ pub struct Repl {
     inst: Box<dyn Instrument>,
     command: Command,
     lang_cong_file_path: String,
+    target: Option<Target>,
+    family: Option<Family>,
+    min_severity: Severity,
+    /// The script uploaded by the current request, in which errors are located
+    source_map: Option<SourceMap>,
+    tui: Option<Tui>,
+    watches: Vec<Watch>,
+    /// The number of watch lines printed in the full-screen interface since any other
+    /// output
+    watch_block: usize,
+    /// Whether a watch changed since the watches were printed above the prompt, without
+    /// the full-screen interface
+    watches_changed: bool,
+    macros: Macros,
+    /// The TSP commands of the session that ran without errors, for `.export`
+    executed: Vec<String>,
+    /// The number of TSP commands that ran without errors but can't be exported, since
+    /// they were routed to a slot
+    unexported: usize,
+    /// The TSP command that is running and the target it was routed to, until the
+    /// instrument prompts for the next one
+    pending: Option<(String, Option<Target>)>,
+    /// When the TSP-Link nodes in the status bar of the full-screen interface were last
+    /// read
+    tsplink_checked: Instant,
+}
+
+/// Set when Ctrl+C is pressed, until the REPL has aborted the running command.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// A second Ctrl+C within this time of the first exits the REPL.
+const EXIT_WINDOW: Duration = Duration::from_secs(2);
+
+/// A query is given up if the instrument prints nothing for this long.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the TSP-Link nodes in the status bar of the full-screen interface are read
+/// again while waiting for a command.
+const TSPLINK_REFRESH: Duration = Duration::from_secs(10);
+
+/// Catch Ctrl+C so that it aborts the running command instead of ending the process.
+fn install_interrupt_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        if let Err(e) = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst)) {
+            warn!("Unable to handle Ctrl+C, it will end the session: {e}");
+        }
+    });
 }
 
 fn accumulate_and_search(accumulator: &mut String, buf: &[u8], needle: &str) -> bool {
@@ -101,9 +172,38 @@ impl Repl {
             inst,
             command: Self::cli(),
             lang_cong_file_path: String::new(),
+            target: None,
+            family: None,
+            min_severity: Severity::Info,
+            source_map: None,
+            tui: None,
+            watches: Vec::new(),
+            watch_block: 0,
+            watches_changed: false,
+            macros: Macros::load(
+                Self::cli()
+                    .get_subcommands()
+                    .map(|c| c.get_name().to_string()),
+            ),
+            executed: Vec::new(),
+            unexported: 0,
+            pending: None,
+            tsplink_checked: Instant::now(),
         }
     }
 
+    /// Use the full-screen interface, showing `status` in its status bar, when the REPL
+    /// is started.
+    pub fn enable_tui(&mut self, status: Status) {
+        self.tui = Some(Tui::new(status));
+    }
+
+    /// Set the model of the connected instrument, which selects the model-specific
+    /// explanations of errors.
+    pub const fn set_model(&mut self, model: &Model) {
+        self.family = model.family();
+    }
+
     fn clear_output_queue(
         &mut self,
         max_attempts: usize,
@@ -130,6 +230,7 @@ impl Repl {
             debug!("Handling data");
             let parser = ResponseParser::new(data);
             let mut get_error = false;
+            let mut errors = Vec::new();
             for response in parser {
                 *prev_state = *state;
                 *state = Some(prev_state.unwrap_or_default().next_state(&response)?);
@@ -149,11 +250,13 @@ impl Repl {
                     }
                     Action::PrintError => {
                         trace!("Print error");
-                        Self::print_data(*state, response, save)?;
+                        if let ParsedResponse::TspError(e) = &response {
+                            errors.push(serde_json::from_str::<TspError>(e.trim())?);
+                        }
                     }
                     Action::GetNodeDetails => {
                         trace!("Update node configuration file");
-                        Self::update_node_config_json(&self.lang_cong_file_path, &response);
+                        self.update_node_config_json(&self.lang_cong_file_path, &response)?;
                     }
 
                     Action::None => {
@@ -162,14 +265,17 @@ impl Repl {
                 }
             }
             if get_error {
-                let (errors, new_prompt) = self.get_errors()?;
-                for e in errors {
-                    error!("TSP error: {e}");
-                    Self::print_data(*state, ParsedResponse::TspError(e.to_string()), save)?;
-                }
+                let (queued, new_prompt) = self.get_errors()?;
+                errors.extend(queued);
                 prompt = new_prompt;
                 *state = Some(ReadState::DataReadEnd);
             }
+            if !errors.is_empty() {
+                if errors.iter().any(|e| e.severity() >= Severity::Error) {
+                    self.pending = None;
+                }
+                self.print_errors(errors, save)?;
+            }
             debug!("Data handling complete");
         }
         Ok(prompt)
@@ -185,7 +291,13 @@ impl Repl {
         Ok(())
     }
 
-    fn handle_script_request(&mut self, file: &Path) -> Result<(bool, bool)> {
+    fn handle_script_request(
+        &mut self,
+        file: &Path,
+        syntax_check: bool,
+        preprocess_script: bool,
+        defines: &BTreeMap<String, String>,
+    ) -> Result<(bool, bool)> {
         let re = Regex::new(r"[^A-Za-z\d_]");
         let prompt = false;
         let command_written = true;
@@ -207,8 +319,66 @@ impl Repl {
 
                 let script_name = format!("kic_{result}");
 
-                self.inst
-                    .write_script(script_name.as_bytes(), contents.as_bytes(), false, true)?;
+                let script = if preprocess_script {
+                    match preprocess(file, &contents, defines, self.family.as_ref()) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("Unable to preprocess {}: {e}", file.display());
+                            self.print_error_line(&format!("Script error: {e}").red())?;
+                            return Ok((true, true));
+                        }
+                    }
+                } else {
+                    Preprocessed::unchanged(file, &contents)
+                };
+                let contents = &script.source;
+
+                // A script with a syntax error is not uploaded, the error is shown
+                // like an error reported by the instrument. The check can be skipped for
+                // scripts that use syntax the checker does not know about.
+                let syntax = if syntax_check {
+                    check_syntax(contents)
+                } else {
+                    Ok(())
+                };
+                if let Err(e) = syntax {
+                    warn!("Syntax error in {}: {e}", file.display());
+                    self.print_error_line(&format!("TSP syntax error: {}", e.message).red())?;
+                    self.print_error_line(&Diagnostic::preprocessed_syntax_error(
+                        file, &script, &e,
+                    ))?;
+                    return Ok((true, true));
+                }
+
+                self.source_map = Some(SourceMap::preprocessed(&script_name, file, &script));
+
+                match self.target {
+                    None => self.inst.write_script(
+                        script_name.as_bytes(),
+                        contents.as_bytes(),
+                        false,
+                        true,
+                    )?,
+                    Some(target) => {
+                        // The script is loaded on the local node and its source is run on
+                        // the target.
+                        self.inst.write_script(
+                            script_name.as_bytes(),
+                            contents.as_bytes(),
+                            false,
+                            false,
+                        )?;
+                        let run = match target {
+                            Target::Node(_) => {
+                                format!("{}.execute({script_name}.source)\n", target.table())
+                            }
+                            Target::Slot(_) => {
+                                format!("_KIC.run_on({}, {script_name}.source)\n", target.table())
+                            }
+                        };
+                        self.inst.write_all(run.as_bytes())?;
+                    }
+                }
             }
             Err(err_msg) => {
                 unreachable!("Issue with regex creation: {}", err_msg.to_string());
@@ -217,21 +387,626 @@ impl Repl {
         Ok((prompt, command_written))
     }
 
+    /// Show the documentation of a command, or the commands that match a query, for the
+    /// family of the connected instrument.
+    fn handle_doc_request(&self, action: &DocAction) -> Result<()> {
+        let family = self.family.as_ref();
+        match action {
+            DocAction::Show { command } => {
+                let Some(entry) = reference().get(command, family) else {
+                    let similar = reference().search(command, family, 5);
+                    Self::println_flush(
+                        &format!("No documentation was found for `{command}`").yellow(),
+                    )?;
+                    if !similar.is_empty() {
+                        Self::println_flush(&"Did you mean:".normal())?;
+                        for entry in similar {
+                            Self::println_flush(&format!("  {}", entry.command).normal())?;
+                        }
+                    }
+                    return Ok(());
+                };
+                if !entry.is_available(family) {
+                    Self::println_flush(
+                        &format!(
+                            "`{command}` is not available on the connected instrument, it is documented for {}",
+                            entry.family_names().join(", ")
+                        )
+                        .yellow(),
+                    )?;
+                }
+                Self::println_flush(&entry)?;
+            }
+            DocAction::Search { query } => {
+                let found = reference().search(query, family, 15);
+                if found.is_empty() {
+                    Self::println_flush(&format!("No commands match `{query}`").yellow())?;
+                }
+                for entry in found {
+                    Self::println_flush(
+                        &format!("  {:<28} {}", entry.command, entry.description).normal(),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_scripts_request(&mut self, action: ScriptsAction) -> Result<()> {
+        // Actions that only write a command produce no text of their own and leave the
+        // prompt to the instrument. The others parse the instrument output, so prompts
+        // are disabled until they are complete.
+        let result = match action {
+            ScriptsAction::Delete { name } => self.inst.delete_script(&name).map(|()| None),
+            ScriptsAction::Autorun { name, enable } => {
+                self.inst.set_script_autorun(&name, enable).map(|()| None)
+            }
+            ScriptsAction::Run { name } => self.inst.run_script(&name).map(|()| None),
+            ScriptsAction::List => {
+                self.inst.write_all(b"localnode.prompts = 0\n")?;
+                self.inst.list_scripts().map(|scripts| {
+                    if scripts.is_empty() {
+                        return Some("No scripts are saved on the instrument.".to_string());
+                    }
+                    Some(
+                        scripts
+                            .into_iter()
+                            .map(|s| {
+                                if s.autorun {
+                                    format!("{} (autorun)", s.name)
+                                } else {
+                                    s.name
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    )
+                })
+            }
+            ScriptsAction::Show { name } => {
+                self.inst.write_all(b"localnode.prompts = 0\n")?;
+                self.inst.script_source(&name).map(Some)
+            }
+            ScriptsAction::Pull { names, output } => {
+                self.inst.write_all(b"localnode.prompts = 0\n")?;
+                self.pull_scripts(&names, &output).map(Some)
+            }
+        };
+
+        match result {
+            Ok(None) => return Ok(()),
+            Ok(Some(text)) => Self::println_flush(&text.normal())?,
+            Err(e) => {
+                error!("Error managing scripts: {e}");
+                self.print_error_line(&e.to_string().red())?;
+            }
+        }
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(())
+    }
+
+    fn handle_slot_info_request(&mut self, slot: usize) -> Result<()> {
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        match self.inst.detailed_info() {
+            Ok(details) => {
+                let module = u16::try_from(slot).ok().and_then(|s| details.module(s));
+                match module {
+                    Some(module) => Self::println_flush(&module.to_string().normal())?,
+                    None => Self::println_flush(&format!("No module in slot {slot}").yellow())?,
+                }
+            }
+            Err(e) => {
+                error!("Error reading slot information: {e}");
+                self.print_error_line(&e.to_string().red())?;
+            }
+        }
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(())
+    }
+
+    /// List the global variables of the instrument, or the entries of the table at
+    /// `path`. Globals that the instrument defines itself are hidden unless `all` is set.
+    fn handle_vars_request(&mut self, path: Option<&str>, all: bool) -> Result<()> {
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        match list_variables(self.inst.as_mut(), path) {
+            Ok(variables) => {
+                let family = self.family.as_ref();
+                let shown: Vec<_> = variables
+                    .into_iter()
+                    .filter(|v| {
+                        all || path.is_some()
+                            || !(v.name == "_KIC" || is_instrument_global(&v.name, family))
+                    })
+                    .collect();
+                if shown.is_empty() {
+                    Self::println_flush(&"No variables".yellow())?;
+                }
+                for v in shown {
+                    Self::println_flush(
+                        &format!("  {:<24} {:<9} {}", v.name, v.kind, v.preview).normal(),
+                    )?;
+                }
+            }
+            Err(e) => {
+                error!("Error reading variables: {e}");
+                self.print_error_line(&e.to_string().red())?;
+            }
+        }
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(())
+    }
+
+    /// Read the output of the instrument until `done` is true for all of it. Gives up if
+    /// the instrument prints nothing for [`QUERY_TIMEOUT`], and then clears the rest of
+    /// the output so that it is not shown after the prompt.
+    fn read_output(&mut self, done: impl Fn(&str) -> bool, what: &str) -> Result<String> {
+        let mut output = String::new();
+        let mut last_output = Instant::now();
+        while !done(&output) {
+            if last_output.elapsed() > QUERY_TIMEOUT {
+                warn!("Timed out waiting for {what}");
+                self.clear_output_queue(5000, Duration::from_millis(1))?;
+                return Err(InstrumentReplError::Other(format!(
+                    "timed out waiting for the instrument to print {what}"
+                )));
+            }
+            let mut buf = vec![0u8; 1024];
+            let read_size = match self.inst.read(&mut buf) {
+                Ok(read_size) => read_size,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => 0,
+                Err(e) => return Err(e.into()),
+            };
+            let data = &buf[..read_size];
+            let data = &data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())];
+            if data.is_empty() {
+                std::thread::sleep(Duration::from_millis(1));
+            } else {
+                output.push_str(&String::from_utf8_lossy(data));
+                last_output = Instant::now();
+            }
+        }
+        Ok(output)
+    }
+
+    /// Write `tsp`, which prints [`plot::PLOT_END`] last, and return the lines it printed.
+    fn query_plot(&mut self, tsp: &str) -> Result<Vec<String>> {
+        self.inst.write_all(format!("{tsp}\n").as_bytes())?;
+        let output = self.read_output(|o| o.contains(plot::PLOT_END), "the plot data")?;
+        Ok(output.lines().map(ToString::to_string).collect())
+    }
+
+    /// Plot the readings of a buffer, against their source values if the buffer has
+    /// them, or the numbers in a table.
+    fn handle_plot_request(&mut self, target: &str, spark: bool, unit: Option<&str>) -> Result<()> {
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        let data = self
+            .query_plot(&plot::plot_query(target))
+            .and_then(|lines| plot::parse_plot(target, &lines));
+        let mut axes = plot::Axes {
+            x_label: "n".to_string(),
+            y_label: target.to_string(),
+            y_unit: unit.unwrap_or_default().to_string(),
+            ..plot::Axes::default()
+        };
+        match data {
+            Ok(plot::PlotData::Buffer(buffer)) => {
+                if axes.y_unit.is_empty() {
+                    axes.y_unit = buffer.unit;
+                }
+                let points: Vec<_> = match buffer.source_values {
+                    Some(sources) if !spark => {
+                        axes.x_label = "source".to_string();
+                        // Readings are usually of the quantity that is not sourced.
+                        axes.x_unit = match axes.y_unit.as_str() {
+                            "A" => "V",
+                            "V" => "A",
+                            _ => "",
+                        }
+                        .to_string();
+                        sources.into_iter().zip(buffer.readings).collect()
+                    }
+                    _ => buffer.indices.into_iter().zip(buffer.readings).collect(),
+                };
+                Self::println_flush(&plot::render(&points, &axes, spark).normal())?;
+            }
+            Ok(plot::PlotData::Values(values)) => {
+                Self::println_flush(&plot::render(&values, &axes, spark).normal())?;
+            }
+            Ok(plot::PlotData::Value(value)) => {
+                Self::println_flush(
+                    &format!("{target} is {value}, use `--live` to plot it over time").yellow(),
+                )?;
+            }
+            Err(e) => {
+                error!("Error plotting {target}: {e}");
+                self.print_error_line(&e.to_string().red())?;
+            }
+        }
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(())
+    }
+
+    /// Evaluate `target` every `interval` and redraw a plot of the last `samples` values
+    /// until the user enters anything. Returns `true` if the user asked to exit.
+    fn handle_live_plot_request(
+        &mut self,
+        target: &str,
+        interval: Duration,
+        samples: usize,
+        spark: bool,
+        unit: Option<&str>,
+        loop_in: &Receiver<UserInput>,
+    ) -> Result<bool> {
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        Self::println_flush(&format!("Plotting {target}, press Enter to stop").yellow())?;
+        let axes = plot::Axes {
+            x_label: "time".to_string(),
+            x_unit: "s".to_string(),
+            y_label: target.to_string(),
+            y_unit: unit.unwrap_or_default().to_string(),
+        };
+        let query = plot::sample_query(target);
+        let start = Instant::now();
+        let mut points: VecDeque<(f64, f64)> = VecDeque::with_capacity(samples);
+        let mut drawn = 0_usize;
+        let exit = 'sample: loop {
+            let sampled_at = Instant::now();
+            let value = match self
+                .query_plot(&query)
+                .and_then(|lines| plot::parse_sample(target, &lines))
+            {
+                Ok(value) => value,
+                Err(e) => {
+                    error!("Error sampling {target}: {e}");
+                    self.print_error_line(&e.to_string().red())?;
+                    break 'sample false;
+                }
+            };
+            if points.len() >= samples {
+                points.pop_front();
+            }
+            points.push_back((start.elapsed().as_secs_f64(), value));
+            let chart = plot::render(points.make_contiguous(), &axes, spark);
+            if drawn > 0 {
+                // Move back to the start of the previous plot and clear it.
+                Self::print_flush(&format!("\x1b[{drawn}A\r\x1b[J"))?;
+            }
+            Self::println_flush(&chart.normal())?;
+            drawn = chart.lines().count();
+
+            while sampled_at.elapsed() < interval {
+                match loop_in.try_recv() {
+                    Ok(UserInput {
+                        request: Request::Exit,
+                        ..
+                    }) => break 'sample true,
+                    Ok(_) | Err(TryRecvError::Disconnected) => break 'sample false,
+                    Err(TryRecvError::Empty) if INTERRUPTED.load(Ordering::SeqCst) => {
+                        break 'sample false;
+                    }
+                    Err(TryRecvError::Empty) => std::thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        };
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(exit)
+    }
+
+    fn handle_watch_request(&mut self, action: WatchAction) -> Result<()> {
+        match action {
+            WatchAction::List if self.watches.is_empty() => {
+                Self::println_flush(&"No expressions are watched".yellow())?;
+            }
+            WatchAction::List => {
+                for (i, w) in self.watches.iter().enumerate() {
+                    let log = w
+                        .log
+                        .as_ref()
+                        .map_or_else(String::new, |l| format!(", logged to {}", l.display()));
+                    Self::println_flush(
+                        &format!("  {}: {w}  every {:?}{log}", i.saturating_add(1), w.every)
+                            .normal(),
+                    )?;
+                }
+            }
+            WatchAction::Add {
+                expression,
+                every,
+                log,
+            } => {
+                let shown = if self.tui.is_some() {
+                    ""
+                } else {
+                    ". Changed values are shown with the next prompt, or as they change with \
+                    `kic connect --tui`"
+                };
+                Self::println_flush(
+                    &format!(
+                        "Watching {expression} every {every:?}, stop with `.watch off {}`{shown}",
+                        self.watches.len().saturating_add(1)
+                    )
+                    .yellow(),
+                )?;
+                self.watches.push(Watch::new(expression, every, log));
+            }
+            WatchAction::Remove { number: None } => {
+                self.watches.clear();
+                Self::println_flush(&"Stopped all watches".yellow())?;
+            }
+            WatchAction::Remove {
+                number: Some(number),
+            } => {
+                if (1..=self.watches.len()).contains(&number) {
+                    let w = self.watches.remove(number.saturating_sub(1));
+                    Self::println_flush(&format!("Stopped watching {}", w.expression).yellow())?;
+                } else {
+                    self.print_error_line(&format!("There is no watch {number}").red())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_export_request(&mut self, file: &Path, function: Option<&str>) -> Result<()> {
+        if self.executed.is_empty() {
+            return Self::println_flush(&"No TSP commands have run without errors yet".yellow());
+        }
+        let info = self.inst.info()?.to_string();
+        let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let script = export::script(&self.executed, &info, &time, function);
+        if let Some(parent) = file.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, script)?;
+        Self::println_flush(
+            &format!(
+                "Exported {} command(s) to {}",
+                self.executed.len(),
+                file.display()
+            )
+            .yellow(),
+        )?;
+        if self.unexported > 0 {
+            Self::println_flush(
+                &format!(
+                    "Left out {} command(s) that were sent to a slot, which can't be run from a script",
+                    self.unexported
+                )
+                .yellow(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn handle_macro_request(&self, action: MacroAction) -> Result<()> {
+        match action {
+            MacroAction::List => self.print_macros()?,
+            MacroAction::Define { name, body } => match self.macros.define(&name, body) {
+                Ok(()) => {
+                    let name = name.trim_start_matches('.');
+                    Self::println_flush(&format!("Defined `.{name}`").yellow())?;
+                }
+                Err(e) => self.print_error_line(&e.to_string().red())?,
+            },
+            MacroAction::Remove { name } => {
+                let name = name.trim_start_matches('.');
+                if self.macros.remove(name) {
+                    Self::println_flush(&format!("Removed `.{name}`").yellow())?;
+                } else {
+                    self.print_error_line(&format!("There is no macro `.{name}`").red())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_macros(&self) -> Result<()> {
+        let macros = self.macros.list();
+        if macros.is_empty() {
+            return Self::println_flush(&"No macros are defined".yellow());
+        }
+        let width = macros
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+        for (name, body) in macros {
+            Self::println_flush(&format!("  .{name:<width$}  {body}").normal())?;
+        }
+        Ok(())
+    }
+
+    /// Write `tsp`, which prints `end` last, while the REPL is waiting for a command and
+    /// return what it printed. The prompt that follows is read as well so that the user
+    /// loop doesn't show another one.
+    fn query_while_idle(&mut self, tsp: &str, end: &str) -> Result<String> {
+        self.inst.write_all(format!("{tsp}\n").as_bytes())?;
+        self.read_output(
+            |o| {
+                o.split_once(end)
+                    .is_some_and(|(_, rest)| rest.contains("TSP>") || rest.contains("TSP?"))
+            },
+            "the result of a query",
+        )
+    }
+
+    /// Run `query` while the REPL is waiting for a command, with prompts turned off so
+    /// that its output can be parsed. The prompt that follows is read so that the user
+    /// loop doesn't print another one.
+    fn with_prompts_off<T>(&mut self, query: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        let result = query(self)?;
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        let _ = read_until(
+            self.inst.as_mut(),
+            &["TSP>".to_string()],
+            5000,
+            Duration::from_millis(1),
+        )?;
+        Ok(result)
+    }
+
+    /// Evaluate the watches that are due and redraw them above the prompt if a value
+    /// changed.
+    fn poll_watches(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let due: Vec<usize> = (0..self.watches.len())
+            .filter(|i| self.watches[*i].is_due(now))
+            .collect();
+        if due.is_empty() {
+            return Ok(());
+        }
+        for i in &due {
+            self.watches[*i].schedule(now);
+        }
+        let query = watch::query(
+            due.iter()
+                .map(|i| (*i, self.watches[*i].expression.as_str())),
+        );
+
+        let output = self.query_while_idle(&query, watch::WATCH_END)?;
+
+        let time = Local::now();
+        let mut changed = false;
+        for (i, value) in watch::parse_values(&output) {
+            if let Some(w) = self.watches.get_mut(i) {
+                changed |= w.update(value, time)?;
+            }
+        }
+        if changed {
+            if self.tui.is_some() {
+                self.draw_watches()?;
+            } else {
+                // The user may be typing after the prompt, so the watches are printed
+                // with the next one.
+                self.watches_changed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the watches at the bottom of the output pane of the full-screen interface,
+    /// replacing them if nothing else has been printed since they were last printed.
+    fn draw_watches(&mut self) -> Result<()> {
+        let mut drawn = String::new();
+        if self.watch_block > 0 {
+            let _ = write!(drawn, "\x1b[{}A", self.watch_block);
+        }
+        for w in &self.watches {
+            let _ = writeln!(drawn, "\x1b[2K{}", w.to_string().cyan());
+        }
+        self.watch_block = self.watches.len();
+        Self::print_flush(&drawn)
+    }
+
+    fn pull_scripts(
+        &mut self,
+        names: &[String],
+        output: &Path,
+    ) -> std::result::Result<String, InstrumentError> {
+        Ok(self
+            .inst
+            .pull_scripts(names, output)?
+            .iter()
+            .map(|p| {
+                let name = p.file_stem().unwrap_or_default().to_string_lossy();
+                format!("Saved script '{name}' to {}", p.display())
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
     /// Start the Repl
     ///
     /// # Errors
     /// There are many errors that can be returned from this function, they include but
     /// aren't limited to any errors possible from [`std::io::Read`] or [`std::io::Write`]
-    #[allow(clippy::too_many_lines, clippy::cognitive_complexity)] //This is just going to be a long function
     #[instrument(skip(self))]
     pub fn start(&mut self) -> Result<()> {
+        let Some(tui) = self.tui.clone() else {
+            return self.run();
+        };
+        tui.enter()?;
+        let result = self.run();
+        tui.leave()?;
+        result
+    }
+
+    /// Send the commands of a transcript to the instrument one at a time and compare the
+    /// output and errors of each with the recorded ones. A command may take up to
+    /// `timeout`.
+    ///
+    /// # Errors
+    /// Returns an error if the instrument could not be written or read, or a command
+    /// did not finish within `timeout`.
+    pub fn replay(&mut self, steps: &[Step], timeout: Duration) -> Result<Vec<Difference>> {
+        info!("Replaying {} command(s)", steps.len());
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        self.inst.write_script(
+            b"_kic_common",
+            KIC_COMMON_TSP.to_string().as_bytes(),
+            false,
+            true,
+        )?;
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        // Errors from before the replay are not compared.
+        let _ = self.get_errors()?;
+
+        let attempts = usize::try_from(timeout.as_millis()).unwrap_or(usize::MAX);
+        let mut differences = Vec::new();
+        for (i, step) in steps.iter().enumerate() {
+            let number = i.saturating_add(1);
+            debug!("Replaying command {number}: {}", step.tsp);
+            self.inst
+                .write_all(format!("{}\nprint(\"{REPLAY_END}\")\n", step.tsp).as_bytes())?;
+            let output = read_until(
+                self.inst.as_mut(),
+                &[REPLAY_END.to_string()],
+                attempts,
+                Duration::from_millis(1),
+            )
+            .map_err(|_| {
+                InstrumentReplError::Other(format!(
+                    "command {number} did not finish within {timeout:?}"
+                ))
+            })?;
+            let output = output
+                .split(REPLAY_END)
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            let (errors, _) = self.get_errors()?;
+            let replayed = Step {
+                tsp: step.tsp.clone(),
+                output,
+                errors: errors.iter().map(TspError::code).collect(),
+            };
+            differences.extend(step.compare(number, &replayed));
+        }
+        Ok(differences)
+    }
+
+    #[allow(clippy::too_many_lines, clippy::cognitive_complexity)] //This is just going to be a long function
+    fn run(&mut self) -> Result<()> {
         info!("Starting REPL");
         let mut prev_state: Option<ReadState> = None;
         let mut state: Option<ReadState> = None;
 
         let (user_out, loop_in) = channel();
+        install_interrupt_handler();
+        INTERRUPTED.store(false, Ordering::SeqCst);
 
-        let join = Self::init_user_input(user_out)?;
+        let join = match self.tui.clone() {
+            Some(tui) => Self::init_tui_input(user_out, tui, self.macros.clone())?,
+            None => Self::init_user_input(user_out, self.macros.clone())?,
+        };
 
         self.clear_output_queue(5000, Duration::from_millis(1))?;
         //self.inst.set_nonblocking(false)?;
@@ -245,22 +1020,63 @@ impl Repl {
         )?;
         debug!("Writing common script to instrument completed");
 
+        if self.tui.is_some() {
+            self.inst.write_all(b"localnode.prompts = 0\n")?;
+            self.clear_output_queue(5000, Duration::from_millis(1))?;
+            self.update_tsplink_status()?;
+        }
+
         self.inst.write_all(b"_KIC.prompts_enable(true)\n")?;
         let (errors, _) = self.get_errors()?;
-        for e in errors {
-            error!("TSP error: {e}");
-            Self::print_data(None, ParsedResponse::TspError(e.to_string()), None)?;
-        }
+        self.print_errors(errors, None)?;
         let mut prompt = true;
         let mut abort = false;
         let mut command_written = true;
         let mut last_read = Instant::now();
         let mut processing_request = false;
         let mut save: Option<Save> = None;
+        let mut last_interrupt: Option<Instant> = None;
         debug!("Starting user loop");
         'user_loop: loop {
             //self.inst.set_nonblocking(true)?;
             std::thread::sleep(Duration::from_micros(1));
+            if INTERRUPTED.swap(false, Ordering::SeqCst) {
+                if last_interrupt.is_some_and(|t| t.elapsed() < EXIT_WINDOW) {
+                    info!("Exiting after a second Ctrl+C");
+                    // The input thread is blocked reading the next line, so it is not
+                    // joined. It ends with the process.
+                    return Ok(());
+                }
+                last_interrupt = Some(Instant::now());
+                if let Err(e) = self.interrupt(save.as_ref()) {
+                    error!("Error aborting after Ctrl+C: {e}");
+                    self.print_error_line(
+                        &format!("Unable to abort the running command: {e}").red(),
+                    )?;
+                    // Prompts may have been left off, so turn them on again for the
+                    // instrument to prompt for the next command.
+                    self.inst.write_all(b"localnode.prompts = 1\n")?;
+                }
+                let message = if processing_request {
+                    "\nAborted, press Ctrl+C again to exit"
+                } else {
+                    "\nPress Ctrl+C again to exit"
+                };
+                Self::println_flush(&message.yellow())?;
+                // The prompt is shown when the instrument prompts after the abort.
+                abort = false;
+                prompt = false;
+                command_written = true;
+                processing_request = true;
+                prev_state = None;
+                state = None;
+                continue 'user_loop;
+            }
+            if let Some(tui) = &self.tui {
+                tui.fit_to_terminal()?;
+                let output = save.as_ref().map(|s| s.output.display().to_string());
+                tui.update_status(|status| status.save = output)?;
+            }
             if abort || command_written || last_read.elapsed() >= Duration::from_secs(3) {
                 let mut read_buf: Vec<u8> = vec![0; 1024];
                 last_read = Instant::now();
@@ -301,15 +1117,9 @@ impl Repl {
                     command_written = true;
                 }
                 (true, true | false, false) => {
+                    self.pending = None;
                     let (errors, _) = self.get_errors()?;
-                    for e in errors {
-                        error!("TSP error: {e}");
-                        Self::print_data(
-                            state,
-                            ParsedResponse::TspError(e.to_string()),
-                            save.as_ref(),
-                        )?;
-                    }
+                    self.print_errors(errors, save.as_ref())?;
                     save = None;
                     // Enable prompts after reading errors
                     self.inst.write_all(b"localnode.prompts = 1\n")?;
@@ -317,12 +1127,22 @@ impl Repl {
                 }
                 (false, true, true) => {
                     match save {
-                        Some(s) if s.clone().method != SaveMethod::Start => save = None,
+                        Some(s)
+                            if !matches!(s.method, SaveMethod::Start | SaveMethod::Transcript) =>
+                        {
+                            save = None;
+                        }
                         _ => {}
                     }
                     prompt = false;
                     command_written = false;
-                    Self::print_flush(&"\nTSP> ".blue())?;
+                    if let Some((tsp, target)) = self.pending.take() {
+                        match target.map_or_else(|| Some(tsp.clone()), |t| t.export(&tsp)) {
+                            Some(tsp) => self.executed.push(tsp),
+                            None => self.unexported = self.unexported.saturating_add(1),
+                        }
+                    }
+                    self.show_prompt()?;
                     processing_request = false;
                 }
                 (false, true, false) => {
@@ -332,8 +1152,23 @@ impl Repl {
             }
 
             match loop_in.try_recv() {
-                Ok(msg) => {
+                Ok(UserInput { line, request: msg }) => {
                     debug!("User loop received request: {msg:?}");
+                    self.watch_block = 0;
+                    if let Some(t) = save.as_ref().and_then(|s| s.transcript.as_ref()) {
+                        if !matches!(
+                            msg,
+                            Request::Tsp(_)
+                                | Request::None
+                                | Request::Usage(_)
+                                | Request::InvalidInput(_)
+                        ) {
+                            t.append(Entry::DotCommand {
+                                command: line,
+                                runs_tsp: msg.runs_tsp(),
+                            })?;
+                        }
+                    }
                     if processing_request {
                         if msg == Request::Abort {
                             self.inst.as_mut().abort()?;
@@ -348,33 +1183,134 @@ impl Repl {
                     }
 
                     processing_request = true;
+                    // Errors are only located in the script uploaded by the
+                    // previous request, not in any later ones.
+                    self.source_map = None;
 
                     match msg {
                         Request::Tsp(tsp) => {
-                            if let Some(ref s) = save {
-                                //print out the user command with a TSP> to the appropriate file.
-                                Self::write_to_file(
-                                    &s.output,
-                                    format!("\nTSP> {tsp}\n").as_bytes(),
-                                )?;
+                            let sent = self.target.map_or_else(|| tsp.clone(), |t| t.wrap(&tsp));
+                            match save {
+                                // The transcript has what was sent so that it can be replayed.
+                                Some(Save {
+                                    transcript: Some(ref t),
+                                    ..
+                                }) => {
+                                    t.append(Entry::Command { tsp: sent.clone() })?;
+                                }
+                                Some(ref s) => {
+                                    //print out the user command with a TSP> to the appropriate file.
+                                    Self::write_to_file(
+                                        &s.output,
+                                        format!("\nTSP> {tsp}\n").as_bytes(),
+                                    )?;
+                                }
+                                None => {}
                             }
-                            self.inst.write_all(format!("{tsp}\n").as_bytes())?;
+                            self.inst.write_all(format!("{sent}\n").as_bytes())?;
+                            self.pending = Some((tsp, self.target));
                             command_written = true;
                             prev_state = None;
                         }
+                        Request::Route(target) => {
+                            self.target = target;
+                            if let Some(target) = target {
+                                info!("Routing commands to {target}");
+                                self.inst
+                                    .write_all(format!("{}\n", target.check()).as_bytes())?;
+                                command_written = true;
+                                prev_state = None;
+                            } else {
+                                info!("Routing commands to the local node");
+                                prompt = true;
+                                command_written = true;
+                            }
+                        }
                         Request::GetError => {
                             let (errors, _) = self.get_errors()?;
-                            for e in errors {
-                                error!("TSP error: {e}");
-                                Self::print_data(
-                                    state,
-                                    ParsedResponse::TspError(e.to_string()),
-                                    save.as_ref(),
-                                )?;
+                            self.print_errors(errors, save.as_ref())?;
+                            prompt = true;
+                            command_written = true;
+                        }
+                        Request::Errors(ErrorsAction::Explain { code }) => {
+                            match catalog().get(code, self.family.as_ref()) {
+                                Some(entry) => Self::println_flush(&entry)?,
+                                None => Self::println_flush(
+                                    &format!("No explanation is available for error {code}")
+                                        .yellow(),
+                                )?,
                             }
                             prompt = true;
                             command_written = true;
                         }
+                        Request::Vars { path, all } => {
+                            self.handle_vars_request(path.as_deref(), all)?;
+                            command_written = true;
+                            prev_state = None;
+                        }
+                        Request::Plot {
+                            target,
+                            live: false,
+                            spark,
+                            unit,
+                            ..
+                        } => {
+                            self.handle_plot_request(&target, spark, unit.as_deref())?;
+                            command_written = true;
+                            prev_state = None;
+                        }
+                        Request::Plot {
+                            target,
+                            live: true,
+                            interval,
+                            samples,
+                            spark,
+                            unit,
+                        } => {
+                            let exit = self.handle_live_plot_request(
+                                &target,
+                                interval,
+                                samples,
+                                spark,
+                                unit.as_deref(),
+                                &loop_in,
+                            )?;
+                            if exit {
+                                info!("Exiting...");
+                                break 'user_loop;
+                            }
+                            command_written = true;
+                            prev_state = None;
+                        }
+                        Request::Watch(action) => {
+                            self.handle_watch_request(action)?;
+                            prompt = true;
+                            command_written = true;
+                        }
+                        Request::Export { file, function } => {
+                            self.handle_export_request(&file, function.as_deref())?;
+                            prompt = true;
+                            command_written = true;
+                        }
+                        Request::Macro(action) => {
+                            self.handle_macro_request(action)?;
+                            prompt = true;
+                            command_written = true;
+                        }
+                        Request::Doc(action) => {
+                            self.handle_doc_request(&action)?;
+                            prompt = true;
+                            command_written = true;
+                        }
+                        Request::Errors(ErrorsAction::Filter { severity }) => {
+                            self.min_severity = severity;
+                            Self::println_flush(
+                                &format!("Showing errors of {severity} severity and above")
+                                    .yellow(),
+                            )?;
+                            prompt = true;
+                            command_written = true;
+                        }
                         Request::Save(s) => {
                             trace!("Save Requested");
                             match s.clone().method {
@@ -383,35 +1319,47 @@ impl Repl {
                                     save = None;
                                     // due to complications, we just print the next prompt for the
                                     // user
-                                    eprintln!(
-                                        "{}",
-                                        "\nSaving of commands, errors, and printed output ended"
-                                            .yellow()
-                                    );
-                                    Self::print_flush(&"\nTSP> ".blue())?;
+                                    Self::println_flush(
+                                        &"\nSaving of commands, errors, and printed output ended"
+                                            .yellow(),
+                                    )?;
+                                    self.show_prompt()?;
                                 }
                                 SaveMethod::Start => {
                                     processing_request = false;
                                     save = Some(s);
                                     // due to complications, we just print the next prompt for the
                                     // user
-                                    eprintln!(
-                                        "{}",
-                                        format!(
+                                    Self::println_flush(
+                                        &format!(
                                             "\nSaving commands, errors, and printed output to {}",
                                             save.as_ref().map_or_else(
                                                 || "UNABLE TO GET OUTPUT".to_string(),
                                                 |d| d.output.display().to_string()
                                             )
                                         )
+                                        .yellow(),
+                                    )?;
+                                    self.show_prompt()?;
+                                }
+                                SaveMethod::Transcript => {
+                                    processing_request = false;
+                                    Self::println_flush(
+                                        &format!(
+                                            "\nRecording a transcript of commands, errors, and printed output to {}",
+                                            s.output.display()
+                                        )
                                         .yellow()
-                                    );
-                                    Self::print_flush(&"\nTSP> ".blue())?;
+                                    )?;
+                                    save = Some(Save {
+                                        transcript: Some(Transcript::open(&s.output)?),
+                                        ..s
+                                    });
+                                    self.show_prompt()?;
                                 }
                                 SaveMethod::Script { file } => {
                                     save = Some(s);
-                                    eprintln!(
-                                        "{}",
+                                    Self::println_flush(
                                         &format!(
                                             "Saving output of script '{}' to {}",
                                             file.display(),
@@ -420,8 +1368,8 @@ impl Repl {
                                                 |d| d.output.display().to_string()
                                             )
                                         )
-                                        .yellow()
-                                    );
+                                        .yellow(),
+                                    )?;
                                     Self::write_to_file(
                                         &save.as_ref().map_or_else(
                                             || "./SCRIPT_OUTPUT.txt".into(),
@@ -430,8 +1378,12 @@ impl Repl {
                                         format!("\nRunning Script: {}\n", file.display())
                                             .as_bytes(),
                                     )?;
-                                    (prompt, command_written) =
-                                        self.handle_script_request(&file)?;
+                                    (prompt, command_written) = self.handle_script_request(
+                                        &file,
+                                        true,
+                                        true,
+                                        &BTreeMap::new(),
+                                    )?;
                                 }
                                 SaveMethod::Buffers {
                                     names,
@@ -439,8 +1391,7 @@ impl Repl {
                                     delimiter,
                                 } => {
                                     save = Some(s);
-                                    eprintln!(
-                                        "{}",
+                                    Self::println_flush(
                                         &format!(
                                             "Saving contents of buffer(s) {} to {}",
                                             names.join(","),
@@ -449,8 +1400,8 @@ impl Repl {
                                                 |d| d.output.display().to_string()
                                             ),
                                         )
-                                        .yellow()
-                                    );
+                                        .yellow(),
+                                    )?;
                                     self.inst.write_all(
                                         format!("_KIC.print_buffers_csv({{{}}}, {{'{}'}}, '{delimiter}')\n",
                                             names.into_iter()
@@ -466,8 +1417,18 @@ impl Repl {
                                 }
                             }
                         }
-                        Request::Script { file } => {
-                            (prompt, command_written) = self.handle_script_request(&file)?;
+                        Request::Script {
+                            file,
+                            check_syntax,
+                            preprocess,
+                            defines,
+                        } => {
+                            (prompt, command_written) = self.handle_script_request(
+                                &file,
+                                check_syntax,
+                                preprocess,
+                                &defines,
+                            )?;
                         }
                         Request::TspLinkNodes { json_file } => {
                             self.set_lang_config_path(json_file.to_string_lossy().to_string());
@@ -481,16 +1442,26 @@ impl Repl {
                             prompt = true;
                             command_written = true;
                         }
-                        Request::Info { .. } => {
+                        Request::Scripts(action) => {
+                            self.handle_scripts_request(action)?;
+                            command_written = true;
+                            prev_state = None;
+                        }
+                        Request::Info { slot: None } => {
                             Self::println_flush(&self.inst.info()?.to_string().normal())?;
                             prompt = true;
                             command_written = true;
                         }
+                        Request::Info { slot: Some(slot) } => {
+                            self.handle_slot_info_request(slot)?;
+                            command_written = true;
+                            prev_state = None;
+                        }
                         Request::Upgrade { file, slot } => {
                             let mut contents: Vec<u8> = Vec::new();
                             let _ = File::open(&file)?.read_to_end(&mut contents)?;
                             if contents.is_empty() {
-                                Self::println_flush(&"Firmware file is empty (0 bytes)".red())?;
+                                self.print_error_line(&"Firmware file is empty (0 bytes)".red())?;
                                 prompt = true;
                                 continue 'user_loop;
                             }
@@ -499,14 +1470,7 @@ impl Repl {
                                 Self::println_flush(
                                     &"Errors from device before sending firmware:".bright_yellow(),
                                 )?;
-                                for e in errors {
-                                    error!("TSP error before fw flash: {e}");
-                                    Self::print_data(
-                                        state,
-                                        ParsedResponse::TspError(e.to_string()),
-                                        save.as_ref(),
-                                    )?;
-                                }
+                                self.print_errors(errors, save.as_ref())?;
                             }
                             if slot.is_some_and(|s| s > 0) {
                                 // Upgrading Module
@@ -523,19 +1487,14 @@ impl Repl {
                             match self.inst.flash_firmware(contents.as_ref(), slot) {
                                 Ok(()) => {
                                     let (errors, _) = self.get_errors()?;
+                                    let flash_failed = !errors.is_empty();
 
-                                    if !errors.is_empty() {
+                                    if flash_failed {
                                         Self::println_flush(
                                             &"\nErrors detected after attempting to flash FW:"
                                                 .bright_yellow(),
                                         )?;
-                                        for e in &errors {
-                                            Self::print_data(
-                                                state,
-                                                ParsedResponse::TspError(e.to_string()),
-                                                save.as_ref(),
-                                            )?;
-                                        }
+                                        self.print_errors(errors, save.as_ref())?;
                                         Self::println_flush(
                                             &"Choose a different file or flash target."
                                                 .bright_yellow(),
@@ -551,7 +1510,7 @@ impl Repl {
                                         Self::println_flush(
                                             &"Firmware file download complete.".bright_yellow(),
                                         )?;
-                                        if errors.is_empty() {
+                                        if !flash_failed {
                                             // Upgrading Mainframe
                                             Self::println_flush(&"Close the terminal and reconnect after the instrument has restarted.".bright_yellow())?;
                                             break 'user_loop;
@@ -560,7 +1519,7 @@ impl Repl {
                                 }
                                 Err(InstrumentError::FwUpgradeFailure(msg)) => {
                                     error!("{msg}");
-                                    Self::println_flush(&msg.red())?;
+                                    self.print_error_line(&msg.red())?;
                                 }
                                 Err(e) => return Err(e.into()),
                             }
@@ -598,9 +1557,19 @@ impl Repl {
                                 {
                                     sub.print_help()?;
                                     continue 'user_loop;
-                                };
+                                }
+                                if let Some(body) = self.macros.get(&sub_cmd) {
+                                    Self::println_flush(
+                                        &format!("User-defined macro for:\n   {body}").normal(),
+                                    )?;
+                                    continue 'user_loop;
+                                }
                             }
                             self.command.print_help()?;
+                            if !self.macros.list().is_empty() {
+                                Self::println_flush(&"Macros:".bold())?;
+                                self.print_macros()?;
+                            }
                         }
                         Request::Usage(s) => {
                             prompt = true;
@@ -611,7 +1580,7 @@ impl Repl {
                             prompt = true;
                             command_written = true;
                             warn!("Invalid input: {s}");
-                            Self::println_flush(&(s + "\n").red())?;
+                            self.print_error_line(&(s + "\n").red())?;
                         }
                         Request::None => {
                             prompt = true;
@@ -623,7 +1592,20 @@ impl Repl {
                     trace!("user input disconnected");
                     break 'user_loop;
                 }
-                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Empty) => {
+                    // Watches are only evaluated while waiting for a command.
+                    if !(processing_request || command_written || prompt || abort) {
+                        if let Err(e) = self.poll_watches() {
+                            error!("Error evaluating watches: {e}");
+                            self.print_error_line(&format!("\nStopped watching: {e}").red())?;
+                            self.watches.clear();
+                            self.show_prompt()?;
+                        }
+                        if self.tui.is_some() && self.tsplink_checked.elapsed() >= TSPLINK_REFRESH {
+                            self.with_prompts_off(Self::update_tsplink_status)?;
+                        }
+                    }
+                }
             }
         }
         drop(loop_in);
@@ -631,6 +1613,21 @@ impl Repl {
         Ok(())
     }
 
+    /// Abort the running command after Ctrl+C, discard its remaining output and print
+    /// the errors it caused. Prompts are enabled again so that the instrument prompts
+    /// for the next command.
+    fn interrupt(&mut self, save: Option<&Save>) -> Result<()> {
+        info!("Aborting after Ctrl+C");
+        self.pending = None;
+        self.inst.as_mut().abort()?;
+        self.inst.write_all(b"localnode.prompts = 0\n")?;
+        self.clear_output_queue(5000, Duration::from_millis(1))?;
+        let (errors, _) = self.get_errors()?;
+        self.print_errors(errors, save)?;
+        self.inst.write_all(b"localnode.prompts = 1\n")?;
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     fn get_errors(&mut self) -> Result<(Vec<TspError>, bool)> {
         self.inst.write_all(b"print(_KIC.error_message())\n")?;
@@ -667,18 +1664,133 @@ impl Repl {
             }
         }
 
-        let parser = ResponseParser::new(err.as_bytes());
-        for response in parser {
-            if let ParsedResponse::TspError(e) = &response {
-                let x: TspError = serde_json::from_str(e.trim())?;
-                errors.push(x);
+        let parser = ResponseParser::new(err.as_bytes());
+        for response in parser {
+            if let ParsedResponse::TspError(e) = &response {
+                let x: TspError = serde_json::from_str(e.trim())?;
+                errors.push(x);
+            }
+            // in trebuchet for usbtmc connection prompt is getting in error query output, which got handled here
+            if response == ParsedResponse::Prompt {
+                prompt = true;
+            }
+        }
+        Ok((errors, prompt))
+    }
+
+    /// The prompt shown to the user, which includes the target commands are routed to.
+    fn prompt(&self) -> String {
+        self.target
+            .map_or_else(|| "\nTSP> ".to_string(), |t| format!("\nTSP {t}> "))
+    }
+
+    /// Show the prompt for the next command, on the input line of the full-screen
+    /// interface if it is used.
+    fn show_prompt(&mut self) -> Result<()> {
+        if let Some(tui) = &self.tui {
+            tui.set_prompt(&self.prompt())?;
+            return Ok(());
+        }
+        if std::mem::take(&mut self.watches_changed) {
+            let watches: Vec<_> = self.watches.iter().map(ToString::to_string).collect();
+            Self::print_flush(&format!("\n{}", watches.join("\n")).cyan())?;
+        }
+        Self::print_flush(&self.prompt().blue())?;
+        Ok(())
+    }
+
+    /// Show the TSP-Link nodes in the status bar of the full-screen interface. Prompts
+    /// should be disabled and the output queue should be empty.
+    fn update_tsplink_status(&mut self) -> Result<()> {
+        self.tsplink_checked = Instant::now();
+        let nodes = match query_status(self.inst.as_mut()) {
+            Ok(status) if status.is_online() => status.nodes.iter().map(|n| n.node).collect(),
+            Ok(_) => Vec::new(),
+            Err(e) => {
+                warn!("Unable to read the TSP-Link status: {e}");
+                Vec::new()
+            }
+        };
+        if let Some(tui) = &self.tui {
+            tui.update_status(|status| status.nodes = nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Print an error, or add it to the error panel of the full-screen interface if it
+    /// is used.
+    fn print_error_line<D: Display>(&self, line: &D) -> Result<()> {
+        match &self.tui {
+            Some(tui) => tui.push_error(&line.to_string())?,
+            None => Self::println_flush(line)?,
+        }
+        Ok(())
+    }
+
+    /// Print TSP errors, colored by severity, with a hint from the error catalog where
+    /// there is one. Errors are grouped by the TSP-Link node that reported them if there
+    /// is more than one, and errors below the minimum severity are hidden.
+    fn print_errors(&self, errors: Vec<TspError>, save: Option<&Save>) -> Result<()> {
+        // The transcript has all errors, regardless of the severities that are shown.
+        if let Some(t) = save.and_then(|s| s.transcript.as_ref()) {
+            for e in &errors {
+                t.append(Entry::from(e))?;
             }
-            // in trebuchet for usbtmc connection prompt is getting in error query output, which got handled here
-            if response == ParsedResponse::Prompt {
-                prompt = true;
+        }
+        let (shown, hidden): (Vec<_>, Vec<_>) = errors
+            .into_iter()
+            .partition(|e| e.severity() >= self.min_severity);
+        let mut by_node: BTreeMap<i16, Vec<TspError>> = BTreeMap::new();
+        for e in shown {
+            by_node.entry(e.node_id()).or_default().push(e);
+        }
+        let grouped = by_node.len() > 1;
+        for (node, errors) in by_node {
+            if grouped {
+                self.print_error_line(&format!("Node {node}:").yellow())?;
+            }
+            for e in errors {
+                error!("TSP error: {e}");
+                if let Some(s) = save.filter(|s| s.method != SaveMethod::Transcript) {
+                    Self::write_to_file(&s.output, format!("TSP Error: {e}\n").as_bytes())?;
+                }
+                let text = e
+                    .time()
+                    .map_or_else(|| e.to_string(), |time| format!("{e} ({time})"));
+                let text = match e.severity() {
+                    Severity::Info => text.normal(),
+                    Severity::Warning => text.yellow(),
+                    Severity::Error => text.red(),
+                    Severity::Fatal => text.bright_red().bold(),
+                };
+                self.print_error_line(&text)?;
+                if let Some(d) = self.source_map.as_ref().and_then(|m| m.diagnostic(&e)) {
+                    self.print_error_line(&d)?;
+                }
+                if e.severity() >= Severity::Error {
+                    if let Some(entry) = catalog().get(e.code(), self.family.as_ref()) {
+                        self.print_error_line(
+                            &format!(
+                                "  Hint: {} (see `.errors explain {}`)",
+                                entry.remedy, entry.code
+                            )
+                            .dimmed(),
+                        )?;
+                    }
+                }
             }
         }
-        Ok((errors, prompt))
+        if !hidden.is_empty() {
+            self.print_error_line(
+                &format!(
+                    "{} message(s) below {} severity hidden",
+                    hidden.len(),
+                    self.min_severity
+                )
+                .dimmed(),
+            )?;
+        }
+        Ok(())
     }
 
     fn print_flush<D: Display>(string: &D) -> Result<()> {
@@ -699,19 +1811,22 @@ impl Repl {
         save: Option<&Save>,
     ) -> Result<()> {
         match resp {
-            ParsedResponse::TspError(e) => {
-                if let Some(s) = save {
-                    Self::write_to_file(&s.output, format!("TSP Error: {e}\n").as_bytes())?;
-                }
-                Self::print_flush(&(e + "\n").red())
-            }
             ParsedResponse::Data(d) => {
-                if let Some(s) = save {
-                    Self::write_to_file(&s.output, &d)?;
+                match save {
+                    Some(Save {
+                        transcript: Some(t),
+                        ..
+                    }) => t.append(Entry::Output {
+                        text: String::from_utf8_lossy(&d).to_string(),
+                    })?,
+                    Some(s) => Self::write_to_file(&s.output, &d)?,
+                    None => {}
                 }
                 Self::print_flush(&String::from_utf8_lossy(&d).to_string())
             }
-            ParsedResponse::Prompt
+            // Errors are printed by `print_errors`
+            ParsedResponse::TspError(_)
+            | ParsedResponse::Prompt
             | ParsedResponse::PromptWithError
             | ParsedResponse::TspErrorStart
             | ParsedResponse::TspErrorEnd
@@ -721,14 +1836,15 @@ impl Repl {
         }
     }
 
-    fn update_node_config_json(file_path: &str, resp: &ParsedResponse) {
+    fn update_node_config_json(&self, file_path: &str, resp: &ParsedResponse) -> Result<()> {
         if let ParsedResponse::Data(d) = &resp {
             if let Err(e) =
                 Self::write_json_data(file_path.to_string(), String::from_utf8_lossy(d).as_ref())
             {
-                eprintln!("Unable to write configuration: {e}");
+                self.print_error_line(&format!("Unable to write configuration: {e}").red())?;
             }
         }
+        Ok(())
     }
 
     fn write_json_data(file_path: String, input_line: &str) -> Result<()> {
@@ -832,6 +1948,28 @@ impl Repl {
                         .required_unless_present("help")
                         .help("Path to the TSP script file to be sent to the instrument")
                 )
+                .arg(
+                    Arg::new("no-check")
+                        .long("no-check")
+                        .action(ArgAction::SetTrue)
+                        .help("Upload the script without checking its syntax first")
+                )
+                .arg(
+                    Arg::new("define")
+                        .short('D')
+                        .long("define")
+                        .value_name("NAME=VALUE")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_define)
+                        .help("Define NAME for the `--#define` and `--#if` directives of the script, overriding its definition in the script")
+                )
+                .arg(
+                    Arg::new("no-preprocess")
+                        .long("no-preprocess")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("define")
+                        .help("Upload the script as it is, without expanding its `--#` directives")
+                )
         )
         .subcommand(
             Command::new(".upgrade").about("Upgrade the firmware on the connected instrument")
@@ -862,6 +2000,9 @@ impl Repl {
                 .arg(
                     arg!(tsp: -t --tsp "Start the capturing the output of arbirary TSP commands in the terminal. To stop capturing output, use `.save --end`.")
                 )
+                .arg(
+                    arg!(transcript: -j --transcript "Start recording a transcript of the commands, errors and printed output as JSON lines, which `kic replay` can run against another instrument. To stop recording, use `.save --end`.")
+                )
                 .arg(
                     arg!(end: -e --end "End the capturing the output of arbirary TSP commands in the terminal.")
                 )
@@ -921,6 +2062,168 @@ impl Repl {
                     Arg::new("path").required_unless_present("help")
                 )
         )
+        .subcommand(
+            Command::new(".errors").about("Read the errors of the instrument, explain an error code or choose which errors are shown")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("action")
+                        .help("`show` reads the errors, `explain <CODE>` explains an error code and `filter <SEVERITY>` hides errors below `info`, `warning`, `error` or `fatal` severity")
+                        .value_parser(["show", "explain", "filter"])
+                        .default_value("show")
+                )
+                .arg(
+                    Arg::new("value")
+                        .help("The error code or severity for the action")
+                        .allow_negative_numbers(true)
+                )
+        )
+        .subcommand(
+            Command::new(".vars").about("List the global variables, functions, scripts and buffers of the instrument, or the entries of a table")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("path").help("The table to list, e.g. `results` or `results.runs[2]`")
+                )
+                .arg(
+                    Arg::new("all").short('a').long("all").help("Also list the globals that the instrument defines, e.g. `smu`").action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new(".plot").about("Plot the readings of a buffer or the numbers in a table, or sample an expression over time with `--live`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("target").required_unless_present("help").help("A buffer, e.g. `defbuffer1`, a table of numbers, or with `--live` an expression, e.g. `smu.measure.read()`")
+                )
+                .arg(
+                    Arg::new("live").short('l').long("live").help("Evaluate the target repeatedly and redraw the plot until Enter is pressed").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(-i --interval <MS> "The time between samples with `--live`, in milliseconds").value_parser(value_parser!(u64)).default_value("500")
+                )
+                .arg(
+                    arg!(-n --samples <COUNT> "The number of samples shown with `--live`").value_parser(value_parser!(usize)).default_value("60")
+                )
+                .arg(
+                    Arg::new("spark").short('s').long("spark").help("Draw a single-line sparkline instead of a chart").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(-u --unit <UNIT> "The unit of the values, e.g. `A`, if the instrument does not report it")
+                )
+        )
+        .subcommand(
+            Command::new(".watch").about("Evaluate an expression periodically while waiting for commands and show its value when it changes, list the watches, or stop them with `.watch off [number]`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("expression").num_args(1..).help("The expression, e.g. `smu.measure.read()`, or `off` followed by the number of a watch to stop it")
+                )
+                .arg(
+                    arg!(-e --every <INTERVAL> "The time between evaluations, e.g. `500ms` or `2s`").default_value("1s")
+                )
+                .arg(
+                    arg!(-l --log <FILE> "Append every value with a timestamp to a CSV file").value_parser(value_parser!(PathBuf))
+                )
+        )
+        .subcommand(
+            Command::new(".export").about("Write the TSP commands of this session that ran without errors to a script")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("file").required_unless_present("help").value_parser(value_parser!(PathBuf)).help("The script to write, e.g. `bench.tsp`")
+                )
+                .arg(
+                    arg!(-f --function <NAME> "Wrap the commands in a function with this name, which is called at the end of the script")
+                )
+        )
+        .subcommand(
+            Command::new(".macro").about("Define a command that expands to TSP or another command, with `$1` to `$9` replaced by its arguments, list the macros, or remove one with `.macro undef <name>`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("action").value_parser(["def", "undef", "list"]).help("Define a macro, remove one, or list them")
+                )
+                .arg(
+                    Arg::new("name").help("The name of the macro, which is invoked as `.<name>`")
+                )
+                .arg(
+                    Arg::new("body").num_args(1..).help("What the macro expands to, e.g. \"smu.source.level=$1\"")
+                )
+        )
+        .subcommand(
+            Command::new(".doc").about("Show the reference of a TSP command for the connected instrument, or search for commands with `?<query>`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("command").required_unless_present("help").help("The command, e.g. `smu.measure.nplc`, or `?` followed by part of a command to search for, e.g. `?nplc`")
+                )
+        )
+        .subcommand(
+            Command::new(".node").about("Route TSP commands and scripts to a TSP-Link™ node until `.node off`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("target").required_unless_present("help").help("The node number, or `off` to send commands to the local node")
+                )
+        )
+        .subcommand(
+            Command::new(".slot").about("Route TSP commands and scripts to a slot of the instrument until `.slot off`")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("target").required_unless_present("help").help("The slot number, or `off` to send commands to the mainframe")
+                )
+        )
+        .subcommand(
+            Command::new(".scripts").about("List and manage the scripts saved on the instrument")
+                .help_template(SUBCMD_TEMPLATE)
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("help").short('h').long("help").help("Print help").action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("action")
+                        .help("The action to perform")
+                        .value_parser(["list", "show", "pull", "delete", "autorun", "run"])
+                        .default_value("list")
+                )
+                .arg(
+                    Arg::new("args")
+                        .help("The script name(s) for the action, followed by `on` or `off` for `autorun`. `pull` retrieves all scripts if no names are given.")
+                        .num_args(0..)
+                )
+                .arg(
+                    arg!(-o --output <DIR> "The directory `pull` saves scripts to").value_parser(value_parser!(PathBuf)).default_value(".")
+                )
+        )
         .subcommand(
             Command::new(".reset")
                 .help_template(SUBCMD_TEMPLATE)
@@ -955,7 +2258,12 @@ impl Repl {
         let path = PathBuf::from(input.trim());
         if path.is_file() {
             trace!("Detected file path: {path:?}");
-            return Ok(Request::Script { file: path });
+            return Ok(Request::Script {
+                file: path,
+                check_syntax: true,
+                preprocess: true,
+                defines: BTreeMap::new(),
+            });
         }
 
         if !Self::starts_with_command(input) {
@@ -998,10 +2306,7 @@ impl Repl {
                 },
                 _ => {
                     let slot = flags.get_one::<usize>("slot").copied();
-                    Request::Info {
-                        //TODO
-                        slot,
-                    }
+                    Request::Info { slot }
                 }
             },
             Some((".save", flags)) => {
@@ -1028,6 +2333,7 @@ impl Repl {
                                 details: "`tsp` arg not found".to_string(),
                             });
                         };
+                        let record_transcript = flags.get_flag("transcript");
                         let Some(end) = flags.get_one::<bool>("end") else {
                             return Err(InstrumentReplError::CommandError {
                                 details: "`end` arg not found".to_string(),
@@ -1039,6 +2345,7 @@ impl Repl {
                             return Ok(Request::Save(Save {
                                 method: SaveMethod::End,
                                 output: "".into(),
+                                transcript: None,
                             }));
                         }
 
@@ -1072,15 +2379,21 @@ impl Repl {
                         let format = flags.get_one::<String>("format");
 
                         // ensure that only one .save method is being used
-                        if ([script.is_some(), *tsp, *end, buffers.is_some()])
-                            .iter()
-                            .filter(|s| **s)
-                            .count()
+                        if ([
+                            script.is_some(),
+                            *tsp,
+                            record_transcript,
+                            *end,
+                            buffers.is_some(),
+                        ])
+                        .iter()
+                        .filter(|s| **s)
+                        .count()
                             > 1
                         {
                             return Err(InstrumentReplError::CommandError {
                                 details:
-                                    "one of --script, --tsp, --end, --buffer may be used at once"
+                                    "one of --script, --tsp, --transcript, --end, --buffer may be used at once"
                                         .to_string(),
                             });
                         }
@@ -1089,6 +2402,8 @@ impl Repl {
                             SaveMethod::Script { file: s.clone() }
                         } else if *tsp {
                             SaveMethod::Start
+                        } else if record_transcript {
+                            SaveMethod::Transcript
                         } else if *end {
                             SaveMethod::End
                         } else if let Some(b) = buffers {
@@ -1108,13 +2423,14 @@ impl Repl {
                             }
                         } else {
                             return Err(InstrumentReplError::CommandError {
-                                details: "one of --script, --tsp, --end, --buffer must be used"
+                                details: "one of --script, --tsp, --transcript, --end, --buffer must be used"
                                     .to_string(),
                             });
                         };
                         Request::Save(Save {
                             method,
                             output: output.clone(),
+                            transcript: None,
                         })
                     }
                 }
@@ -1139,7 +2455,253 @@ impl Repl {
                             .to_string(),
                         ));
                     }
-                    Request::Script { file }
+                    Request::Script {
+                        file,
+                        check_syntax: !flags.get_flag("no-check"),
+                        preprocess: !flags.get_flag("no-preprocess"),
+                        defines: flags
+                            .get_many::<(String, String)>("define")
+                            .into_iter()
+                            .flatten()
+                            .cloned()
+                            .collect(),
+                    }
+                }
+            },
+            Some((".scripts", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".scripts".to_string()),
+                },
+                _ => {
+                    let action = flags
+                        .get_one::<String>("action")
+                        .map_or("list", String::as_str);
+                    let args: Vec<String> = flags
+                        .get_many::<String>("args")
+                        .into_iter()
+                        .flatten()
+                        .cloned()
+                        .collect();
+                    let name = args.first().cloned();
+                    let action = match (action, name) {
+                        ("list", _) => ScriptsAction::List,
+                        ("pull", _) => ScriptsAction::Pull {
+                            names: args,
+                            output: flags
+                                .get_one::<PathBuf>("output")
+                                .cloned()
+                                .unwrap_or_else(|| PathBuf::from(".")),
+                        },
+                        ("show", Some(name)) => ScriptsAction::Show { name },
+                        ("delete", Some(name)) => ScriptsAction::Delete { name },
+                        ("run", Some(name)) => ScriptsAction::Run { name },
+                        ("autorun", Some(name)) => match args.get(1).map(String::as_str) {
+                            Some("on") => ScriptsAction::Autorun { name, enable: true },
+                            Some("off") => ScriptsAction::Autorun {
+                                name,
+                                enable: false,
+                            },
+                            _ => {
+                                return Ok(Request::Usage(
+                                    "expected `on` or `off` after the script name".to_string(),
+                                ))
+                            }
+                        },
+                        (action, _) => {
+                            return Ok(Request::Usage(format!(
+                                "`.scripts {action}` requires a script name"
+                            )))
+                        }
+                    };
+                    Request::Scripts(action)
+                }
+            },
+            Some((".errors", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".errors".to_string()),
+                },
+                _ => {
+                    let value = flags.get_one::<String>("value");
+                    match (flags.get_one::<String>("action").map(String::as_str), value) {
+                        (Some("explain"), Some(code)) => match code.parse::<i64>() {
+                            Ok(code) => Request::Errors(ErrorsAction::Explain { code }),
+                            Err(_) => {
+                                return Ok(Request::Usage(format!(
+                                    "expected an error code, found \"{code}\""
+                                )))
+                            }
+                        },
+                        (Some("filter"), Some(severity)) => match severity.parse() {
+                            Ok(severity) => Request::Errors(ErrorsAction::Filter { severity }),
+                            Err(e) => return Ok(Request::Usage(e)),
+                        },
+                        (Some(action @ ("explain" | "filter")), None) => {
+                            return Ok(Request::Usage(format!(
+                                "`.errors {action}` requires a value"
+                            )))
+                        }
+                        _ => Request::GetError,
+                    }
+                }
+            },
+            Some((".vars", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".vars".to_string()),
+                },
+                _ => Request::Vars {
+                    path: flags.get_one::<String>("path").cloned(),
+                    all: flags.get_flag("all"),
+                },
+            },
+            Some((".plot", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".plot".to_string()),
+                },
+                _ => match flags.get_one::<String>("target") {
+                    Some(target) => Request::Plot {
+                        target: target.clone(),
+                        live: flags.get_flag("live"),
+                        interval: Duration::from_millis(
+                            flags.get_one::<u64>("interval").copied().unwrap_or(500),
+                        ),
+                        samples: flags
+                            .get_one::<usize>("samples")
+                            .copied()
+                            .unwrap_or(60)
+                            .max(2),
+                        spark: flags.get_flag("spark"),
+                        unit: flags.get_one::<String>("unit").cloned(),
+                    },
+                    None => return Ok(Request::Usage("`.plot` requires a target".to_string())),
+                },
+            },
+            Some((".watch", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".watch".to_string()),
+                },
+                _ => {
+                    let words: Vec<String> = flags
+                        .get_many::<String>("expression")
+                        .map_or_else(Vec::new, |w| w.cloned().collect());
+                    match words.first().map(String::as_str) {
+                        None => Request::Watch(WatchAction::List),
+                        Some("off") => match words.get(1).map(|n| n.parse::<usize>()) {
+                            None => Request::Watch(WatchAction::Remove { number: None }),
+                            Some(Ok(number)) if number > 0 => Request::Watch(WatchAction::Remove {
+                                number: Some(number),
+                            }),
+                            Some(_) => {
+                                return Ok(Request::Usage(
+                                    "expected the number of a watch, as listed by `.watch`"
+                                        .to_string(),
+                                ))
+                            }
+                        },
+                        Some(_) => {
+                            let every = flags
+                                .get_one::<String>("every")
+                                .map_or("1s", String::as_str);
+                            match parse_interval(every) {
+                                Ok(every) => Request::Watch(WatchAction::Add {
+                                    expression: words.join(" "),
+                                    every,
+                                    log: flags.get_one::<PathBuf>("log").cloned(),
+                                }),
+                                Err(e) => return Ok(Request::Usage(e.to_string())),
+                            }
+                        }
+                    }
+                }
+            },
+            Some((".export", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".export".to_string()),
+                },
+                _ => {
+                    let Some(file) = flags.get_one::<PathBuf>("file").cloned() else {
+                        return Err(InstrumentReplError::CommandError {
+                            details: "expected file path, but none were provided".to_string(),
+                        });
+                    };
+                    if file.is_dir() {
+                        return Ok(Request::Usage(format!(
+                            "the output path cannot be a directory \"{}\"",
+                            file.display()
+                        )));
+                    }
+                    let function = flags.get_one::<String>("function").cloned();
+                    if let Some(Err(e)) = function.as_deref().map(export::validate_function_name) {
+                        return Ok(Request::Usage(e));
+                    }
+                    Request::Export { file, function }
+                }
+            },
+            Some((".macro", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".macro".to_string()),
+                },
+                _ => {
+                    let name = flags.get_one::<String>("name").cloned();
+                    let body: Vec<String> = flags
+                        .get_many::<String>("body")
+                        .map_or_else(Vec::new, |b| b.cloned().collect());
+                    match (flags.get_one::<String>("action").map(String::as_str), name) {
+                        (None | Some("list"), None) => Request::Macro(MacroAction::List),
+                        (Some("undef"), Some(name)) if body.is_empty() => {
+                            Request::Macro(MacroAction::Remove { name })
+                        }
+                        (Some("def"), Some(name)) if !body.is_empty() => {
+                            Request::Macro(MacroAction::Define {
+                                name,
+                                body: body.join(" "),
+                            })
+                        }
+                        _ => {
+                            return Ok(Request::Usage(
+                                "expected `.macro def <name> <body>`, `.macro undef <name>` or `.macro list`"
+                                    .to_string(),
+                            ))
+                        }
+                    }
+                }
+            },
+            Some((".doc", flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(".doc".to_string()),
+                },
+                _ => match flags.get_one::<String>("command") {
+                    Some(query) if query.starts_with('?') => Request::Doc(DocAction::Search {
+                        query: query.trim_start_matches('?').to_string(),
+                    }),
+                    Some(command) => Request::Doc(DocAction::Show {
+                        command: command.clone(),
+                    }),
+                    None => return Ok(Request::Usage("`.doc` requires a command".to_string())),
+                },
+            },
+            Some((name @ (".node" | ".slot"), flags)) => match flags.get_one::<bool>("help") {
+                Some(help) if *help => Request::Help {
+                    sub_cmd: Some(name.to_string()),
+                },
+                _ => {
+                    let target = flags
+                        .get_one::<String>("target")
+                        .map_or("off", String::as_str);
+                    if target == "off" {
+                        Request::Route(None)
+                    } else {
+                        match target.parse::<u16>() {
+                            Ok(n) if n > 0 && name == ".node" => {
+                                Request::Route(Some(Target::Node(n)))
+                            }
+                            Ok(n) if n > 0 => Request::Route(Some(Target::Slot(n))),
+                            _ => {
+                                return Ok(Request::Usage(format!(
+                                    "expected a number greater than 0 or `off`, found \"{target}\""
+                                )))
+                            }
+                        }
+                    }
                 }
             },
             Some((".reset", flags)) => match flags.get_one::<bool>("help") {
@@ -1214,6 +2776,25 @@ impl Repl {
 
         false
     }
+    /// Expand `input` if it invokes a macro and convert it to the proper request.
+    fn expand_and_parse(input: &str, macros: &Macros) -> Result<UserInput> {
+        let line = match macros.expand(input) {
+            Ok(Some(expanded)) => {
+                debug!("Expanded macro to: {expanded}");
+                expanded
+            }
+            Ok(None) => input.trim().to_string(),
+            Err(e) => {
+                return Ok(UserInput {
+                    line: input.trim().to_string(),
+                    request: Request::Usage(e.to_string()),
+                })
+            }
+        };
+        let request = Self::parse_user_commands(&line)?;
+        Ok(UserInput { line, request })
+    }
+
     /// Start a thread that blocks on user input lines, converts them to the proper request
     /// and `send()`s them on the `out` channel.
     ///
@@ -1223,7 +2804,7 @@ impl Repl {
     /// # Errors
     /// This function can error if the thread couldn't be created.
     #[instrument]
-    fn init_user_input(out: Sender<Request>) -> Result<JoinHandle<Result<()>>> {
+    fn init_user_input(out: Sender<UserInput>, macros: Macros) -> Result<JoinHandle<Result<()>>> {
         let jh = std::thread::Builder::new()
             .name("user_input".to_string())
             .spawn(
@@ -1237,7 +2818,7 @@ impl Repl {
                         //       a message quickly enough.
                         let mut input = String::new();
                         let _ = std::io::stdin().read_line(&mut input)?;
-                        let req = match Self::parse_user_commands(&input) {
+                        let req = match Self::expand_and_parse(&input, &macros) {
                             Ok(r) => r,
                             Err(e) => {
                                 error!("Parse Error: {e}");
@@ -1250,7 +2831,7 @@ impl Repl {
                         }
                         // This `if` statement seeks to fix the NOTE above about not exiting.
                         // It feels a little awkward, but should be effective.
-                        if req == Request::Exit {
+                        if req.request == Request::Exit {
                             break 'input_loop;
                         }
                     }
@@ -1260,6 +2841,40 @@ impl Repl {
             )?;
         Ok(jh)
     }
+    /// Read the commands of the user from the input line of the full-screen interface.
+    /// Ctrl+C is handled like the signal, aborting the running command or exiting if it
+    /// is pressed twice, and Ctrl+D exits.
+    fn init_tui_input(
+        out: Sender<UserInput>,
+        tui: Tui,
+        macros: Macros,
+    ) -> Result<JoinHandle<Result<()>>> {
+        let jh = std::thread::Builder::new()
+            .name("user_input".to_string())
+            .spawn(move || {
+                info!("Starting full-screen user input loop");
+                loop {
+                    let req = match tui.read_input()? {
+                        Input::Line(line) => Self::expand_and_parse(&line, &macros)?,
+                        Input::Interrupt => {
+                            INTERRUPTED.store(true, Ordering::SeqCst);
+                            continue;
+                        }
+                        Input::Close => UserInput {
+                            line: String::new(),
+                            request: Request::Exit,
+                        },
+                    };
+                    if out.send(req.clone()).is_err() || req.request == Request::Exit {
+                        break;
+                    }
+                }
+                info!("Closing full-screen user input loop");
+                Ok(())
+            })?;
+        Ok(jh)
+    }
+
     #[allow(clippy::too_many_lines)]
     const fn state_action(prev_state: Option<ReadState>, state: Option<ReadState>) -> Action {
         match (prev_state, state) {
@@ -1,7 +1,10 @@
-use std::fmt::Display;
+use std::fmt::{Display, Write};
+
+use kic_lib::model::{registry, Family};
 
 use crate::VERSION;
 const VERSION_REPLACE: &str = "!<!<VERSION>!>!";
+const MODELS_REPLACE: &str = "!<!<MODELS>!>!";
 
 pub const KIC_COMMON_TSP: Resource = Resource {
     source: include_str!("./kic_common.tsp"),
@@ -20,14 +23,38 @@ pub struct Resource {
 
 impl Display for Resource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let source = self.source.replace(VERSION_REPLACE, VERSION);
+        let source = self
+            .source
+            .replace(VERSION_REPLACE, VERSION)
+            .replace(MODELS_REPLACE, &models_table());
         write!(f, "{source}")
     }
 }
 
+/// The entries of the `models` table in `kic_common.tsp`, generated from the model
+/// registry.
+fn models_table() -> String {
+    let mut table = String::new();
+    for model in registry().models() {
+        let family = match model.family {
+            Family::_26xx => "_2600",
+            Family::_3700 => "_3700",
+            Family::Tti => "TTI",
+            Family::ModularPlatform => "MP5000",
+        };
+        for name in std::iter::once(&model.name).chain(&model.aliases) {
+            let _ = writeln!(table, "    [\"{name}\"] = {family},");
+        }
+    }
+    table.trim_end().to_string()
+}
+
 #[cfg(test)]
 mod unit {
-    use crate::{resources::Resource, VERSION};
+    use crate::{
+        resources::{Resource, KIC_COMMON_TSP},
+        VERSION,
+    };
 
     #[test]
     fn replace_version() {
@@ -39,4 +66,14 @@ mod unit {
 
         assert_eq!(TEST_FILE.to_string(), expected);
     }
+
+    #[test]
+    fn models_from_registry() {
+        let common = KIC_COMMON_TSP.to_string();
+        assert!(!common.contains("!<!<MODELS>!>!"));
+        assert!(common.contains("    [\"2450\"] = TTI,\n"));
+        assert!(common.contains("    [\"2601\"] = _2600,\n"));
+        assert!(common.contains("    [\"5880_SRU\"] = _3700,\n"));
+        assert!(common.contains("    [\"TSP\"] = MP5000,\n"));
+    }
 }
@@ -0,0 +1,338 @@
+//! Machine-readable transcripts of REPL sessions, written by `.save --transcript` with
+//! one JSON entry per line, and the comparison of a transcript with a replay of its
+//! commands on another instrument.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{InstrumentReplError, Result},
+    TspError,
+};
+
+/// The line that ends the output of a replayed command.
+pub const REPLAY_END: &str = "REPLAY>END";
+
+/// Something that happened in a REPL session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// TSP that was sent to the instrument
+    Command { tsp: String },
+    /// A REPL command, e.g. `.errors`, which is not replayed
+    DotCommand {
+        command: String,
+        /// Whether the command ran TSP that is not in the transcript, e.g. to load a
+        /// script, in which case the transcript can't be replayed
+        #[serde(default)]
+        runs_tsp: bool,
+    },
+    /// Output that the instrument printed
+    Output { text: String },
+    /// An error that the instrument reported
+    Error {
+        code: i64,
+        message: String,
+        node: i16,
+        severity: String,
+    },
+}
+
+impl From<&TspError> for Entry {
+    fn from(e: &TspError) -> Self {
+        Self::Error {
+            code: e.code(),
+            message: e.message().to_string(),
+            node: e.node_id(),
+            severity: e.severity().to_string(),
+        }
+    }
+}
+
+/// An [`Entry`] and when it happened, which is a line of a transcript.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// The time in RFC 3339 format with milliseconds
+    pub time: String,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// A transcript that is being recorded. Clones append to the same file, which stays
+/// open until the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    file: Arc<Mutex<File>>,
+}
+
+impl Transcript {
+    /// Open the transcript at `path`, appending to it if it exists.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be opened.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().append(true).create(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Append `entry` to the transcript, timestamped with the current time.
+    ///
+    /// # Errors
+    /// Returns an error if the transcript could not be written.
+    pub fn append(&self, entry: Entry) -> Result<()> {
+        let record = Record {
+            time: Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            entry,
+        };
+        let line = serde_json::to_string(&record)
+            .map_err(|e| InstrumentReplError::Other(format!("unable to write transcript: {e}")))?;
+        writeln!(
+            self.file.lock().unwrap_or_else(PoisonError::into_inner),
+            "{line}"
+        )?;
+        Ok(())
+    }
+}
+
+impl PartialEq for Transcript {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file)
+    }
+}
+
+impl Eq for Transcript {}
+
+/// Read the transcript at `path`.
+///
+/// # Errors
+/// Returns an error if the file could not be read or a line is not an entry.
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let contents = std::fs::read_to_string(path)?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            serde_json::from_str(l).map_err(|e| {
+                InstrumentReplError::Other(format!(
+                    "{}:{}: not a transcript entry: {e}",
+                    path.display(),
+                    i.saturating_add(1)
+                ))
+            })
+        })
+        .collect()
+}
+
+/// A command of a transcript and what the instrument did in response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Step {
+    /// The TSP that was sent
+    pub tsp: String,
+    /// Everything the instrument printed
+    pub output: String,
+    /// The codes of the errors the instrument reported
+    pub errors: Vec<i64>,
+}
+
+/// Group the entries of a transcript into the commands to replay. Output and errors
+/// belong to the command before them, unless a REPL command is in between.
+///
+/// # Errors
+/// Returns an error if a REPL command ran TSP that is not in the transcript, since the
+/// commands after it may depend on it.
+pub fn steps(records: &[Record]) -> Result<Vec<Step>> {
+    let mut steps: Vec<Step> = Vec::new();
+    let mut current: Option<Step> = None;
+    for record in records {
+        match &record.entry {
+            Entry::Command { tsp } => {
+                steps.extend(current.replace(Step {
+                    tsp: tsp.clone(),
+                    ..Step::default()
+                }));
+            }
+            Entry::DotCommand {
+                command,
+                runs_tsp: true,
+            } => {
+                return Err(InstrumentReplError::Other(format!(
+                    "`{command}` (at {}) ran TSP that is not in the transcript, so the transcript can't be replayed",
+                    record.time
+                )));
+            }
+            Entry::DotCommand { .. } => steps.extend(current.take()),
+            Entry::Output { text } => {
+                if let Some(step) = current.as_mut() {
+                    step.output.push_str(text);
+                }
+            }
+            Entry::Error { code, .. } => {
+                if let Some(step) = current.as_mut() {
+                    step.errors.push(*code);
+                }
+            }
+        }
+    }
+    steps.extend(current);
+    Ok(steps)
+}
+
+/// Normalize output for comparison, ignoring line endings and the whitespace around it.
+fn normalize(output: &str) -> Vec<&str> {
+    output.trim().lines().map(str::trim_end).collect()
+}
+
+/// How a replayed command differs from the transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// The number of the command in the transcript, starting at 1
+    pub number: usize,
+    /// The command
+    pub tsp: String,
+    /// The lines of output that differ, as `-` recorded and `+` replayed lines
+    pub output: Vec<String>,
+    /// The recorded and replayed error codes, if they differ
+    pub errors: Option<(Vec<i64>, Vec<i64>)>,
+}
+
+impl Step {
+    /// Compare the step that was replayed with the recorded one, returning `None` if
+    /// the output and errors match.
+    #[must_use]
+    pub fn compare(&self, number: usize, replayed: &Self) -> Option<Difference> {
+        let recorded_lines = normalize(&self.output);
+        let replayed_lines = normalize(&replayed.output);
+        let mut output = Vec::new();
+        for i in 0..recorded_lines.len().max(replayed_lines.len()) {
+            let (recorded, replayed) = (recorded_lines.get(i), replayed_lines.get(i));
+            if recorded != replayed {
+                output.extend(recorded.map(|l| format!("-{l}")));
+                output.extend(replayed.map(|l| format!("+{l}")));
+            }
+        }
+        let errors = (self.errors != replayed.errors)
+            .then(|| (self.errors.clone(), replayed.errors.clone()));
+        if output.is_empty() && errors.is_none() {
+            return None;
+        }
+        Some(Difference {
+            number,
+            tsp: self.tsp.clone(),
+            output,
+            errors,
+        })
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Command {}: {}", self.number, self.tsp)?;
+        for line in &self.output {
+            writeln!(f, "  {line}")?;
+        }
+        if let Some((recorded, replayed)) = &self.errors {
+            let codes = |c: &[i64]| {
+                c.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            writeln!(
+                f,
+                "  errors: recorded [{}], replayed [{}]",
+                codes(recorded),
+                codes(replayed)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{steps, Entry, Record, Step};
+
+    #[test]
+    fn entries() {
+        let lines = [
+            r#"{"time":"2024-05-01T10:00:00.000+02:00","type":"command","tsp":"print(1)"}"#,
+            r#"{"time":"2024-05-01T10:00:00.100+02:00","type":"output","text":"1\n"}"#,
+            r#"{"time":"2024-05-01T10:00:01.000+02:00","type":"dot_command","command":".errors"}"#,
+            r#"{"time":"2024-05-01T10:00:01.100+02:00","type":"output","text":"ignored\n"}"#,
+            r#"{"time":"2024-05-01T10:00:02.000+02:00","type":"command","tsp":"x()"}"#,
+            r#"{"time":"2024-05-01T10:00:02.100+02:00","type":"error","code":-285,"message":"attempt to call a nil value","node":1,"severity":"error"}"#,
+        ];
+        let records: Vec<Record> = lines
+            .iter()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            records[0].entry,
+            Entry::Command {
+                tsp: "print(1)".to_string()
+            }
+        );
+        assert_eq!(serde_json::to_string(&records[5]).unwrap(), lines[5]);
+
+        let replayed = steps(&records).unwrap();
+        assert_eq!(
+            replayed,
+            vec![
+                Step {
+                    tsp: "print(1)".to_string(),
+                    output: "1\n".to_string(),
+                    errors: vec![]
+                },
+                Step {
+                    tsp: "x()".to_string(),
+                    output: String::new(),
+                    errors: vec![-285]
+                },
+            ]
+        );
+
+        let script = serde_json::from_str(
+            r#"{"time":"2024-05-01T10:00:03.000+02:00","type":"dot_command","command":".script a.tsp","runs_tsp":true}"#,
+        )
+        .unwrap();
+        let records = [records, vec![script]].concat();
+        assert!(steps(&records).is_err());
+    }
+
+    #[test]
+    fn compare() {
+        let recorded = Step {
+            tsp: "print(1) print(2)".to_string(),
+            output: "1\n2\n".to_string(),
+            errors: vec![],
+        };
+        let same = Step {
+            output: "1\r\n2  \n\n".to_string(),
+            ..recorded.clone()
+        };
+        assert_eq!(recorded.compare(1, &same), None);
+
+        let different = Step {
+            output: "1\n3\n".to_string(),
+            errors: vec![-285],
+            ..recorded.clone()
+        };
+        let difference = recorded.compare(2, &different).unwrap();
+        assert_eq!(difference.output, vec!["-2", "+3"]);
+        assert_eq!(
+            difference.to_string(),
+            "Command 2: print(1) print(2)\n  -2\n  +3\n  errors: recorded [], replayed [-285]\n"
+        );
+    }
+}
@@ -6,6 +6,71 @@ pub struct InstrumentTime {
     nanos: u64,
 }
 
+impl Display for InstrumentTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = i64::try_from(self.secs)
+            .ok()
+            .zip(u32::try_from(self.nanos).ok())
+            .and_then(|(secs, nanos)| chrono::DateTime::from_timestamp(secs, nanos));
+        match time {
+            Some(t) => write!(f, "{}", t.format("%Y-%m-%d %H:%M:%S%.3f")),
+            None => write!(f, "{}.{:09}", self.secs, self.nanos),
+        }
+    }
+}
+
+/// How serious a [`TspError`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    /// Convert the severity reported by an instrument. TTI instruments report the
+    /// `eventlog.SEV_*` flags, the other instruments report 0 or 10 (informational),
+    /// 20 (recoverable), 30 (serious) or 40 (fatal).
+    #[must_use]
+    pub const fn from_instrument(severity: u8) -> Self {
+        match severity {
+            2 => Self::Warning,
+            0 | 4 | 10..=19 => Self::Info,
+            40.. => Self::Fatal,
+            _ => Self::Error,
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "info" => Ok(Self::Info),
+            "warning" | "warn" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            "fatal" => Ok(Self::Fatal),
+            _ => Err(format!(
+                "\"{s}\" is not a severity, expected info, warning, error or fatal"
+            )),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Fatal => "fatal",
+        };
+        write!(f, "{s}")
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct TspError {
     error_code: i64,
@@ -15,16 +80,67 @@ pub struct TspError {
     time: Option<InstrumentTime>,
 }
 
+impl TspError {
+    /// The error code
+    #[must_use]
+    pub const fn code(&self) -> i64 {
+        self.error_code
+    }
+
+    /// The message of the error
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// How serious the error is
+    #[must_use]
+    pub const fn severity(&self) -> Severity {
+        Severity::from_instrument(self.severity)
+    }
+
+    /// The TSP-Link node that reported the error
+    #[must_use]
+    pub const fn node_id(&self) -> i16 {
+        self.node_id
+    }
+
+    /// When the error occurred, if the instrument reports it
+    #[must_use]
+    pub const fn time(&self) -> Option<&InstrumentTime> {
+        self.time.as_ref()
+    }
+}
+
 impl Display for TspError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let id = self.error_code;
         let msg = &self.message;
-        //let _sev = self.severity;
         let node = self.node_id;
-        // let _time: String = match self.time {
-        //     Some(InstrumentTime::Tti { secs, nanos }) => format!("{secs}.{nanos:09}"),
-        //     None => String::new(),
-        // };
         write!(f, "[{node}] {{{id}}} {msg}")
     }
 }
+
+#[cfg(test)]
+mod unit {
+    use super::{Severity, TspError};
+
+    #[test]
+    fn severity_and_time() {
+        let e: TspError = serde_json::from_str(
+            r#"{"error_code": -285, "message": "TSP syntax error", "severity": 20, "node_id": 1, "time": {"secs": 1700000000, "nanos": 5000000}}"#,
+        )
+        .unwrap();
+        assert_eq!(e.severity(), Severity::Error);
+        assert_eq!(e.to_string(), "[1] {-285} TSP syntax error");
+        assert_eq!(
+            e.time().map(ToString::to_string).as_deref(),
+            Some("2023-11-14 22:13:20.005")
+        );
+        assert_eq!(Severity::from_instrument(2), Severity::Warning);
+        assert_eq!(Severity::from_instrument(0), Severity::Info);
+        assert_eq!(Severity::from_instrument(40), Severity::Fatal);
+        assert_eq!("warn".parse::<Severity>(), Ok(Severity::Warning));
+        assert!(Severity::Warning < Severity::Error);
+    }
+}
@@ -0,0 +1,413 @@
+//! A full-screen terminal interface for the REPL.
+//!
+//! The instrument output scrolls between a status bar at the top and a panel of the
+//! errors reported by the instrument, and commands are entered on the bottom line.
+
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Write as _},
+    io::{self, Write},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use colored::Colorize;
+use console::{measure_text_width, truncate_str, Key, Term};
+
+/// The most rows the error panel takes up.
+const ERROR_ROWS: usize = 6;
+
+/// The number of errors that are kept for the error panel.
+const ERROR_HISTORY: usize = 100;
+
+/// The state of the instrument that is shown in the status bar.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// The model of the instrument
+    pub model: String,
+    /// The serial number of the instrument
+    pub serial: String,
+    /// The login state of the instrument, e.g. `logged in`
+    pub login: String,
+    /// The nodes on the TSP-Link network, empty if the network is offline
+    pub nodes: Vec<u16>,
+    /// The file that `.save` is writing to, if saving is active
+    pub save: Option<String>,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} #{} │ login: {} │ ",
+            self.model, self.serial, self.login
+        )?;
+        if self.nodes.is_empty() {
+            write!(f, "TSP-Link: offline")?;
+        } else {
+            let nodes: Vec<_> = self.nodes.iter().map(ToString::to_string).collect();
+            write!(f, "TSP-Link: nodes {}", nodes.join(", "))?;
+        }
+        if let Some(save) = &self.save {
+            write!(f, " │ ● saving to {save}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What the user entered on the input line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// A line that was entered with Enter
+    Line(String),
+    /// Ctrl+C was pressed
+    Interrupt,
+    /// Ctrl+D was pressed on an empty line
+    Close,
+}
+
+#[derive(Debug, Default)]
+struct Screen {
+    status: Status,
+    errors: VecDeque<String>,
+    prompt: String,
+    ready: bool,
+    input: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// The entry of `history` on the input line, `history.len()` for a new line
+    recalled: usize,
+    rows: usize,
+    cols: usize,
+}
+
+impl Screen {
+    fn error_rows(&self) -> usize {
+        ERROR_ROWS.min(self.rows.saturating_sub(6) / 2)
+    }
+
+    /// The last row of the output pane, which starts on the second row.
+    fn output_bottom(&self) -> usize {
+        self.rows
+            .saturating_sub(self.error_rows())
+            .saturating_sub(2)
+            .max(2)
+    }
+
+    /// Draw `text` on `row` without moving the cursor out of the output pane.
+    fn line(&self, row: usize, text: &str) -> String {
+        format!(
+            "\x1b7\x1b[{row};1H\x1b[2K{}\x1b8",
+            truncate_str(text, self.cols, "…")
+        )
+    }
+
+    fn draw_status(&self) -> String {
+        let status = format!(" {}", self.status);
+        let padding = self.cols.saturating_sub(measure_text_width(&status));
+        self.line(
+            1,
+            &format!("{status}{}", " ".repeat(padding))
+                .reversed()
+                .to_string(),
+        )
+    }
+
+    fn draw_errors(&self) -> String {
+        let rows = self.error_rows();
+        let separator_row = self.output_bottom().saturating_add(1);
+        let title = if self.errors.is_empty() {
+            "─ Errors ".to_string()
+        } else {
+            format!("─ Errors ({}) ", self.errors.len())
+        };
+        let rule = "─".repeat(self.cols.saturating_sub(measure_text_width(&title)));
+        let mut drawn = self.line(
+            separator_row,
+            &format!("{title}{rule}").dimmed().to_string(),
+        );
+        let shown = self
+            .errors
+            .iter()
+            .skip(self.errors.len().saturating_sub(rows));
+        for i in 0..rows {
+            let row = separator_row.saturating_add(1).saturating_add(i);
+            drawn.push_str(&self.line(row, shown.clone().nth(i).map_or("", String::as_str)));
+        }
+        drawn
+    }
+
+    /// Draw everything but the output pane.
+    fn draw_frame(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.draw_status(),
+            self.draw_errors(),
+            self.draw_input()
+        )
+    }
+
+    fn draw_input(&self) -> String {
+        let prompt = self.prompt.trim_start();
+        let prompt = if self.ready {
+            prompt.blue().to_string()
+        } else {
+            prompt.dimmed().to_string()
+        };
+        // Scroll the input so that the cursor is visible.
+        let width = self
+            .cols
+            .saturating_sub(measure_text_width(&prompt))
+            .saturating_sub(1)
+            .max(1);
+        let start = self.cursor.saturating_sub(width.saturating_sub(1));
+        let mut text = String::new();
+        for (i, c) in self.input.iter().enumerate().skip(start).take(width) {
+            if i == self.cursor {
+                text.push_str(&c.to_string().reversed().to_string());
+            } else {
+                text.push(*c);
+            }
+        }
+        if self.cursor >= self.input.len() {
+            text.push_str(&" ".reversed().to_string());
+        }
+        self.line(self.rows, &format!("{prompt}{text}"))
+    }
+}
+
+/// Write `text` to stdout at once so that it is not interleaved with other output.
+fn write(text: &str) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    out.write_all(text.as_bytes())?;
+    out.flush()
+}
+
+/// A full-screen terminal interface. Clones draw to the same screen, so the input can
+/// be read on another thread than the one that prints the output.
+#[derive(Debug, Clone)]
+pub struct Tui {
+    screen: Arc<Mutex<Screen>>,
+}
+
+impl Tui {
+    /// Create the interface, showing `status` in the status bar.
+    #[must_use]
+    pub fn new(status: Status) -> Self {
+        Self {
+            screen: Arc::new(Mutex::new(Screen {
+                status,
+                prompt: "TSP> ".to_string(),
+                ..Screen::default()
+            })),
+        }
+    }
+
+    fn screen(&self) -> MutexGuard<'_, Screen> {
+        self.screen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Switch to the full-screen interface. Everything that is printed afterwards
+    /// scrolls in the output pane until [`Tui::leave`] is called.
+    ///
+    /// # Errors
+    /// Returns an error if stdout is not a terminal or could not be written.
+    pub fn enter(&self) -> io::Result<()> {
+        let term = Term::stdout();
+        if !term.is_term() {
+            return Err(io::Error::other(
+                "the full-screen interface requires a terminal",
+            ));
+        }
+        let (rows, cols) = term.size();
+        let mut screen = self.screen();
+        screen.rows = usize::from(rows);
+        screen.cols = usize::from(cols);
+        let drawn = format!(
+            "\x1b[?1049h\x1b[2J\x1b[?25l\x1b[2;{}r\x1b[2;1H{}",
+            screen.output_bottom(),
+            screen.draw_frame(),
+        );
+        drop(screen);
+        write(&drawn)
+    }
+
+    /// Lay the interface out again if the terminal was resized since it was drawn. The
+    /// output continues at the bottom of the resized output pane.
+    ///
+    /// # Errors
+    /// Returns an error if stdout could not be written.
+    pub fn fit_to_terminal(&self) -> io::Result<()> {
+        let (rows, cols) = Term::stdout().size();
+        let (rows, cols) = (usize::from(rows), usize::from(cols));
+        let mut screen = self.screen();
+        if screen.rows == 0 || (screen.rows, screen.cols) == (rows, cols) {
+            return Ok(());
+        }
+        // Clear the old error panel and input line, which may now be in the output pane.
+        let mut drawn = String::new();
+        for row in screen.output_bottom().saturating_add(1)..=rows {
+            let _ = write!(drawn, "\x1b[{row};1H\x1b[2K");
+        }
+        screen.rows = rows;
+        screen.cols = cols;
+        let bottom = screen.output_bottom();
+        let _ = write!(
+            drawn,
+            "\x1b[2;{bottom}r\x1b[{bottom};1H{}",
+            screen.draw_frame()
+        );
+        drop(screen);
+        write(&drawn)
+    }
+
+    /// Leave the full-screen interface and restore the terminal.
+    ///
+    /// # Errors
+    /// Returns an error if stdout could not be written.
+    pub fn leave(&self) -> io::Result<()> {
+        write("\x1b[r\x1b[?25h\x1b[?1049l")
+    }
+
+    /// Change the status bar.
+    ///
+    /// # Errors
+    /// Returns an error if stdout could not be written.
+    pub fn update_status(&self, update: impl FnOnce(&mut Status)) -> io::Result<()> {
+        let mut screen = self.screen();
+        let before = screen.status.clone();
+        update(&mut screen.status);
+        if screen.status == before {
+            return Ok(());
+        }
+        write(&screen.draw_status())
+    }
+
+    /// Add an error to the error panel.
+    ///
+    /// # Errors
+    /// Returns an error if stdout could not be written.
+    pub fn push_error(&self, error: &str) -> io::Result<()> {
+        let mut screen = self.screen();
+        // Blank lines that separate messages in the output pane are left out.
+        for line in error.lines().filter(|l| measure_text_width(l) > 0) {
+            if screen.errors.len() >= ERROR_HISTORY {
+                screen.errors.pop_front();
+            }
+            screen.errors.push_back(line.to_string());
+        }
+        write(&screen.draw_errors())
+    }
+
+    /// Show `prompt` on the input line, indicating that the next command can be
+    /// entered.
+    ///
+    /// # Errors
+    /// Returns an error if stdout could not be written.
+    pub fn set_prompt(&self, prompt: &str) -> io::Result<()> {
+        let mut screen = self.screen();
+        prompt.trim_start().clone_into(&mut screen.prompt);
+        screen.ready = true;
+        write(&screen.draw_input())
+    }
+
+    /// Edit the input line until Enter, Ctrl+C or Ctrl+D on an empty line is pressed.
+    /// The up and down arrows recall previous lines. An entered line is echoed to the
+    /// output pane after the prompt.
+    ///
+    /// # Errors
+    /// Returns an error if the keyboard could not be read or stdout could not be
+    /// written.
+    pub fn read_input(&self) -> io::Result<Input> {
+        let term = Term::stdout();
+        loop {
+            let key = term.read_key_raw()?;
+            let mut screen = self.screen();
+            let cursor = screen.cursor;
+            match key {
+                Key::Enter => {
+                    let line: String = screen.input.drain(..).collect();
+                    screen.cursor = 0;
+                    if !line.trim().is_empty() && screen.history.last() != Some(&line) {
+                        screen.history.push(line.clone());
+                    }
+                    screen.recalled = screen.history.len();
+                    screen.ready = false;
+                    let echo = format!("{}{line}\n", screen.prompt.blue());
+                    write(&format!("{echo}{}", screen.draw_input()))?;
+                    return Ok(Input::Line(line));
+                }
+                Key::CtrlC => return Ok(Input::Interrupt),
+                Key::Char('\u{4}') if screen.input.is_empty() => return Ok(Input::Close),
+                Key::Char(c) if !c.is_control() => {
+                    screen.input.insert(cursor, c);
+                    screen.cursor = cursor.saturating_add(1);
+                }
+                Key::Backspace if cursor > 0 => {
+                    screen.cursor = cursor.saturating_sub(1);
+                    let cursor = screen.cursor;
+                    screen.input.remove(cursor);
+                }
+                Key::Del if cursor < screen.input.len() => {
+                    screen.input.remove(cursor);
+                }
+                Key::ArrowLeft => screen.cursor = cursor.saturating_sub(1),
+                Key::ArrowRight => screen.cursor = cursor.saturating_add(1).min(screen.input.len()),
+                Key::Home => screen.cursor = 0,
+                Key::End => screen.cursor = screen.input.len(),
+                Key::ArrowUp | Key::ArrowDown => {
+                    let recalled = if key == Key::ArrowUp {
+                        screen.recalled.saturating_sub(1)
+                    } else {
+                        screen.recalled.saturating_add(1).min(screen.history.len())
+                    };
+                    screen.recalled = recalled;
+                    screen.input = screen
+                        .history
+                        .get(recalled)
+                        .map_or_else(Vec::new, |l| l.chars().collect());
+                    screen.cursor = screen.input.len();
+                }
+                _ => continue,
+            }
+            write(&screen.draw_input())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{Screen, Status};
+
+    #[test]
+    fn layout() {
+        let mut status = Status {
+            model: "2450".to_string(),
+            serial: "01234567".to_string(),
+            login: "not required".to_string(),
+            ..Status::default()
+        };
+        assert_eq!(
+            status.to_string(),
+            "2450 #01234567 │ login: not required │ TSP-Link: offline"
+        );
+        status.nodes = vec![1, 2];
+        status.save = Some("out.txt".to_string());
+        assert_eq!(
+            status.to_string(),
+            "2450 #01234567 │ login: not required │ TSP-Link: nodes 1, 2 │ ● saving to out.txt"
+        );
+
+        let screen = Screen {
+            rows: 24,
+            cols: 80,
+            ..Screen::default()
+        };
+        assert_eq!((screen.output_bottom(), screen.error_rows()), (16, 6));
+        let small = Screen {
+            rows: 8,
+            cols: 80,
+            ..Screen::default()
+        };
+        assert_eq!((small.output_bottom(), small.error_rows()), (5, 1));
+    }
+}
@@ -0,0 +1,214 @@
+//! Expressions that are evaluated on the instrument periodically while the REPL is
+//! waiting for a command, with their values optionally logged to a CSV file.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::error::{InstrumentReplError, Result};
+
+/// The line that ends the output of a watch query.
+pub const WATCH_END: &str = "WATCH>END";
+
+/// The shortest time between evaluations of a watch.
+const MIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An expression that is evaluated on the instrument every `every`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    /// The TSP expression, e.g. `smu.measure.read()`
+    pub expression: String,
+    /// The time between evaluations
+    pub every: Duration,
+    /// The CSV file every value is appended to
+    pub log: Option<PathBuf>,
+    /// The last value, as printed by the instrument
+    pub value: Option<String>,
+    /// When the value last changed
+    pub changed: Option<DateTime<Local>>,
+    next: Instant,
+}
+
+impl Watch {
+    /// Create a watch that is evaluated for the first time as soon as possible.
+    #[must_use]
+    pub fn new(expression: String, every: Duration, log: Option<PathBuf>) -> Self {
+        Self {
+            expression,
+            every,
+            log,
+            value: None,
+            changed: None,
+            next: Instant::now(),
+        }
+    }
+
+    /// Whether the watch should be evaluated at `now`.
+    #[must_use]
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next <= now
+    }
+
+    /// Schedule the next evaluation, `every` after `now`.
+    pub fn schedule(&mut self, now: Instant) {
+        self.next = now.checked_add(self.every).unwrap_or(now);
+    }
+
+    /// Record a value that was read at `time`, appending it to the log. Returns
+    /// whether the value changed.
+    ///
+    /// # Errors
+    /// Returns an error if the log could not be written.
+    pub fn update(&mut self, value: String, time: DateTime<Local>) -> Result<bool> {
+        if let Some(log) = &self.log {
+            append_log(log, time, &self.expression, &value)?;
+        }
+        if self.value.as_ref() == Some(&value) {
+            return Ok(false);
+        }
+        self.value = Some(value);
+        self.changed = Some(time);
+        Ok(true)
+    }
+}
+
+impl std::fmt::Display for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} = {}",
+            self.expression,
+            self.value.as_deref().unwrap_or("…")
+        )?;
+        if let Some(changed) = self.changed {
+            write!(f, "  ({})", changed.format("%H:%M:%S%.3f"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse an interval such as `500ms`, `2s`, `1.5s` or `1m`. A number without a unit is
+/// in milliseconds.
+///
+/// # Errors
+/// Returns an error if the interval is not a positive number followed by a known unit,
+/// or is shorter than 50 ms.
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split);
+    let scale = match unit.trim() {
+        "" | "ms" => 1e-3,
+        "s" => 1.0,
+        "m" | "min" => 60.0,
+        _ => f64::NAN,
+    };
+    let seconds = number.parse::<f64>().map_or(f64::NAN, |n| n * scale);
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(d) if d >= MIN_INTERVAL => Ok(d),
+        Ok(_) => Err(InstrumentReplError::Other(format!(
+            "\"{interval}\" is too short, watches can be evaluated at most every {} ms",
+            MIN_INTERVAL.as_millis()
+        ))),
+        Err(_) => Err(InstrumentReplError::Other(format!(
+            "\"{interval}\" is not an interval, e.g. `500ms` or `2s`"
+        ))),
+    }
+}
+
+/// The TSP that prints the value of each of `expressions`, prefixed with its index,
+/// followed by [`WATCH_END`]. Errors are printed instead of the value.
+pub fn query<'a>(expressions: impl IntoIterator<Item = (usize, &'a str)>) -> String {
+    let mut tsp = String::new();
+    for (i, expression) in expressions {
+        let _ = write!(
+            tsp,
+            "do local ok, v = pcall(function() return {expression} end) \
+            if not ok then v = \"error: \" .. tostring(v) end \
+            print(\"WATCH>{i}\\t\" .. tostring(v)) end "
+        );
+    }
+    tsp.push_str("print(\"WATCH>END\")");
+    tsp
+}
+
+/// Parse the output of [`query`] into the index and value of each expression.
+#[must_use]
+pub fn parse_values(output: &str) -> Vec<(usize, String)> {
+    output
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("WATCH>"))
+        .filter_map(|l| l.split_once('\t'))
+        .filter_map(|(i, v)| Some((i.parse().ok()?, v.to_string())))
+        .collect()
+}
+
+/// Quote a CSV field if it has to be.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Append a value to the log at `path`, writing the header first if the log is new.
+fn append_log(path: &Path, time: DateTime<Local>, expression: &str, value: &str) -> Result<()> {
+    let is_new = !path.exists();
+    let mut file = File::options().append(true).create(true).open(path)?;
+    if is_new {
+        file.write_all(b"timestamp,expression,value\n")?;
+    }
+    writeln!(
+        file,
+        "{},{},{}",
+        time.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        csv_field(expression),
+        csv_field(value)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit {
+    use std::time::Duration;
+
+    use super::{csv_field, parse_interval, parse_values, query};
+    use kic_lib::tsp::check_syntax;
+
+    #[test]
+    fn intervals() {
+        assert_eq!(parse_interval("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_interval("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_interval("1m").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_interval("250").unwrap(), Duration::from_millis(250));
+        assert!(parse_interval("10ms").is_err());
+        assert!(parse_interval("fast").is_err());
+        assert!(parse_interval("5h").is_err());
+    }
+
+    #[test]
+    fn values() {
+        let tsp = query([(0, "smu.measure.read()"), (2, "temp")]);
+        assert_eq!(check_syntax(&tsp), Ok(()));
+        let output = "WATCH>0\t1.2e-09\nWATCH>2\terror: attempt to index a nil value\nWATCH>END";
+        assert_eq!(
+            parse_values(output),
+            vec![
+                (0, "1.2e-09".to_string()),
+                (2, "error: attempt to index a nil value".to_string())
+            ]
+        );
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("1.5"), "1.5");
+    }
+}
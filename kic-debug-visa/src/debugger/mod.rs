@@ -1,8 +1,13 @@
 use clap::{arg, value_parser, Command};
 use colored::Colorize;
-use kic_lib::instrument::{clear_output_queue, Instrument};
+use kic_lib::{
+    instrument::{clear_output_queue, Instrument},
+    model::{Family, Model},
+    tsp::{check_syntax, preprocess},
+};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs,
     io::{Error, Write},
@@ -34,6 +39,9 @@ pub struct Debugger {
     debuggee_file_name: Option<String>,
     debuggee_file_path: Option<PathBuf>,
     breakpoints: Vec<Breakpoint>,
+    syntax_check: bool,
+    family: Option<Family>,
+    defines: BTreeMap<String, String>,
 }
 
 impl Debugger {
@@ -46,9 +54,30 @@ impl Debugger {
             debuggee_file_name: None,
             debuggee_file_path: None,
             breakpoints: Default::default(),
+            syntax_check: true,
+            family: None,
+            defines: BTreeMap::new(),
         }
     }
 
+    /// Set whether the syntax of a script is checked before it is debugged. The check
+    /// is on by default.
+    pub fn set_syntax_check(&mut self, syntax_check: bool) {
+        self.syntax_check = syntax_check;
+    }
+
+    /// Set the model of the connected instrument, for the `family` in the `--#if`
+    /// directives of a script.
+    pub const fn set_model(&mut self, model: &Model) {
+        self.family = model.family();
+    }
+
+    /// Set the names that are defined for the directives of a script, overriding their
+    /// definitions in the script.
+    pub fn set_defines(&mut self, defines: BTreeMap<String, String>) {
+        self.defines = defines;
+    }
+
     // Funtion to handle all the special characters in the tsp script
     // * `script_name` - A String holds file name
     fn format_scriptname(mut script_name: String) -> String {
@@ -98,6 +127,44 @@ impl Debugger {
         file_content: &str,
         breakpoints: Vec<Breakpoint>,
     ) -> Result<()> {
+        let path = self
+            .debuggee_file_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(file_name));
+        let script = match preprocess(&path, file_content, &self.defines, self.family.as_ref()) {
+            Ok(s) => s,
+            Err(source) => {
+                let e = DebugError::PreprocessError { source };
+                Self::println_flush(&e.to_string().red());
+                return Err(e);
+            }
+        };
+        // The debugger reports the lines of the loaded script, which would not be the
+        // lines of the file if other files were included.
+        if !script.includes.is_empty() {
+            let e = DebugError::Other(format!(
+                "{}: `--#include` is not supported when debugging",
+                path.display()
+            ));
+            Self::println_flush(&e.to_string().red());
+            return Err(e);
+        }
+        let file_content = script.source.as_str();
+
+        let syntax = if self.syntax_check {
+            check_syntax(file_content)
+        } else {
+            Ok(())
+        };
+        if let Err(source) = syntax {
+            let e = DebugError::SyntaxError {
+                file: file_name.to_string(),
+                source,
+            };
+            Self::println_flush(&e.to_string().red());
+            return Err(e);
+        }
+
         self.load_debugger_files()?;
         self.clear_debugger_file_sources()?;
 
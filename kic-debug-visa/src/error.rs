@@ -56,6 +56,22 @@ pub enum DebugError {
         source: clap::error::Error,
     },
 
+    /// The script to debug has a syntax error, so it was not loaded.
+    #[error("syntax error in {file}:{source}")]
+    SyntaxError {
+        /// The name of the script file
+        file: String,
+        /// The syntax error
+        source: kic_lib::tsp::SyntaxError,
+    },
+
+    /// The directives of the script to debug could not be expanded, so it was not loaded.
+    #[error("preprocessing error in {source}")]
+    PreprocessError {
+        /// The preprocessing error
+        source: kic_lib::tsp::PreprocessError,
+    },
+
     /// Some other error
     #[error("{0}")]
     Other(String),
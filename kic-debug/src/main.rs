@@ -1,11 +1,12 @@
 use anyhow::Context;
 use chrono::Utc;
-use clap::{command, value_parser, Arg, ArgMatches, Command};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
 use colored::Colorize;
 use kic_debug::debugger::Debugger;
 use kic_lib::{
     instrument::{authenticate::Authentication, CmdLanguage, Instrument, State},
     model::connect_to,
+    tsp::parse_define,
     ConnectionInfo,
 };
 use std::io::{stdin, ErrorKind};
@@ -103,7 +104,22 @@ fn main() -> anyhow::Result<()> {
                 e
             })?;
             clear_output_queue(&mut instrument, 5000, Duration::from_millis(1))?;
-            Debugger::new(instrument)
+            let info = instrument.info();
+            let mut debugger = Debugger::new(instrument);
+            debugger.set_syntax_check(!sub_matches.get_flag("no-check"));
+            match info {
+                Ok(info) => debugger.set_model(&info.model),
+                Err(e) => warn!("Unable to get the instrument model for preprocessing: {e}"),
+            }
+            debugger.set_defines(
+                sub_matches
+                    .get_many::<(String, String)>("define")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+            );
+            debugger
         }
         _ => unreachable!(),
     };
@@ -217,6 +233,21 @@ fn add_connection_subcommands(command: impl Into<Command>) -> Command {
             .required(false)
             .long("username")
             .value_parser(value_parser!(String)),
+    ).arg(
+        Arg::new("no-check")
+            .help("Debug scripts without checking their syntax first.")
+            .required(false)
+            .long("no-check")
+            .action(ArgAction::SetTrue),
+    ).arg(
+        Arg::new("define")
+            .help("Define NAME for the `--#define` and `--#if` directives of scripts, overriding their definitions in the scripts. Can be given multiple times.")
+            .required(false)
+            .short('D')
+            .long("define")
+            .value_name("NAME=VALUE")
+            .action(ArgAction::Append)
+            .value_parser(parse_define),
     );
 
     command
@@ -1,6 +1,6 @@
 use std::{collections::HashSet, hash::Hash, io::Error, sync::Mutex};
 
-use kic_lib::{ki2600, model::ki3700, tti, versatest};
+use kic_lib::model::{Family, Model};
 
 pub mod ethernet;
 pub mod instrument_discovery;
@@ -12,17 +12,14 @@ lazy_static! {
     pub static ref DISC_INSTRUMENTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
 
+/// The category of the instrument model, as used by the discovery output, based on
+/// the family given by the model registry.
 #[must_use]
 pub fn model_category(in_str: &str) -> &'static str {
-    if ki2600::Instrument::model_is(in_str)
-        || ki3700::Instrument::model_is(in_str)
-        || tti::Instrument::model_is(in_str)
-    {
-        "tti/26xx"
-    } else if versatest::Instrument::model_is(in_str) {
-        "versatest"
-    } else {
-        ""
+    match in_str.parse::<Model>().ok().and_then(|m| m.family()) {
+        Some(Family::_26xx | Family::_3700 | Family::Tti) => "tti/26xx",
+        Some(Family::ModularPlatform) => "versatest",
+        None => "",
     }
 }
 
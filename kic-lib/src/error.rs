@@ -134,12 +134,20 @@ pub enum InstrumentError {
     #[error("Instrument upgrade failed: {0}")]
     FwUpgradeFailure(String),
 
+    /// The firmware transfer was cancelled before the image was completely sent.
+    #[error("Instrument upgrade cancelled: firmware image was not completely transferred")]
+    FwUpgradeCancelled,
+
     #[error("unknown vendor error: {0}")]
     UnknownVendor(String),
 
     #[error("system keyring error: {0}")]
     KeyringError(#[from] keyring::Error),
 
+    /// The credential store could not be used.
+    #[error("credential store error: {0}")]
+    CredentialStoreError(#[from] crate::instrument::credentials::CredentialError),
+
     #[error("serialization or deserialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
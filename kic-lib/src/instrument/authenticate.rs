@@ -1,11 +1,10 @@
 // Authenticate functionality of the instrument.
 
-#[cfg(test)]
-use keyring::{mock, set_default_credential_builder};
-
-use crate::{model::Model, InstrumentError};
-
-const SERVICE_NAME: &str = "tsp-toolkit";
+use crate::{
+    instrument::credentials::{credential_id, Credential, CredentialError, CredentialStore},
+    model::Model,
+    InstrumentError,
+};
 
 /// An enum that provides the expected functionality for authentication into an instrument.
 ///
@@ -26,18 +25,13 @@ pub enum Authentication {
     },
     /// Allows a credential to be entered without prompts or keyring lookups.
     Credential { username: String, password: String },
-    /// Uses an id to look up the proper credentials in the system keyring.
+    /// Uses an id to look up the proper credentials in the system keyring, or the
+    /// credentials file if one is configured.
     Keyring { id: String },
     /// No authentication is required, don't try to use any.
     NoAuth,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
-struct SecretEntry {
-    username: String,
-    password: String,
-}
-
 impl Authentication {
     ///
     /// Retrieves the username
@@ -76,10 +70,7 @@ impl Authentication {
             }
             Self::Credential { username, .. } => Ok(Some((*username).to_string())),
             Self::Keyring { id } => {
-                let entry = keyring::Entry::new(SERVICE_NAME, id)?;
-                let secret = &entry.get_secret()?;
-                let secret: SecretEntry =
-                    serde_json::from_str(String::from_utf8_lossy(secret).as_ref())?;
+                let secret = Self::stored_credential(id)?;
 
                 Ok(Some(secret.username))
             }
@@ -120,10 +111,7 @@ impl Authentication {
             }
             Self::Credential { password, .. } => Ok(Some((*password).to_string())),
             Self::Keyring { id } => {
-                let entry = keyring::Entry::new(SERVICE_NAME, id)?;
-                let secret = &entry.get_secret()?;
-                let secret: SecretEntry =
-                    serde_json::from_str(String::from_utf8_lossy(secret).as_ref())?;
+                let secret = Self::stored_credential(id)?;
 
                 Ok(Some(secret.password))
             }
@@ -139,28 +127,19 @@ impl Authentication {
     /// Returns an error if there was an error accessing the keyring (besides not existing)
     pub fn keyring_entry_exists(&self) -> Result<bool, InstrumentError> {
         match self {
-            Self::Keyring { id } => {
-                let entry = keyring::Entry::new(SERVICE_NAME, id)?;
-                match entry.get_secret() {
-                    Ok(_) => Ok(true),
-                    Err(keyring::Error::NoEntry) => Ok(false),
-                    Err(e) => Err(e.into()),
-                }
-            }
+            Self::Keyring { id } => Ok(CredentialStore::from_env()?.get(id)?.is_some()),
             _ => Ok(false),
         }
     }
 
-    /// Saves this credential to the system keyring. This will overwrite an existing
-    /// entry or create a new one if it doesn't already exist.
+    /// Saves this credential to the credential store selected by
+    /// [`CredentialStore::from_env`]. This will overwrite an existing entry or create a
+    /// new one if it doesn't already exist.
     ///
     /// # Errors
-    /// Errors may occur from the interactions with the [`keyring`] crate.
+    /// Errors may occur from the interactions with the credential store.
     pub fn save_credential(&self, model: &Model, serial: &str) -> Result<(), InstrumentError> {
-        #[cfg(test)] // Don't use the system credential manager for unit tests.
-        set_default_credential_builder(mock::default_credential_builder());
-
-        let name = format!("{model}#{serial}");
+        let name = credential_id(model, serial);
         let (username, password) = match self {
             Self::Prompt => {
                 return Err(InstrumentError::AuthenticationFailure(
@@ -173,20 +152,18 @@ impl Authentication {
             ),
             Self::Credential { username, password } => (username.to_string(), password.to_string()),
             Self::Keyring { id } => {
-                let entry = keyring::Entry::new(SERVICE_NAME, id)?;
-                let secret = &entry.get_secret()?;
-                let secret: SecretEntry =
-                    serde_json::from_str(String::from_utf8_lossy(secret).as_ref())?;
+                let secret = Self::stored_credential(id)?;
                 (secret.username, secret.password)
             }
             Self::NoAuth => return Ok(()),
         };
-        let secret = SecretEntry { username, password };
-
-        let entry = keyring::Entry::new(SERVICE_NAME, &name)?;
-
-        entry.set_secret(serde_json::to_string(&secret)?.as_bytes())?;
+        Ok(CredentialStore::from_env()?.set(&name, &Credential { username, password })?)
+    }
 
-        Ok(())
+    /// Look up the credential stored under `id`.
+    fn stored_credential(id: &str) -> Result<Credential, InstrumentError> {
+        CredentialStore::from_env()?
+            .get(id)?
+            .ok_or_else(|| CredentialError::NotFound(id.to_string()).into())
     }
 }
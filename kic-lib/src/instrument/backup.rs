@@ -0,0 +1,410 @@
+//! Capture the user content of an instrument (scripts, setups and TSP-Link
+//! configuration) into a [`Backup`] and push it back onto the same or a replacement
+//! instrument.
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace};
+
+use crate::{
+    error::Result,
+    instrument::{
+        info::InstrumentInfo,
+        query::{parse_integer, query_tagged},
+        script::{validate_name, ScriptInfo},
+        Info, Script,
+    },
+    model::Model,
+    InstrumentError,
+};
+
+/// The version of the backup format written by this library.
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// The name under which the instrument setup is stored in a [`Backup`].
+const SETUP_NAME: &str = "kic_setup_backup";
+
+/// The prefix of the name of the temporary scripts used to capture and apply the
+/// instrument setup.
+const TEMP_SCRIPT_PREFIX: &str = "kic_setup_";
+
+/// The identity of the instrument a [`Backup`] was taken from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSource {
+    /// The vendor of the instrument
+    pub vendor: String,
+    /// The model of the instrument
+    pub model: String,
+    /// The serial number of the instrument
+    pub serial_number: String,
+    /// The firmware revision of the instrument
+    pub firmware_rev: Option<String>,
+}
+
+impl From<&InstrumentInfo> for BackupSource {
+    fn from(info: &InstrumentInfo) -> Self {
+        Self {
+            vendor: info.vendor.to_string(),
+            model: info.model.to_string(),
+            serial_number: info.serial_number.clone(),
+            firmware_rev: info.firmware_rev.clone(),
+        }
+    }
+}
+
+/// A script, or setup, stored in a [`Backup`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupScript {
+    /// The name of the script
+    pub name: String,
+    /// Whether the script is run when the instrument powers on
+    pub autorun: bool,
+    /// The source code of the script
+    pub source: String,
+}
+
+/// The TSP-Link configuration of an instrument.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TspLinkConfig {
+    /// The TSP-Link node number of the instrument
+    pub node: u16,
+}
+
+/// Everything needed to restore the user content of an instrument.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backup {
+    /// The version of the format of this backup
+    pub format_version: u32,
+    /// When the backup was created (RFC 3339)
+    pub created: String,
+    /// The instrument the backup was taken from
+    pub instrument: BackupSource,
+    /// The scripts saved in non-volatile memory
+    pub scripts: Vec<BackupScript>,
+    /// The instrument setups, captured as configuration scripts
+    pub setups: Vec<BackupScript>,
+    /// The TSP-Link configuration, if the instrument supports TSP-Link
+    pub tsplink: Option<TspLinkConfig>,
+}
+
+/// How well a [`Backup`] matches the instrument it is being restored onto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// The instrument is the same model as the one the backup was taken from.
+    SameModel,
+    /// The instrument is a different model in the same family.
+    SameFamily,
+    /// The instrument is not compatible with the backup.
+    Incompatible,
+}
+
+impl Backup {
+    /// Check whether this backup can be restored onto the instrument described by
+    /// `info`.
+    #[must_use]
+    pub fn compatibility(&self, info: &InstrumentInfo) -> Compatibility {
+        // Model::from_str is infallible
+        let model: Model = self
+            .instrument
+            .model
+            .parse()
+            .unwrap_or_else(|_| Model::Other(self.instrument.model.clone()));
+        if model == info.model {
+            Compatibility::SameModel
+        } else if model.family().is_some() && model.family() == info.model.family() {
+            Compatibility::SameFamily
+        } else {
+            Compatibility::Incompatible
+        }
+    }
+}
+
+/// Options that control how a [`Backup`] is restored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    /// Apply the setups of the backup to the instrument. Setups are not restored
+    /// otherwise.
+    pub apply_setups: bool,
+}
+
+/// Get a name for a temporary script that is unique to this process and is not the
+/// name of any of the `existing` scripts. The name is short enough that it is not
+/// truncated by [`Script::write_script`].
+fn temp_script_name(existing: &[ScriptInfo]) -> String {
+    let base = format!(
+        "{TEMP_SCRIPT_PREFIX}{:x}_{:x}",
+        std::process::id(),
+        chrono::Local::now().timestamp()
+    );
+    (0u32..)
+        .map(|n| format!("{base}_{n}"))
+        .find(|name| existing.iter().all(|s| &s.name != name))
+        .unwrap_or(base)
+}
+
+/// Parse the response of the TSP-Link configuration query.
+fn parse_tsplink(lines: &[String]) -> Option<TspLinkConfig> {
+    lines
+        .first()
+        .and_then(|l| parse_integer(l, 1..=64))
+        .map(|node| TspLinkConfig { node })
+}
+
+/// Capture the scripts, setups and TSP-Link configuration of the instrument.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if any of the queries fail.
+pub fn create_backup<T: Script + Info + ?Sized>(inst: &mut T) -> Result<Backup> {
+    let info = inst.info()?;
+    info!("Creating backup of {info}");
+
+    let existing = inst.list_scripts()?;
+    let mut scripts = Vec::new();
+    for s in &existing {
+        trace!("Backing up script '{}'", s.name);
+        let source = inst.script_source(&s.name)?;
+        scripts.push(BackupScript {
+            name: s.name.clone(),
+            autorun: s.autorun,
+            source,
+        });
+    }
+
+    // The setup is captured into a script with a name that is not in use, so a user
+    // script is never overwritten.
+    let temp = temp_script_name(&existing);
+    let has_setup = query_tagged(
+        inst,
+        &format!("do if createconfigscript ~= nil then createconfigscript(\"{temp}\") print(\"SETUP>YES\") end print(\"SETUP>END\") end"),
+        "SETUP",
+    )?;
+    let setups = if has_setup.is_empty() {
+        debug!("Instrument does not support configuration scripts");
+        Vec::new()
+    } else {
+        let source = inst.script_source(&temp)?;
+        inst.delete_script(&temp)?;
+        vec![BackupScript {
+            name: SETUP_NAME.to_string(),
+            autorun: false,
+            source,
+        }]
+    };
+
+    let tsplink = parse_tsplink(&query_tagged(
+        inst,
+        "do if tsplink ~= nil and tsplink.node ~= nil then print(\"TSPLINK>\" .. tostring(tsplink.node)) end print(\"TSPLINK>END\") end",
+        "TSPLINK",
+    )?);
+
+    Ok(Backup {
+        format_version: BACKUP_FORMAT_VERSION,
+        created: chrono::Local::now().to_rfc3339(),
+        instrument: BackupSource::from(&info),
+        scripts,
+        setups,
+        tsplink,
+    })
+}
+
+/// Push the contents of `backup` onto the instrument.
+///
+/// # Notes
+/// - Compatibility with the instrument is not checked here, see
+///   [`Backup::compatibility`].
+/// - Setups are loaded as temporary scripts that are not saved, run and then removed,
+///   so they never replace a script on the instrument.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the backup is of an unsupported format, if it
+/// contains an invalid script name, in which case nothing is written, or if writing to
+/// the instrument fails.
+pub fn restore_backup<T: Script + ?Sized>(
+    inst: &mut T,
+    backup: &Backup,
+    options: RestoreOptions,
+) -> Result<()> {
+    if backup.format_version > BACKUP_FORMAT_VERSION {
+        return Err(InstrumentError::Other(format!(
+            "backup format version {} is newer than the supported version {BACKUP_FORMAT_VERSION}",
+            backup.format_version
+        )));
+    }
+
+    for s in &backup.scripts {
+        validate_name(&s.name)?;
+    }
+
+    for s in &backup.scripts {
+        info!("Restoring script '{}'", s.name);
+        inst.write_script(s.name.as_bytes(), s.source.as_bytes(), true, false)?;
+        if s.autorun {
+            inst.set_script_autorun(&s.name, true)?;
+        }
+    }
+
+    if options.apply_setups {
+        let existing: Vec<ScriptInfo> = backup
+            .scripts
+            .iter()
+            .map(|s| ScriptInfo {
+                name: s.name.clone(),
+                autorun: s.autorun,
+            })
+            .collect();
+        for s in &backup.setups {
+            info!("Applying setup '{}'", s.name);
+            let temp = temp_script_name(&existing);
+            inst.write_script(temp.as_bytes(), s.source.as_bytes(), false, true)?;
+            inst.write_all(format!("{temp} = nil\n").as_bytes())?;
+            inst.flush()?;
+        }
+    } else if !backup.setups.is_empty() {
+        debug!("Not applying {} setup(s)", backup.setups.len());
+    }
+
+    if let Some(tsplink) = &backup.tsplink {
+        info!("Restoring TSP-Link node {}", tsplink.node);
+        inst.write_all(
+            format!(
+                "if tsplink ~= nil and tsplink.node ~= nil then tsplink.node = {} end\n",
+                tsplink.node
+            )
+            .as_bytes(),
+        )?;
+        inst.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit {
+    use std::io::{Read, Write};
+
+    use super::{
+        parse_tsplink, restore_backup, Backup, BackupScript, BackupSource, Compatibility,
+        RestoreOptions, TspLinkConfig, SETUP_NAME, TEMP_SCRIPT_PREFIX,
+    };
+    use crate::{
+        instrument::{info::InstrumentInfo, Script},
+        model::Model,
+    };
+
+    #[derive(Default)]
+    struct Recorder(Vec<u8>);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Recorder {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Script for Recorder {}
+
+    fn script(name: &str) -> BackupScript {
+        BackupScript {
+            name: name.to_string(),
+            autorun: false,
+            source: "print(1)".to_string(),
+        }
+    }
+
+    fn backup_of(model: &str) -> Backup {
+        Backup {
+            format_version: 1,
+            created: String::new(),
+            instrument: BackupSource {
+                vendor: "Keithley Instruments".to_string(),
+                model: model.to_string(),
+                serial_number: "0123456".to_string(),
+                firmware_rev: None,
+            },
+            scripts: Vec::new(),
+            setups: Vec::new(),
+            tsplink: None,
+        }
+    }
+
+    fn info_of(model: Model) -> InstrumentInfo {
+        InstrumentInfo {
+            model,
+            ..InstrumentInfo::default()
+        }
+    }
+
+    #[test]
+    fn compatibility() {
+        assert_eq!(
+            backup_of("2450").compatibility(&info_of("2450".parse::<Model>().unwrap())),
+            Compatibility::SameModel
+        );
+        assert_eq!(
+            backup_of("2460").compatibility(&info_of("2450".parse::<Model>().unwrap())),
+            Compatibility::SameFamily
+        );
+        assert_eq!(
+            backup_of("2636B").compatibility(&info_of("2450".parse::<Model>().unwrap())),
+            Compatibility::Incompatible
+        );
+        assert_eq!(
+            backup_of("unknown").compatibility(&info_of(Model::Other("other".to_string()))),
+            Compatibility::Incompatible
+        );
+    }
+
+    #[test]
+    fn tsplink_parsing() {
+        assert_eq!(
+            parse_tsplink(&["2.00000e+00".to_string()]),
+            Some(TspLinkConfig { node: 2 })
+        );
+        assert_eq!(
+            parse_tsplink(&["12".to_string()]),
+            Some(TspLinkConfig { node: 12 })
+        );
+        assert_eq!(parse_tsplink(&[]), None);
+        assert_eq!(parse_tsplink(&["nil".to_string()]), None);
+    }
+
+    #[test]
+    fn restore_rejects_invalid_names() {
+        let mut backup = backup_of("2450");
+        backup.scripts = vec![script("good"), script("bad\") os.exit() --")];
+        let mut inst = Recorder::default();
+        assert!(restore_backup(&mut inst, &backup, RestoreOptions::default()).is_err());
+        assert!(inst.0.is_empty());
+    }
+
+    #[test]
+    fn restore_setups() {
+        let mut backup = backup_of("2450");
+        backup.setups = vec![script(SETUP_NAME)];
+
+        let mut inst = Recorder::default();
+        restore_backup(&mut inst, &backup, RestoreOptions::default()).unwrap();
+        assert!(inst.0.is_empty());
+
+        let mut inst = Recorder::default();
+        restore_backup(&mut inst, &backup, RestoreOptions { apply_setups: true }).unwrap();
+        let written = String::from_utf8(inst.0).unwrap();
+        assert!(!written.contains(SETUP_NAME));
+        assert!(!written.contains(".save()"));
+        assert!(written.contains(&format!("loadscript {TEMP_SCRIPT_PREFIX}")));
+        assert!(written.contains(" = nil\n"));
+    }
+}
@@ -0,0 +1,556 @@
+//! Storage for the credentials used to log in to instruments.
+//!
+//! Credentials are stored under an id, by convention `<model>#<serial>`, either in the
+//! system keyring or, where no keyring is available (e.g. on a headless CI runner), in
+//! an encrypted file selected with [`CREDENTIALS_FILE_ENV`].
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::{debug, trace};
+use zeroize::Zeroizing;
+
+#[cfg(test)]
+use keyring::{mock, set_default_credential_builder};
+
+/// Errors from a [`CredentialStore`].
+#[derive(Error, Debug)]
+pub enum CredentialError {
+    /// No credential is stored under the given id.
+    #[error("no credential is stored under '{0}'")]
+    NotFound(String),
+
+    /// A credential is already stored under the given id.
+    #[error("a credential is already stored under '{0}'")]
+    AlreadyExists(String),
+
+    /// [`CREDENTIALS_FILE_ENV`] is set but [`CREDENTIALS_KEY_ENV`] is not.
+    #[error("{CREDENTIALS_FILE_ENV} is set but {CREDENTIALS_KEY_ENV} is not")]
+    MissingKey,
+
+    /// The credentials file is not one, was encrypted with another passphrase or is
+    /// corrupted.
+    #[error("unable to read credentials file: {0}")]
+    InvalidFile(&'static str),
+
+    /// The credentials file could not be encrypted.
+    #[error("unable to encrypt credentials file")]
+    EncryptionFailed,
+
+    #[error("system keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+
+    #[error("credentials file error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("credential serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, CredentialError>;
+
+/// The service name under which credentials are stored in the system keyring.
+const SERVICE_NAME: &str = "tsp-toolkit";
+
+/// The id of the keyring entry that records the ids of all other entries, since the
+/// system keyring cannot be enumerated.
+const INDEX_ID: &str = "#index";
+
+/// If set, credentials are stored in the encrypted file at this path instead of the
+/// system keyring.
+pub const CREDENTIALS_FILE_ENV: &str = "TSP_TOOLKIT_CREDENTIALS_FILE";
+
+/// The passphrase used to encrypt the file given by [`CREDENTIALS_FILE_ENV`].
+pub const CREDENTIALS_KEY_ENV: &str = "TSP_TOOLKIT_CREDENTIALS_KEY";
+
+/// Identifies an encrypted credentials file and the version of its layout.
+const FILE_MAGIC: &[u8; 8] = b"KICCRED\x02";
+const ROUNDS_LEN: usize = 4;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+/// The magic, KDF rounds, salt and nonce, which are authenticated but not encrypted.
+const HEADER_LEN: usize = FILE_MAGIC.len() + ROUNDS_LEN + SALT_LEN + NONCE_LEN;
+
+/// The parameters of the key derivation (PBKDF2-HMAC-SHA256) used to encrypt a
+/// credentials file. They are stored in the file, so a file can always be read
+/// whatever parameters it was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// The number of PBKDF2 iterations
+    pub rounds: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self { rounds: 100_000 }
+    }
+}
+
+/// A username and password used to log in to an instrument.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Credential {
+    /// The username, empty for instruments that only require a password
+    pub username: String,
+    /// The password
+    pub password: String,
+}
+
+/// Where credentials are stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialStore {
+    /// The system keyring (Windows Credential Manager, macOS Keychain or the Secret
+    /// Service on Linux).
+    Keyring,
+    /// A file encrypted with a key derived from `passphrase`.
+    File {
+        /// The path of the credentials file
+        path: PathBuf,
+        /// The passphrase from which the encryption key is derived
+        passphrase: Zeroizing<String>,
+        /// The key derivation parameters used when a new file is created
+        kdf: KdfParams,
+    },
+}
+
+impl CredentialStore {
+    /// Select the store given by the environment: the encrypted file if
+    /// [`CREDENTIALS_FILE_ENV`] is set, otherwise the system keyring.
+    ///
+    /// # Errors
+    /// Returns an error if [`CREDENTIALS_FILE_ENV`] is set but
+    /// [`CREDENTIALS_KEY_ENV`] is not.
+    pub fn from_env() -> Result<Self> {
+        let Some(path) = std::env::var_os(CREDENTIALS_FILE_ENV) else {
+            return Ok(Self::Keyring);
+        };
+        let passphrase = std::env::var(CREDENTIALS_KEY_ENV)
+            .map(Zeroizing::new)
+            .map_err(|_| CredentialError::MissingKey)?;
+        Ok(Self::File {
+            path: PathBuf::from(path),
+            passphrase,
+            kdf: KdfParams::default(),
+        })
+    }
+
+    /// Get the credential stored under `id`, if there is one.
+    ///
+    /// # Errors
+    /// Returns an error if the store could not be read.
+    pub fn get(&self, id: &str) -> Result<Option<Credential>> {
+        match self {
+            Self::Keyring => match keyring_entry(id)?.get_secret() {
+                Ok(secret) => Ok(Some(serde_json::from_str(
+                    String::from_utf8_lossy(&secret).as_ref(),
+                )?)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Self::File {
+                path,
+                passphrase,
+                kdf,
+            } => Ok(CredentialFile::load(path, passphrase, *kdf)?
+                .entries
+                .remove(id)),
+        }
+    }
+
+    /// Store `credential` under `id`, replacing any existing entry.
+    ///
+    /// # Errors
+    /// Returns an error if the store could not be read or written.
+    pub fn set(&self, id: &str, credential: &Credential) -> Result<()> {
+        trace!("Storing credential '{id}'");
+        match self {
+            Self::Keyring => {
+                keyring_entry(id)?.set_secret(serde_json::to_string(credential)?.as_bytes())?;
+                let mut index = keyring_index()?;
+                if !index.iter().any(|i| i == id) {
+                    index.push(id.to_string());
+                    set_keyring_index(&index)?;
+                }
+                Ok(())
+            }
+            Self::File {
+                path,
+                passphrase,
+                kdf,
+            } => {
+                let mut file = CredentialFile::load(path, passphrase, *kdf)?;
+                file.entries.insert(id.to_string(), credential.clone());
+                file.save(path)
+            }
+        }
+    }
+
+    /// Remove the credential stored under `id`. Returns whether an entry was removed.
+    ///
+    /// # Errors
+    /// Returns an error if the store could not be read or written.
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        trace!("Removing credential '{id}'");
+        match self {
+            Self::Keyring => {
+                let removed = match keyring_entry(id)?.delete_credential() {
+                    Ok(()) => true,
+                    Err(keyring::Error::NoEntry) => false,
+                    Err(e) => return Err(e.into()),
+                };
+                let mut index = keyring_index()?;
+                let len = index.len();
+                index.retain(|i| i != id);
+                if index.len() != len {
+                    set_keyring_index(&index)?;
+                }
+                Ok(removed)
+            }
+            Self::File {
+                path,
+                passphrase,
+                kdf,
+            } => {
+                let mut file = CredentialFile::load(path, passphrase, *kdf)?;
+                let removed = file.entries.remove(id).is_some();
+                if removed {
+                    file.save(path)?;
+                }
+                Ok(removed)
+            }
+        }
+    }
+
+    /// List the ids of the stored credentials.
+    ///
+    /// # Notes
+    /// The system keyring cannot be enumerated, so only entries stored since the index
+    /// was introduced are listed. Older entries are added the next time they are used
+    /// to log in successfully.
+    ///
+    /// # Errors
+    /// Returns an error if the store could not be read.
+    pub fn list(&self) -> Result<Vec<String>> {
+        match self {
+            Self::Keyring => {
+                let mut ids = keyring_index()?;
+                ids.sort();
+                Ok(ids)
+            }
+            Self::File {
+                path,
+                passphrase,
+                kdf,
+            } => Ok(CredentialFile::load(path, passphrase, *kdf)?
+                .entries
+                .into_keys()
+                .collect()),
+        }
+    }
+
+    /// Move the credential stored under `from` to `to`, e.g. after the serial number
+    /// of an instrument changed during a repair.
+    ///
+    /// # Errors
+    /// Returns an error if there is no credential stored under `from`, if one is
+    /// already stored under `to`, or if the store could not be read or written.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        if self.get(to)?.is_some() {
+            return Err(CredentialError::AlreadyExists(to.to_string()));
+        }
+        let Some(credential) = self.get(from)? else {
+            return Err(CredentialError::NotFound(from.to_string()));
+        };
+        debug!("Moving credential '{from}' to '{to}'");
+        self.set(to, &credential)?;
+        self.remove(from)?;
+        Ok(())
+    }
+
+    /// Move every credential stored for serial number `old_serial` to `new_serial`,
+    /// keeping the model part of the id. Returns the `(from, to)` ids that were moved.
+    ///
+    /// # Errors
+    /// Returns an error if any of the entries could not be moved.
+    pub fn migrate_serial(
+        &self,
+        old_serial: &str,
+        new_serial: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let suffix = format!("#{old_serial}");
+        let mut moved = Vec::new();
+        for from in self.list()? {
+            if let Some(model) = from.strip_suffix(&suffix) {
+                let to = credential_id(model, new_serial);
+                self.rename(&from, &to)?;
+                moved.push((from, to));
+            }
+        }
+        Ok(moved)
+    }
+}
+
+/// The id under which the credential of the instrument with the given model and
+/// serial number is stored.
+#[must_use]
+pub fn credential_id(model: impl std::fmt::Display, serial: &str) -> String {
+    format!("{model}#{serial}")
+}
+
+fn keyring_entry(id: &str) -> Result<keyring::Entry> {
+    #[cfg(test)] // Don't use the system credential manager for unit tests.
+    set_default_credential_builder(mock::default_credential_builder());
+
+    Ok(keyring::Entry::new(SERVICE_NAME, id)?)
+}
+
+fn keyring_index() -> Result<Vec<String>> {
+    match keyring_entry(INDEX_ID)?.get_secret() {
+        Ok(secret) => Ok(serde_json::from_slice(&secret)?),
+        Err(keyring::Error::NoEntry) => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn set_keyring_index(index: &[String]) -> Result<()> {
+    Ok(keyring_entry(INDEX_ID)?.set_secret(serde_json::to_string(index)?.as_bytes())?)
+}
+
+/// The decrypted contents of a credentials file along with the key used to encrypt
+/// it.
+struct CredentialFile {
+    rounds: u32,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; KEY_LEN]>,
+    entries: BTreeMap<String, Credential>,
+}
+
+impl CredentialFile {
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN], kdf: KdfParams) -> Self {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, kdf.rounds, key.as_mut());
+        Self {
+            rounds: kdf.rounds,
+            salt,
+            key,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(self.key.as_ref().into())
+    }
+
+    /// Read and decrypt the file at `path`. A file that does not exist yet is treated
+    /// as empty and will be encrypted with a key derived using `kdf`.
+    fn load(path: &Path, passphrase: &str, kdf: KdfParams) -> Result<Self> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = [0u8; SALT_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                return Ok(Self::derive(passphrase, salt, kdf));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Self::decrypt(&data, passphrase)
+    }
+
+    fn decrypt(data: &[u8], passphrase: &str) -> Result<Self> {
+        let invalid = CredentialError::InvalidFile;
+        if data.len() < HEADER_LEN + TAG_LEN || !data.starts_with(FILE_MAGIC) {
+            return Err(invalid("not a credentials file"));
+        }
+        let (header, body) = data.split_at(HEADER_LEN);
+        let (rounds, rest) = header[FILE_MAGIC.len()..].split_at(ROUNDS_LEN);
+        let (salt, nonce) = rest.split_at(SALT_LEN);
+        let mut rounds_bytes = [0u8; ROUNDS_LEN];
+        rounds_bytes.copy_from_slice(rounds);
+        let rounds = u32::from_be_bytes(rounds_bytes);
+        if rounds == 0 {
+            return Err(invalid("corrupted file"));
+        }
+        let mut salt_bytes = [0u8; SALT_LEN];
+        salt_bytes.copy_from_slice(salt);
+
+        let mut file = Self::derive(passphrase, salt_bytes, KdfParams { rounds });
+        let plain = Zeroizing::new(
+            file.cipher()
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: body,
+                        aad: header,
+                    },
+                )
+                .map_err(|_| invalid("wrong passphrase or corrupted file"))?,
+        );
+        file.entries = serde_json::from_slice(&plain)?;
+        Ok(file)
+    }
+
+    fn encrypt(&self) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plain = Zeroizing::new(serde_json::to_vec(&self.entries)?);
+
+        let mut data = Vec::with_capacity(HEADER_LEN + plain.len() + TAG_LEN);
+        data.extend_from_slice(FILE_MAGIC);
+        data.extend_from_slice(&self.rounds.to_be_bytes());
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&nonce);
+        let cipher = self
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plain,
+                    aad: &data,
+                },
+            )
+            .map_err(|_| CredentialError::EncryptionFailed)?;
+        data.extend_from_slice(&cipher);
+        Ok(data)
+    }
+
+    /// Encrypt and write the file to `path`, readable only by the current user.
+    fn save(&self, path: &Path) -> Result<()> {
+        let data = self.encrypt()?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(&data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{
+        credential_id, Credential, CredentialError, CredentialFile, CredentialStore, KdfParams,
+    };
+    use assert_matches::assert_matches;
+    use std::path::PathBuf;
+    use zeroize::Zeroizing;
+
+    fn temp_store(name: &str) -> (PathBuf, CredentialStore) {
+        let path =
+            std::env::temp_dir().join(format!("kic-credentials-{name}-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = CredentialStore::File {
+            path: path.clone(),
+            passphrase: Zeroizing::new("correct horse battery staple".to_string()),
+            // Key derivation is deliberately slow, keep the unit tests fast.
+            kdf: KdfParams { rounds: 1_000 },
+        };
+        (path, store)
+    }
+
+    fn credential(password: &str) -> Credential {
+        Credential {
+            username: "admin".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn file_store_round_trip() {
+        let (path, store) = temp_store("round-trip");
+        assert_eq!(store.list().unwrap(), Vec::<String>::new());
+
+        store.set("2450#0123456", &credential("first")).unwrap();
+        store.set("MP5103#9876543", &credential("second")).unwrap();
+        store.set("2450#0123456", &credential("rotated")).unwrap();
+
+        assert_eq!(
+            store.list().unwrap(),
+            vec!["2450#0123456".to_string(), "MP5103#9876543".to_string()]
+        );
+        assert_eq!(
+            store.get("2450#0123456").unwrap(),
+            Some(credential("rotated"))
+        );
+        assert!(!std::fs::read(&path)
+            .unwrap()
+            .windows(b"rotated".len())
+            .any(|w| w == b"rotated"));
+
+        assert!(store.remove("MP5103#9876543").unwrap());
+        assert!(!store.remove("MP5103#9876543").unwrap());
+        assert_eq!(store.get("MP5103#9876543").unwrap(), None);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_store_wrong_passphrase() {
+        let (path, store) = temp_store("wrong-passphrase");
+        store.set("2450#0123456", &credential("secret")).unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        assert_matches!(
+            CredentialFile::decrypt(&data, "not the passphrase").err(),
+            Some(CredentialError::InvalidFile(_))
+        );
+        assert!(CredentialFile::decrypt(&data, "correct horse battery staple").is_ok());
+
+        // The header is authenticated too.
+        data[super::FILE_MAGIC.len() + super::ROUNDS_LEN] ^= 1;
+        assert_matches!(
+            CredentialFile::decrypt(&data, "correct horse battery staple").err(),
+            Some(CredentialError::InvalidFile(_))
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_store_migrate_serial() {
+        let (path, store) = temp_store("migrate");
+        store.set("2450#0123456", &credential("a")).unwrap();
+        store.set("2460#0123456", &credential("b")).unwrap();
+        store.set("2450#1111111", &credential("c")).unwrap();
+
+        let moved = store.migrate_serial("0123456", "7654321").unwrap();
+        assert_eq!(
+            moved,
+            vec![
+                ("2450#0123456".to_string(), "2450#7654321".to_string()),
+                ("2460#0123456".to_string(), "2460#7654321".to_string()),
+            ]
+        );
+        assert_eq!(store.get("2450#7654321").unwrap(), Some(credential("a")));
+        assert_eq!(store.get("2450#0123456").unwrap(), None);
+        assert_eq!(store.get("2450#1111111").unwrap(), Some(credential("c")));
+
+        assert_matches!(
+            store.rename("2450#1111111", "2450#7654321"),
+            Err(CredentialError::AlreadyExists(_))
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn id_format() {
+        assert_eq!(credential_id("2450", "0123456"), "2450#0123456");
+    }
+}
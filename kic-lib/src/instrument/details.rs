@@ -0,0 +1,451 @@
+//! Detailed information about an instrument beyond its identification: installed
+//! options, modules, LAN identity, TSP-Link nodes and calibration dates.
+
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Result,
+    instrument::{
+        info::InstrumentInfo,
+        query::{parse_integer, tagged_value},
+        read_until, Info,
+    },
+};
+
+const DETAIL_TAG: &str = "DETAIL>";
+const DETAIL_END: &str = "DETAIL>END";
+
+/// The attributes that are read from the instrument, as `(key, expression)` pairs.
+/// Attributes that differ between families are listed once per family under the same
+/// key, the first one the instrument provides is used.
+const DETAIL_QUERIES: &[(&str, &str)] = &[
+    ("options", "localnode.options"),
+    ("lan.mac", "lan.status.macaddress"),
+    ("lan.mac", "lan.macaddress"),
+    ("lan.hostname", "lan.status.dns.name"),
+    ("lan.hostname", "lan.hostname"),
+    ("lan.ip", "lan.status.ipaddress"),
+    ("lan.subnet", "lan.status.subnetmask"),
+    ("lan.gateway", "lan.status.gateway"),
+    ("lan.method", "lan.config.method"),
+    ("lan.ipconfig", "lan.ipconfig()"),
+    ("calibration.last", "smua.cal.date"),
+    ("calibration.due", "smua.cal.due"),
+    ("calibration.last", "smu.cal.date"),
+    ("calibration.due", "smu.cal.due"),
+    ("calibration.last", "dmm.adjustment.date"),
+];
+
+/// A module installed in a slot of a mainframe.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    /// The slot number
+    pub slot: u16,
+    /// The model of the module
+    pub model: String,
+    /// The serial number of the module
+    pub serial_number: Option<String>,
+    /// The firmware revision of the module
+    pub firmware_rev: Option<String>,
+}
+
+/// An instrument on the TSP-Link network.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    /// The TSP-Link node number
+    pub node: u16,
+    /// The model of the instrument
+    pub model: String,
+    /// The serial number of the instrument
+    pub serial_number: Option<String>,
+    /// The firmware revision of the instrument
+    pub firmware_rev: Option<String>,
+}
+
+/// The LAN identity and configuration of an instrument.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct LanInfo {
+    /// The MAC address
+    pub mac_address: Option<String>,
+    /// The host name
+    pub hostname: Option<String>,
+    /// The IP address
+    pub ip_address: Option<String>,
+    /// The subnet mask
+    pub subnet_mask: Option<String>,
+    /// The default gateway
+    pub gateway: Option<String>,
+    /// How the IP address is configured (e.g. `auto` or `manual`)
+    pub method: Option<String>,
+}
+
+/// The calibration dates of an instrument.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct CalibrationInfo {
+    /// The date of the last calibration
+    pub last: Option<String>,
+    /// The date the next calibration is due
+    pub due: Option<String>,
+}
+
+/// Everything that could be read about an instrument. Items the instrument does not
+/// provide are left empty.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct DetailedInfo {
+    /// The identification of the instrument
+    #[serde(flatten)]
+    pub info: InstrumentInfo,
+    /// The installed options
+    pub options: Vec<String>,
+    /// The modules installed in the slots of the instrument
+    pub modules: Vec<ModuleInfo>,
+    /// The LAN identity and configuration
+    pub lan: Option<LanInfo>,
+    /// The instruments on the TSP-Link network, including this one
+    pub tsplink_nodes: Vec<NodeInfo>,
+    /// The calibration dates
+    pub calibration: Option<CalibrationInfo>,
+}
+
+impl DetailedInfo {
+    /// The module installed in the given slot, if there is one.
+    #[must_use]
+    pub fn module(&self, slot: u16) -> Option<&ModuleInfo> {
+        self.modules.iter().find(|m| m.slot == slot)
+    }
+}
+
+impl Display for ModuleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "slot[{}]: {}", self.slot, self.model)?;
+        if let Some(sn) = &self.serial_number {
+            write!(f, ", serial {sn}")?;
+        }
+        if let Some(fw) = &self.firmware_rev {
+            write!(f, ", firmware {fw}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for NodeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "node[{}]: {}", self.node, self.model)?;
+        if let Some(sn) = &self.serial_number {
+            write!(f, ", serial {sn}")?;
+        }
+        if let Some(fw) = &self.firmware_rev {
+            write!(f, ", firmware {fw}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for DetailedInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.info)?;
+        if !self.options.is_empty() {
+            writeln!(f, "Options: {}", self.options.join(", "))?;
+        }
+        for m in &self.modules {
+            writeln!(f, "{m}")?;
+        }
+        if let Some(lan) = &self.lan {
+            let fields = [
+                ("MAC address", &lan.mac_address),
+                ("Host name", &lan.hostname),
+                ("IP address", &lan.ip_address),
+                ("Subnet mask", &lan.subnet_mask),
+                ("Gateway", &lan.gateway),
+                ("IP configuration", &lan.method),
+            ];
+            for (name, value) in fields {
+                if let Some(value) = value {
+                    writeln!(f, "{name}: {value}")?;
+                }
+            }
+        }
+        for n in &self.tsplink_nodes {
+            writeln!(f, "{n}")?;
+        }
+        if let Some(cal) = &self.calibration {
+            if let Some(last) = &cal.last {
+                writeln!(f, "Last calibration: {last}")?;
+            }
+            if let Some(due) = &cal.due {
+                writeln!(f, "Calibration due: {due}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert a date given by the instrument, in seconds since the epoch, to a date
+/// string. Values that are not timestamps are kept as they are and a value of 0,
+/// which instruments use for "never", is dropped.
+fn to_date(value: &str) -> Option<String> {
+    let Ok(secs) = value.trim().parse::<f64>() else {
+        return Some(value.to_string());
+    };
+    if secs <= 0.0 {
+        return None;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|d| d.date_naive().to_string())
+        .or_else(|| Some(value.to_string()))
+}
+
+/// Format a LAN configuration method given as `lan.AUTO` (0) or `lan.MANUAL` (1).
+fn lan_method(value: &str) -> String {
+    match value.trim() {
+        "0" | "0.00000e+00" => "auto".to_string(),
+        "1" | "1.00000e+00" => "manual".to_string(),
+        v => v.trim_start_matches("lan.").to_lowercase(),
+    }
+}
+
+/// Parse the output of the detail query into a map of keys to values. Only the first
+/// value of each key is kept.
+fn parse_details(output: &str) -> BTreeMap<String, String> {
+    let mut details = BTreeMap::new();
+    for (key, value) in output
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix(DETAIL_TAG))
+        .filter_map(|l| l.split_once('\t'))
+    {
+        details
+            .entry(key.to_string())
+            .or_insert_with(|| value.trim().to_string());
+    }
+    details
+}
+
+/// Collect the modules or nodes stored under `{prefix}.{n}.model`,
+/// `{prefix}.{n}.serial` and `{prefix}.{n}.firmware`.
+fn collect_numbered(details: &BTreeMap<String, String>, prefix: &str) -> Vec<NodeInfo> {
+    let mut items: BTreeMap<u16, NodeInfo> = BTreeMap::new();
+    for (key, value) in details {
+        let Some(rest) = key.strip_prefix(prefix).and_then(|r| r.strip_prefix('.')) else {
+            continue;
+        };
+        let Some((n, field)) = rest.split_once('.') else {
+            continue;
+        };
+        let Some(n) = parse_integer(n, 0..=u16::MAX) else {
+            continue;
+        };
+        let item = items.entry(n).or_insert_with(|| NodeInfo {
+            node: n,
+            ..NodeInfo::default()
+        });
+        match field {
+            "model" => item.model.clone_from(value),
+            "serial" => item.serial_number = Some(value.clone()),
+            "firmware" => item.firmware_rev = Some(value.clone()),
+            _ => {}
+        }
+    }
+    items
+        .into_values()
+        .filter(|i| !i.model.is_empty())
+        .collect()
+}
+
+/// Build the [`DetailedInfo`] from the identification of the instrument and the
+/// parsed details.
+fn to_detailed_info(info: InstrumentInfo, details: &BTreeMap<String, String>) -> DetailedInfo {
+    let get = |key: &str| details.get(key).cloned();
+
+    let options = get("options")
+        .map(|o| {
+            o.split([',', ';'])
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let modules = collect_numbered(details, "slot")
+        .into_iter()
+        .filter(|m| !m.model.eq_ignore_ascii_case("empty"))
+        .map(|m| ModuleInfo {
+            slot: m.node,
+            model: m.model,
+            serial_number: m.serial_number,
+            firmware_rev: m.firmware_rev,
+        })
+        .collect();
+
+    // `lan.ipconfig()` returns the method, IP address, subnet mask and gateway.
+    let ipconfig: Vec<String> = get("lan.ipconfig")
+        .map(|c| c.split(',').map(|v| v.trim().to_string()).collect())
+        .unwrap_or_default();
+    let ipconfig = |i: usize| ipconfig.get(i).filter(|v| !v.is_empty()).cloned();
+    let lan = LanInfo {
+        mac_address: get("lan.mac"),
+        hostname: get("lan.hostname"),
+        ip_address: get("lan.ip").or_else(|| ipconfig(1)),
+        subnet_mask: get("lan.subnet").or_else(|| ipconfig(2)),
+        gateway: get("lan.gateway").or_else(|| ipconfig(3)),
+        method: get("lan.method")
+            .or_else(|| ipconfig(0))
+            .map(|m| lan_method(&m)),
+    };
+    let lan = (lan != LanInfo::default()).then_some(lan);
+
+    let calibration = CalibrationInfo {
+        last: get("calibration.last").and_then(|d| to_date(&d)),
+        due: get("calibration.due").and_then(|d| to_date(&d)),
+    };
+    let calibration = (calibration != CalibrationInfo::default()).then_some(calibration);
+
+    DetailedInfo {
+        info,
+        options,
+        modules,
+        lan,
+        tsplink_nodes: collect_numbered(details, "node"),
+        calibration,
+    }
+}
+
+/// Read the [`DetailedInfo`] of the instrument.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the queries could not be written or read.
+pub fn query_details<T: Info + ?Sized>(inst: &mut T) -> Result<DetailedInfo> {
+    let info = inst.info()?;
+    info!("Reading detailed information of {info}");
+
+    let mut queries: Vec<(String, String)> = DETAIL_QUERIES
+        .iter()
+        .map(|(k, e)| ((*k).to_string(), (*e).to_string()))
+        .collect();
+    for slot in 1..=info.model.slots() {
+        queries.push((format!("slot.{slot}.model"), format!("slot[{slot}].model")));
+        queries.push((
+            format!("slot.{slot}.serial"),
+            format!("slot[{slot}].serialno"),
+        ));
+        queries.push((
+            format!("slot.{slot}.firmware"),
+            format!("slot[{slot}].revision"),
+        ));
+    }
+    debug!("Reading {} attributes", queries.len());
+
+    for (key, expr) in &queries {
+        inst.write_all(tagged_value(DETAIL_TAG, key, expr).as_bytes())?;
+    }
+    // Nodes that are not on the TSP-Link network raise an error when accessed.
+    inst.write_all(
+        format!(
+            "do pcall(function() if tsplink == nil or tsplink.state ~= \"online\" then return end for i = 1, 64 do local ok, n = pcall(function() return node[i] end) if ok and n ~= nil then for _, f in ipairs({{{{\"model\", \"model\"}}, {{\"serial\", \"serialno\"}}, {{\"firmware\", \"revision\"}}}}) do local fok, v = pcall(function() return n[f[2]] end) if fok and v ~= nil then print(\"{DETAIL_TAG}node.\" .. i .. \".\" .. f[1] .. \"\\t\" .. tostring(v)) end end end end end) end\n"
+        )
+        .as_bytes(),
+    )?;
+    inst.write_all(format!("print(\"{DETAIL_END}\")\n").as_bytes())?;
+    inst.flush()?;
+
+    let output = read_until(
+        inst,
+        &[DETAIL_END.to_string()],
+        10_000,
+        Duration::from_millis(1),
+    )?;
+
+    Ok(to_detailed_info(info, &parse_details(&output)))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{parse_details, to_detailed_info, CalibrationInfo, LanInfo, ModuleInfo};
+    use crate::instrument::info::InstrumentInfo;
+
+    #[test]
+    fn detailed_info_2600() {
+        let output = "DETAIL>lan.mac\t08:00:11:22:33:44\n\
+                      DETAIL>lan.hostname\tk-2636b-0123456\n\
+                      DETAIL>lan.ip\t192.168.0.2\n\
+                      DETAIL>lan.subnet\t255.255.255.0\n\
+                      DETAIL>lan.gateway\t192.168.0.1\n\
+                      DETAIL>lan.method\t0.00000e+00\n\
+                      DETAIL>calibration.last\t1.60000e+09\n\
+                      DETAIL>calibration.due\t0.00000e+00\n\
+                      DETAIL>node.2.model\t2636B\n\
+                      DETAIL>node.2.serial\t0123456\n\
+                      DETAIL>node.2.firmware\t4.0.5\n\
+                      DETAIL>node.1.model\t2602B\n\
+                      DETAIL>END";
+        let details = to_detailed_info(InstrumentInfo::default(), &parse_details(output));
+        assert_eq!(
+            details.lan,
+            Some(LanInfo {
+                mac_address: Some("08:00:11:22:33:44".to_string()),
+                hostname: Some("k-2636b-0123456".to_string()),
+                ip_address: Some("192.168.0.2".to_string()),
+                subnet_mask: Some("255.255.255.0".to_string()),
+                gateway: Some("192.168.0.1".to_string()),
+                method: Some("auto".to_string()),
+            })
+        );
+        assert_eq!(
+            details.calibration,
+            Some(CalibrationInfo {
+                last: Some("2020-09-13".to_string()),
+                due: None,
+            })
+        );
+        assert_eq!(
+            details
+                .tsplink_nodes
+                .iter()
+                .map(|n| (n.node, n.model.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(1, "2602B"), (2, "2636B")]
+        );
+        assert_eq!(
+            details.tsplink_nodes[1].firmware_rev,
+            Some("4.0.5".to_string())
+        );
+        assert!(details.modules.is_empty());
+        assert!(details.options.is_empty());
+    }
+
+    #[test]
+    fn detailed_info_modules() {
+        let output = "DETAIL>lan.ipconfig\tlan.AUTO,10.0.0.5,255.0.0.0,10.0.0.1\n\
+                      DETAIL>slot.1.model\tMSMU60-2\n\
+                      DETAIL>slot.1.serial\t9876\n\
+                      DETAIL>slot.2.model\tEmpty\n\
+                      DETAIL>options\tOPT1, OPT2\n\
+                      DETAIL>END";
+        let details = to_detailed_info(InstrumentInfo::default(), &parse_details(output));
+        assert_eq!(
+            details.modules,
+            vec![ModuleInfo {
+                slot: 1,
+                model: "MSMU60-2".to_string(),
+                serial_number: Some("9876".to_string()),
+                firmware_rev: None,
+            }]
+        );
+        assert_eq!(details.module(1).map(|m| m.slot), Some(1));
+        assert!(details.module(2).is_none());
+        assert_eq!(
+            details.lan.and_then(|l| l.ip_address),
+            Some("10.0.0.5".to_string())
+        );
+        assert_eq!(details.options, vec!["OPT1", "OPT2"]);
+        assert_eq!(details.calibration, None);
+    }
+}
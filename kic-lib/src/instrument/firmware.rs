@@ -1,4 +1,23 @@
-use crate::error::Result;
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use tracing::trace;
+
+use crate::{error::Result, instrument::write_chunk, InstrumentError};
+
+/// The number of bytes of a firmware image written to the instrument at a time.
+pub const FW_CHUNK_SIZE: usize = 1024;
+
+/// The minimum amount of time between two machine-readable progress reports.
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
 
 /// The trait an instrument must implement in order to flash the firmware onto an
 /// instrument.
@@ -9,4 +28,310 @@ pub trait Flash {
     /// An error can occur in the write to or reading from the instrument as well as in
     /// reading the firmware image.
     fn flash_firmware(&mut self, image: &[u8], firmware_info: Option<u16>) -> Result<()>;
+
+    /// Flash a firmware image to an instrument, reporting progress and honoring
+    /// cancellation as described by `options`.
+    ///
+    /// Instruments that do not support progress reporting or cancellation simply
+    /// call [`Flash::flash_firmware`].
+    ///
+    /// # Errors
+    /// The same errors as [`Flash::flash_firmware`] as well as
+    /// [`InstrumentError::FwUpgradeCancelled`] if the transfer was cancelled before
+    /// it was completed.
+    fn flash_firmware_with(
+        &mut self,
+        image: &[u8],
+        firmware_info: Option<u16>,
+        options: &FlashOptions,
+    ) -> Result<()> {
+        let _ = options;
+        self.flash_firmware(image, firmware_info)
+    }
+}
+
+/// How progress of a firmware transfer is reported to the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// A progress bar drawn on stderr for interactive use.
+    #[default]
+    Bar,
+
+    /// One JSON object per line on stdout, intended to be consumed by other programs.
+    Json,
+
+    /// No progress is reported.
+    None,
+}
+
+/// A handle that can be used to cancel a firmware transfer from another thread.
+///
+/// Cancellation is only possible while the image is being transferred. Once the
+/// instrument has been told that the transfer is complete, the upgrade can no longer
+/// be stopped.
+#[derive(Debug, Clone, Default)]
+pub struct FlashCancel(Arc<AtomicBool>);
+
+impl FlashCancel {
+    /// Request that the associated firmware transfer be stopped.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether cancellation has been requested.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Options that control how a firmware image is transferred.
+#[derive(Debug, Clone, Default)]
+pub struct FlashOptions {
+    /// How progress should be reported.
+    pub progress: ProgressFormat,
+
+    /// The handle used to cancel the transfer.
+    pub cancel: FlashCancel,
+}
+
+/// A single machine-readable progress report, written to stdout as one line of JSON so
+/// that it is not mixed with the messages on stderr.
+#[derive(Debug, Serialize)]
+struct ProgressReport {
+    event: &'static str,
+    sent: usize,
+    total: usize,
+    bytes_per_sec: u64,
+    eta_secs: Option<u64>,
+}
+
+/// Keeps track of, and reports, the progress of a transfer to an instrument.
+pub(crate) struct TransferProgress {
+    format: ProgressFormat,
+    total: usize,
+    sent: usize,
+    start: Instant,
+    last_report: Option<Instant>,
+    bar: Option<ProgressBar>,
+    /// Where JSON reports are written
+    output: Box<dyn Write>,
+}
+
+impl TransferProgress {
+    pub(crate) fn new(total: usize, format: ProgressFormat, message: &'static str) -> Self {
+        Self::with_output(total, format, message, Box::new(std::io::stdout()))
+    }
+
+    fn with_output(
+        total: usize,
+        format: ProgressFormat,
+        message: &'static str,
+        output: Box<dyn Write>,
+    ) -> Self {
+        let bar = if format == ProgressFormat::Bar {
+            let pb = ProgressBar::new(total as u64);
+            #[allow(clippy::literal_string_with_formatting_args)]
+            // This is a template for ProgressStyle that requires this syntax
+            pb.set_style(
+                ProgressStyle::with_template(
+                    " {spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta}) {msg}",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            pb.set_message(message);
+            Some(pb)
+        } else {
+            None
+        };
+
+        Self {
+            format,
+            total,
+            sent: 0,
+            start: Instant::now(),
+            last_report: None,
+            bar,
+            output,
+        }
+    }
+
+    pub(crate) fn advance(&mut self, bytes: usize) {
+        self.sent = self.sent.saturating_add(bytes);
+        if let Some(pb) = &self.bar {
+            pb.set_position(self.sent as u64);
+        }
+        let due = self
+            .last_report
+            .is_none_or(|last| last.elapsed() >= REPORT_INTERVAL);
+        if due || self.sent == self.total {
+            self.report("progress");
+        }
+    }
+
+    pub(crate) fn report(&mut self, event: &'static str) {
+        self.last_report = Some(Instant::now());
+        if self.format != ProgressFormat::Json {
+            return;
+        }
+        let elapsed = self.start.elapsed().as_secs_f64();
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let bytes_per_sec = if elapsed > 0.0 {
+            (self.sent as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let eta_secs = if bytes_per_sec > 0 {
+            Some((self.total.saturating_sub(self.sent) as u64).div_ceil(bytes_per_sec))
+        } else {
+            None
+        };
+        let report = ProgressReport {
+            event,
+            sent: self.sent,
+            total: self.total,
+            bytes_per_sec,
+            eta_secs,
+        };
+        if let Ok(line) = serde_json::to_string(&report) {
+            let _ = writeln!(self.output, "{line}");
+            let _ = self.output.flush();
+        }
+    }
+
+    pub(crate) fn finish(mut self, event: &'static str, msg: &'static str) {
+        if let Some(pb) = self.bar.take() {
+            pb.abandon_with_message(msg);
+        }
+        self.report(event);
+    }
+}
+
+/// Transfer a firmware `image` to `writer` in chunks of `chunk_size` bytes, reporting
+/// progress and checking for cancellation between each chunk.
+///
+/// # Errors
+/// Returns [`InstrumentError::FwUpgradeCancelled`] if `options.cancel` was triggered
+/// before the full image was written, or any IO error raised while writing.
+pub(crate) fn transfer_image<W: Write + ?Sized>(
+    writer: &mut W,
+    image: &[u8],
+    chunk_size: usize,
+    options: &FlashOptions,
+) -> Result<()> {
+    let mut progress = TransferProgress::new(image.len(), options.progress, "Loading Firmware...");
+    progress.report("start");
+    for chunk in image.chunks(chunk_size.max(1)) {
+        if options.cancel.is_cancelled() {
+            trace!(
+                "Firmware transfer cancelled after {} of {} bytes",
+                progress.sent,
+                progress.total
+            );
+            progress.finish("cancelled", "Firmware transfer cancelled.");
+            return Err(InstrumentError::FwUpgradeCancelled);
+        }
+        if let Err(e) = write_chunk(writer, chunk) {
+            progress.finish("error", "Firmware transfer failed.");
+            return Err(e);
+        }
+        progress.advance(chunk.len());
+    }
+    progress.finish("complete", "Firmware file transferred.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{transfer_image, FlashOptions, ProgressFormat, TransferProgress};
+    use crate::InstrumentError;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+
+    struct Chunks(Vec<Vec<u8>>, usize);
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // Block every other write to exercise the retry path.
+            self.1 += 1;
+            if self.1 % 2 == 0 {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transfer_image_chunks() {
+        let mut w = Chunks(Vec::new(), 0);
+        let options = FlashOptions {
+            progress: ProgressFormat::None,
+            ..FlashOptions::default()
+        };
+        transfer_image(&mut w, &[1, 2, 3, 4, 5], 2, &options).unwrap();
+        assert_eq!(w.0, vec![vec![1, 2], vec![3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn transfer_image_cancelled() {
+        let mut w = Chunks(Vec::new(), 0);
+        let options = FlashOptions {
+            progress: ProgressFormat::None,
+            ..FlashOptions::default()
+        };
+        options.cancel.cancel();
+        assert_matches!(
+            transfer_image(&mut w, &[1, 2, 3, 4, 5], 2, &options),
+            Err(InstrumentError::FwUpgradeCancelled)
+        );
+        assert!(w.0.is_empty());
+    }
+
+    /// A writer whose content can be read after it was moved into a [`TransferProgress`].
+    #[derive(Clone, Default)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_progress() {
+        let output = Shared::default();
+        let mut progress =
+            TransferProgress::with_output(5, ProgressFormat::Json, "", Box::new(output.clone()));
+        progress.report("start");
+        progress.advance(2);
+        progress.advance(3);
+        progress.finish("complete", "");
+
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let reports: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let events: Vec<(&str, u64)> = reports
+            .iter()
+            .map(|r| (r["event"].as_str().unwrap(), r["sent"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(events, vec![("start", 0), ("progress", 5), ("complete", 5)]);
+        assert!(reports.iter().all(|r| r["total"] == 5));
+    }
 }
@@ -3,6 +3,7 @@ use tracing::{debug, instrument};
 
 use crate::{
     error::Result,
+    instrument::details::{query_details, DetailedInfo},
     model::{Model, Vendor},
     InstrumentError,
 };
@@ -76,6 +77,18 @@ pub trait Info: Read + Write {
     fn info(&mut self) -> Result<InstrumentInfo> {
         get_info(self)
     }
+
+    /// Get the detailed information for the instrument: installed options, modules,
+    /// LAN identity, TSP-Link nodes and calibration dates.
+    ///
+    /// Prompts should be disabled and the output queue should be empty before this
+    /// is called.
+    ///
+    /// # Errors
+    /// [`InstrumentError`] if the information could not be written or read.
+    fn detailed_info(&mut self) -> Result<DetailedInfo> {
+        query_details(self)
+    }
 }
 
 impl TryFrom<&[u8]> for InstrumentInfo {
@@ -221,7 +234,7 @@ KEITHLEY INSTRUMENTS,MODEL 2461,04331961,1.7.12b
 TSP>";
         let expected = InstrumentInfo {
             vendor: Vendor::Keithley,
-            model: Model::_2461,
+            model: "2461".parse::<Model>().unwrap(),
             serial_number: "04331961".to_string(),
             firmware_rev: Some("1.7.12b".to_string()),
         };
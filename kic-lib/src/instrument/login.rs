@@ -96,4 +96,28 @@ pub trait Login {
     fn login(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Relinquish the login held by this interface so the instrument is locked again.
+    ///
+    /// # Default `impl`
+    /// The default implementation does nothing and always returns `Ok(())`.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if any errors occurred.
+    fn logout(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Describe the session or interface that holds the login when
+    /// [`Login::check_login`] returns [`State::LogoutNeeded`], if the instrument
+    /// reports it.
+    ///
+    /// # Default `impl`
+    /// The default implementation always returns `Ok(None)`.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if any errors occurred.
+    fn lock_holder(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
 }
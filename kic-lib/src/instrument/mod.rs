@@ -2,16 +2,23 @@
 
 pub mod abort;
 pub mod authenticate;
+pub mod backup;
+pub mod credentials;
+pub mod details;
 pub mod firmware;
 pub mod info;
 pub mod language;
 pub mod login;
+pub(crate) mod query;
 pub mod reset;
 pub mod script;
+pub mod snapshot;
+pub mod tsplink;
+pub mod variables;
 
 use std::{
-    io::{Read, Write},
-    time::Duration,
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
 };
 
 use crate::interface::NonBlock;
@@ -22,7 +29,7 @@ pub use info::Info;
 pub use language::{CmdLanguage, Language};
 pub use login::{Login, State};
 pub use reset::Reset;
-pub use script::Script;
+pub use script::{Script, ScriptInfo, ScriptOptions};
 use tracing::{debug, trace};
 
 /// A marker trait that defines the traits any [`Instrument`] needs to have.
@@ -70,6 +77,52 @@ pub fn read_until<T: Read + Write + ?Sized>(
     Err(InstrumentError::Other(String::default()))
 }
 
+/// How long a write may make no progress before it fails with [`ErrorKind::TimedOut`].
+pub(crate) const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Write `chunk` to `writer` in its entirety, retrying while the writer would block.
+///
+/// # Errors
+/// Returns an IO error of kind [`ErrorKind::TimedOut`] if no part of `chunk` could be
+/// written for [`WRITE_TIMEOUT`], or any other error raised while writing.
+pub(crate) fn write_chunk<W: Write + ?Sized>(writer: &mut W, chunk: &[u8]) -> Result<()> {
+    write_chunk_within(writer, chunk, WRITE_TIMEOUT)
+}
+
+fn write_chunk_within<W: Write + ?Sized>(
+    writer: &mut W,
+    mut chunk: &[u8],
+    timeout: Duration,
+) -> Result<()> {
+    let mut progressed = Instant::now();
+    while !chunk.is_empty() {
+        match writer.write(chunk) {
+            Ok(0) => {
+                return Err(std::io::Error::from(ErrorKind::WriteZero).into());
+            }
+            Ok(n) => {
+                chunk = &chunk[n..];
+                progressed = Instant::now();
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {
+                if progressed.elapsed() >= timeout {
+                    return Err(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!(
+                            "the instrument did not accept any data for {} s",
+                            timeout.as_secs_f32()
+                        ),
+                    )
+                    .into());
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
 /// Read from a 'rw' until we are sure we have cleared the output queue.
 ///
 /// # Warning
@@ -112,3 +165,41 @@ pub fn clear_output_queue<T: Read + Write + ?Sized>(
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod unit {
+    use std::{
+        io::{ErrorKind, Write},
+        time::Duration,
+    };
+
+    use assert_matches::assert_matches;
+
+    use super::write_chunk_within;
+    use crate::InstrumentError;
+
+    /// A transport that never takes any data.
+    struct Stalled;
+
+    impl Write for Stalled {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stalled_write() {
+        assert_matches!(
+            write_chunk_within(&mut Stalled, b"print(1)\n", Duration::from_millis(50)),
+            Err(InstrumentError::IoError { source }) if source.kind() == ErrorKind::TimedOut
+        );
+        assert_matches!(
+            write_chunk_within(&mut Stalled, b"", Duration::from_millis(50)),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Helpers to query values from an instrument by printing them on tagged lines that
+//! can be told apart from any other output.
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use crate::{
+    error::Result,
+    instrument::{read_until, Script},
+};
+
+/// Write `tsp`, which should print lines prefixed with `{tag}>` followed by
+/// `{tag}>END`, and return the content of the tagged lines.
+pub(crate) fn query_tagged<T: Script + ?Sized>(
+    inst: &mut T,
+    tsp: &str,
+    tag: &str,
+) -> Result<Vec<String>> {
+    let prefix = format!("{tag}>");
+    let end = format!("{tag}>END");
+    inst.write_all(format!("{tsp}\n").as_bytes())?;
+    inst.flush()?;
+    let output = read_until(
+        inst,
+        std::slice::from_ref(&end),
+        5000,
+        Duration::from_millis(1),
+    )?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|l| *l != end)
+        .filter_map(|l| l.strip_prefix(&prefix))
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Get a TSP statement that prints the value of the expression `expr` as
+/// `{tag}{key}<tab>{value}` on a single line, or prints nothing if `expr` raises an
+/// error or is `nil`. All the values returned by a function call are printed,
+/// separated by commas.
+pub(crate) fn tagged_value(tag: &str, key: &str, expr: &str) -> String {
+    let expr = if expr.ends_with(')') {
+        format!("table.concat({{{expr}}}, \",\")")
+    } else {
+        expr.to_string()
+    };
+    format!(
+        "do local ok, v = pcall(function() return {expr} end) if ok and v ~= nil then print(\"{tag}{key}\\t\" .. (string.gsub(tostring(v), \"[\\r\\n]+\", \"; \"))) end end\n"
+    )
+}
+
+/// Parse a whole number given by the instrument (e.g. `2.00000e+00`) that is within
+/// `range`.
+pub(crate) fn parse_integer(value: &str, range: RangeInclusive<u16>) -> Option<u16> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| {
+            n.fract() == 0.0 && (f64::from(*range.start())..=f64::from(*range.end())).contains(n)
+        })
+        .map(|n| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let n = n as u16;
+            n
+        })
+}
+
+#[cfg(test)]
+mod unit {
+    use super::parse_integer;
+
+    #[test]
+    fn integer_parsing() {
+        assert_eq!(parse_integer("2.00000e+00", 1..=64), Some(2));
+        assert_eq!(parse_integer(" 64 ", 1..=64), Some(64));
+        assert_eq!(parse_integer("65", 1..=64), None);
+        assert_eq!(parse_integer("0", 1..=64), None);
+        assert_eq!(parse_integer("1.5", 0..=u16::MAX), None);
+        assert_eq!(parse_integer("-1", 0..=u16::MAX), None);
+        assert_eq!(parse_integer("nil", 0..=u16::MAX), None);
+    }
+}
@@ -21,4 +21,9 @@ where
         //Do we need to wait?
         Ok(())
     }
+
+    /// Set whether the instrument is reset when it is disconnected, which it is by
+    /// default. Settings that should stay on the instrument, e.g. applied setups, are
+    /// kept by turning this off.
+    fn set_reset_on_drop(&mut self, reset: bool);
 }
@@ -1,10 +1,107 @@
 //! A trait that allows for the writing of a TSP script file to the instrument.
 
-use std::io::{BufRead, Read, Write};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bytes::Buf;
+use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::{
+    error::Result,
+    instrument::{
+        firmware::{ProgressFormat, TransferProgress},
+        read_until, write_chunk,
+    },
+    tsp::{is_name, minify, Minify},
+    InstrumentError,
+};
+
+const SCRIPT_TAG: &str = "SCRIPT>";
+const SCRIPT_END: &str = "SCRIPT>END";
+const SOURCE_START: &str = "SCRIPT>SOURCE_START";
+const SOURCE_END: &str = "SCRIPT>SOURCE_END";
+const SCRIPT_MISSING: &str = "SCRIPT>MISSING";
+
+/// The number of bytes of a script written to the instrument at a time.
+pub const SCRIPT_CHUNK_SIZE: usize = 1024;
+
+/// Options that control how a script is written to an instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptOptions {
+    /// The comments and whitespace that are removed before the script is written
+    pub minify: Minify,
+    /// The size of the largest script that can be written, after it is minified, e.g.
+    /// the script memory of the model
+    pub max_size: Option<usize>,
+    /// How progress should be reported
+    pub progress: ProgressFormat,
+}
+
+impl Default for ScriptOptions {
+    fn default() -> Self {
+        Self {
+            minify: Minify::default(),
+            max_size: None,
+            progress: ProgressFormat::None,
+        }
+    }
+}
+
+/// A script that is stored in the non-volatile memory of an instrument.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptInfo {
+    /// The name of the script
+    pub name: String,
+    /// Whether the script is run when the instrument powers on
+    pub autorun: bool,
+}
+
+/// Get a TSP expression that refers to the user script whose name is given by the TSP
+/// expression `key`.
+fn script_ref(key: &str) -> String {
+    format!("(script.user and script.user.scripts and script.user.scripts[{key}] or _G[{key}])")
+}
+
+/// Ensure that `name` can be safely used as the name of a script.
+pub(crate) fn validate_name(name: &str) -> Result<()> {
+    if is_name(name) {
+        Ok(())
+    } else {
+        Err(InstrumentError::Other(format!(
+            "\"{name}\" is not a valid script name"
+        )))
+    }
+}
+
+/// Parse the output of the script catalog query.
+fn parse_catalog(output: &str) -> Vec<ScriptInfo> {
+    output
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix(SCRIPT_TAG))
+        .filter(|l| *l != "END")
+        .filter_map(|l| {
+            let (name, autorun) = l.split_once('\t')?;
+            Some(ScriptInfo {
+                name: name.to_string(),
+                autorun: autorun.trim() == "true",
+            })
+        })
+        .collect()
+}
+
+/// Extract the source of a script from the output of the script source query.
+fn parse_source(output: &str) -> Option<String> {
+    let (_, rest) = output.split_once(SOURCE_START)?;
+    let (source, _) = rest.rsplit_once(SOURCE_END)?;
+    let source = source.strip_prefix("\r\n").unwrap_or(source);
+    let source = source.strip_prefix('\n').unwrap_or(source);
+    let source = source.strip_suffix('\n').unwrap_or(source);
+    let source = source.strip_suffix('\r').unwrap_or(source);
+    Some(source.to_string())
+}
 
 /// The [`Instrument`] can write a script to be executed.
 pub trait Script
@@ -33,33 +130,375 @@ where
         script: &[u8],
         save_script: bool,
         run_script: bool,
+    ) -> Result<()> {
+        self.write_script_with(
+            name,
+            script,
+            save_script,
+            run_script,
+            &ScriptOptions::default(),
+        )
+    }
+
+    /// Write the given script to the instrument like [`Script::write_script`], minifying
+    /// it and reporting progress as described by `options`.
+    ///
+    /// The script is written in chunks of [`SCRIPT_CHUNK_SIZE`] bytes, waiting while
+    /// the instrument can't take more.
+    ///
+    /// # Errors
+    /// The same errors as [`Script::write_script`], or an [`InstrumentError`] if the
+    /// script is larger than `options.max_size`, in which case nothing is written.
+    fn write_script_with(
+        &mut self,
+        name: &[u8],
+        script: &[u8],
+        save_script: bool,
+        run_script: bool,
+        options: &ScriptOptions,
     ) -> Result<()> {
         // Truncate name otherwise we risk a Fatal Error (NS-2201)
         let name = String::from_utf8_lossy(Buf::take(name, 31).chunk()).to_string();
-        let mut script = script.reader(); //String::from_utf8_lossy(script.as_ref()).to_string();
-        self.write_all(b"_orig_prompts = localnode.prompts localnode.prompts = 0\n")?;
+        let minified;
+        let script = if options.minify.is_none() {
+            script
+        } else {
+            minified = minify(&String::from_utf8_lossy(script), options.minify);
+            minified.as_bytes()
+        };
+        if let Some(max_size) = options.max_size.filter(|m| script.len() > *m) {
+            return Err(InstrumentError::Other(format!(
+                "the script is {} bytes, but the instrument only has {max_size} bytes of script memory",
+                script.len()
+            )));
+        }
+
+        write_chunk(
+            self,
+            b"_orig_prompts = localnode.prompts localnode.prompts = 0\n",
+        )?;
         self.flush()?;
-        self.write_all(format!("{name}=nil\n").as_bytes())?;
+        write_chunk(self, format!("{name}=nil\n").as_bytes())?;
         self.flush()?;
-        self.write_all(format!("loadscript {name}\n").as_bytes())?;
+        write_chunk(self, format!("loadscript {name}\n").as_bytes())?;
 
-        self.write_all(script.fill_buf().unwrap())?;
-        self.write_all(b"\nendscript\n")?;
+        let mut progress =
+            TransferProgress::new(script.len(), options.progress, "Loading script...");
+        progress.report("start");
+        for chunk in script.chunks(SCRIPT_CHUNK_SIZE) {
+            if let Err(e) = write_chunk(self, chunk) {
+                progress.finish("error", "Script transfer failed.");
+                // Leave `loadscript` mode, drop the partial script and turn the prompts
+                // back on, so that the instrument can be used again.
+                let _ = write_chunk(self, format!("\nendscript\n{name}=nil\n").as_bytes());
+                let _ = write_chunk(
+                    self,
+                    b"localnode.prompts = _orig_prompts _orig_prompts = nil\n",
+                );
+                let _ = self.flush();
+                return Err(e);
+            }
+            progress.advance(chunk.len());
+        }
+        progress.finish("complete", "Script transferred.");
+        write_chunk(self, b"\nendscript\n")?;
         self.flush()?;
 
         if save_script {
-            self.write_all(format!("{name}.save()\n").as_bytes())?;
+            write_chunk(self, format!("{name}.save()\n").as_bytes())?;
             self.flush()?;
         }
 
         if run_script {
-            self.write_all(format!("{name}.run()\n").as_bytes())?;
+            write_chunk(self, format!("{name}.run()\n").as_bytes())?;
             self.flush()?;
         }
 
-        self.write_all(b"localnode.prompts = _orig_prompts _orig_prompts = nil\n")?;
+        write_chunk(
+            self,
+            b"localnode.prompts = _orig_prompts _orig_prompts = nil\n",
+        )?;
+        self.flush()?;
+
+        Ok(())
+    }
+
+    /// Get the names of all the scripts saved on the instrument, using
+    /// `script.user.catalog()` or `script.catalog()`, whichever is available.
+    ///
+    /// # Notes
+    /// The output of the instrument is read until the end of the catalog is found, so
+    /// the output queue should be empty before this is called.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the catalog could not be written or read.
+    fn list_scripts(&mut self) -> Result<Vec<ScriptInfo>> {
+        self.write_all(
+            format!(
+                "do local c = nil if script.user and script.user.catalog then c = script.user.catalog() elseif script.catalog then c = script.catalog() end if c then for n in c do local s = {} local a = s and s.autorun print(\"{SCRIPT_TAG}\" .. n .. \"\\t\" .. tostring(a == \"yes\" or a == true)) end end print(\"{SCRIPT_END}\") end\n",
+                script_ref("n"),
+            )
+            .as_bytes(),
+        )?;
+        self.flush()?;
+        let output = read_until(
+            self,
+            &[SCRIPT_END.to_string()],
+            5000,
+            Duration::from_millis(1),
+        )?;
+        Ok(parse_catalog(&output))
+    }
+
+    /// Get the source code of the script with the given `name`.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the name is invalid, the script doesn't exist
+    /// on the instrument or the source could not be read.
+    fn script_source(&mut self, name: &str) -> Result<String> {
+        validate_name(name)?;
+        self.write_all(
+            format!(
+                "do local s = {} if s == nil or s.source == nil then print(\"{SCRIPT_MISSING}\") else print(\"{SOURCE_START}\") print(s.source) print(\"{SOURCE_END}\") end end\n",
+                script_ref(&format!("\"{name}\""))
+            )
+            .as_bytes(),
+        )?;
+        self.flush()?;
+        let output = read_until(
+            self,
+            &[SOURCE_END.to_string(), SCRIPT_MISSING.to_string()],
+            100_000,
+            Duration::from_millis(1),
+        )?;
+        parse_source(&output).ok_or_else(|| {
+            InstrumentError::Other(format!("script \"{name}\" was not found on the instrument"))
+        })
+    }
+
+    /// Save the source of the scripts with the given `names`, or of all the scripts
+    /// saved on the instrument if no names are given, to files named `<name>.tsp` in
+    /// `dir`, which is created if needed.
+    ///
+    /// Returns the paths of the files that were written.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if a script could not be read from the instrument
+    /// or a file could not be written.
+    fn pull_scripts(&mut self, names: &[String], dir: &Path) -> Result<Vec<PathBuf>> {
+        let names = if names.is_empty() {
+            self.list_scripts()?.into_iter().map(|s| s.name).collect()
+        } else {
+            names.to_vec()
+        };
+        std::fs::create_dir_all(dir)?;
+        let mut paths = Vec::new();
+        for name in names {
+            let source = self.script_source(&name)?;
+            let path = dir.join(format!("{name}.tsp"));
+            std::fs::write(&path, source)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Delete the script with the given `name` from the instrument.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the name is invalid or the command could not be
+    /// written.
+    fn delete_script(&mut self, name: &str) -> Result<()> {
+        validate_name(name)?;
+        self.write_all(format!("script.delete(\"{name}\")\n").as_bytes())?;
         self.flush()?;
+        Ok(())
+    }
 
+    /// Set whether the script with the given `name` runs when the instrument powers
+    /// on. The script is saved again so the change persists.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the name is invalid or the command could not be
+    /// written.
+    fn set_script_autorun(&mut self, name: &str, autorun: bool) -> Result<()> {
+        validate_name(name)?;
+        let autorun = if autorun { "yes" } else { "no" };
+        self.write_all(
+            format!(
+                "do local s = {} if s ~= nil then s.autorun = \"{autorun}\" s.save() end end\n",
+                script_ref(&format!("\"{name}\""))
+            )
+            .as_bytes(),
+        )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Run the script with the given `name` that is already on the instrument.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the name is invalid or the command could not be
+    /// written.
+    fn run_script(&mut self, name: &str) -> Result<()> {
+        validate_name(name)?;
+        self.write_all(format!("{}.run()\n", script_ref(&format!("\"{name}\""))).as_bytes())?;
+        self.flush()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod unit {
+    use std::io::{Read, Write};
+
+    use super::{
+        parse_catalog, parse_source, validate_name, Script, ScriptInfo, ScriptOptions,
+        SCRIPT_CHUNK_SIZE,
+    };
+    use crate::tsp::Minify;
+
+    /// Records the writes, blocking every other one like a slow transport.
+    #[derive(Default)]
+    struct Slow {
+        writes: Vec<Vec<u8>>,
+        calls: usize,
+        /// The first write that starts with this fails
+        fail_on: Option<&'static [u8]>,
+    }
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 2 == 0 {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            if self.fail_on.is_some_and(|f| buf.starts_with(f)) {
+                self.fail_on = None;
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Script for Slow {}
+
+    #[test]
+    fn chunked_upload() {
+        let line = "print(\"0123456789\") -- a comment\n";
+        let script = line.repeat(100);
+
+        // The writes between `loadscript` and `endscript` are the content of the script.
+        let content = |slow: &Slow| -> Vec<Vec<u8>> {
+            slow.writes
+                .iter()
+                .skip_while(|w| *w != b"loadscript big\n")
+                .skip(1)
+                .take_while(|w| *w != b"\nendscript\n")
+                .cloned()
+                .collect()
+        };
+
+        let mut slow = Slow::default();
+        slow.write_script(b"big", script.as_bytes(), false, false)
+            .unwrap();
+        let chunks = content(&slow);
+        assert!(chunks.iter().all(|c| c.len() <= SCRIPT_CHUNK_SIZE));
+        assert_eq!(chunks.len(), script.len().div_ceil(SCRIPT_CHUNK_SIZE));
+        assert_eq!(chunks.concat(), script.as_bytes());
+
+        let options = ScriptOptions {
+            minify: Minify::ALL,
+            ..ScriptOptions::default()
+        };
+        let mut slow = Slow::default();
+        slow.write_script_with(b"big", script.as_bytes(), false, false, &options)
+            .unwrap();
+        assert_eq!(
+            content(&slow).concat(),
+            "print(\"0123456789\")\n".repeat(100).into_bytes()
+        );
+
+        let options = ScriptOptions {
+            max_size: Some(1000),
+            ..ScriptOptions::default()
+        };
+        let mut slow = Slow::default();
+        assert!(slow
+            .write_script_with(b"big", script.as_bytes(), false, false, &options)
+            .is_err());
+        assert!(slow.writes.is_empty());
+    }
+
+    #[test]
+    fn failed_upload() {
+        let mut slow = Slow {
+            fail_on: Some(b"print"),
+            ..Slow::default()
+        };
+        assert!(slow
+            .write_script(b"failed", b"print(1)\n", false, true)
+            .is_err());
+        // The instrument leaves `loadscript` mode and its prompts are restored, but the
+        // partial script is not run.
+        let after: Vec<&[u8]> = slow
+            .writes
+            .iter()
+            .skip_while(|w| *w != b"loadscript failed\n")
+            .skip(1)
+            .map(Vec::as_slice)
+            .collect();
+        assert_eq!(
+            after,
+            [
+                &b"\nendscript\nfailed=nil\n"[..],
+                &b"localnode.prompts = _orig_prompts _orig_prompts = nil\n"[..]
+            ]
+        );
+    }
+
+    #[test]
+    fn catalog_parsing() {
+        let output = "SCRIPT>first\tfalse\nTSP>\nSCRIPT>second\ttrue\r\nSCRIPT>END";
+        assert_eq!(
+            parse_catalog(output),
+            vec![
+                ScriptInfo {
+                    name: "first".to_string(),
+                    autorun: false
+                },
+                ScriptInfo {
+                    name: "second".to_string(),
+                    autorun: true
+                },
+            ]
+        );
+        assert!(parse_catalog("SCRIPT>END").is_empty());
+    }
+
+    #[test]
+    fn source_parsing() {
+        let output = "SCRIPT>SOURCE_START\nprint(1)\nprint(2)\nSCRIPT>SOURCE_END";
+        assert_eq!(parse_source(output), Some("print(1)\nprint(2)".to_string()));
+        assert_eq!(parse_source("SCRIPT>MISSING"), None);
+    }
+
+    #[test]
+    fn script_names() {
+        assert!(validate_name("kic_script1").is_ok());
+        assert!(validate_name("_hidden").is_ok());
+        assert!(validate_name("1script").is_err());
+        assert!(validate_name("a\") os.exit(").is_err());
+        assert!(validate_name("").is_err());
+    }
+}
@@ -0,0 +1,421 @@
+//! Capture a canonical tree of the settings of an instrument and compare two such
+//! trees to find configuration drift between instruments.
+
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, info};
+
+use crate::{
+    error::Result,
+    instrument::{backup::BackupSource, query::tagged_value, read_until, Info},
+    model::Family,
+};
+
+const SNAPSHOT_TAG: &str = "SNAP>";
+const SNAPSHOT_END: &str = "SNAP>END";
+
+/// Settings that are read from every instrument.
+const COMMON_SETTINGS: &[&str] = &[
+    "beeper.enable",
+    "localnode.linefreq",
+    "localnode.prompts4882",
+    "localnode.showerrors",
+];
+
+/// Settings of 2600-series instruments. `{smu}` is replaced by each SMU channel.
+const SMU_26XX_SETTINGS: &[&str] = &[
+    "{smu}.source.func",
+    "{smu}.source.levelv",
+    "{smu}.source.leveli",
+    "{smu}.source.rangev",
+    "{smu}.source.rangei",
+    "{smu}.source.autorangev",
+    "{smu}.source.autorangei",
+    "{smu}.source.limitv",
+    "{smu}.source.limiti",
+    "{smu}.source.output",
+    "{smu}.source.offmode",
+    "{smu}.source.highc",
+    "{smu}.sense",
+    "{smu}.measure.nplc",
+    "{smu}.measure.rangev",
+    "{smu}.measure.rangei",
+    "{smu}.measure.autorangev",
+    "{smu}.measure.autorangei",
+    "{smu}.measure.autozero",
+    "{smu}.measure.count",
+    "{smu}.measure.delay",
+    "{smu}.measure.filter.enable",
+    "{smu}.measure.filter.count",
+    "{smu}.measure.filter.type",
+    "{smu}.trigger.count",
+    "{smu}.trigger.arm.count",
+    "{smu}.trigger.source.action",
+    "{smu}.trigger.measure.action",
+    "{smu}.trigger.endpulse.action",
+    "{smu}.trigger.endsweep.action",
+];
+
+/// Settings of 2600-series instruments that are not specific to a channel.
+const INSTRUMENT_26XX_SETTINGS: &[&str] = &[
+    "digio.writeprotect",
+    "lan.config.dns.hostname",
+    "lan.config.method",
+    "lan.config.ipaddress",
+    "lan.config.subnetmask",
+    "lan.config.gateway",
+    "lan.autoconnect",
+    "tsplink.node",
+];
+
+/// Settings of TTI instruments.
+const TTI_SETTINGS: &[&str] = &[
+    "smu.source.func",
+    "smu.source.level",
+    "smu.source.range",
+    "smu.source.autorange",
+    "smu.source.autodelay",
+    "smu.source.vlimit.level",
+    "smu.source.ilimit.level",
+    "smu.source.readback",
+    "smu.source.highc",
+    "smu.source.offmode",
+    "smu.measure.func",
+    "smu.measure.range",
+    "smu.measure.autorange",
+    "smu.measure.nplc",
+    "smu.measure.autozero.enable",
+    "smu.measure.sense",
+    "smu.measure.count",
+    "smu.measure.filter.enable",
+    "smu.measure.filter.count",
+    "smu.measure.filter.type",
+    "smu.terminals",
+    "dmm.measure.func",
+    "dmm.measure.range",
+    "dmm.measure.autorange",
+    "dmm.measure.nplc",
+    "dmm.measure.autozero.enable",
+    "dmm.measure.count",
+    "dmm.terminals",
+    "trigger.model.getblocklist()",
+    "digio.line[1].mode",
+    "digio.line[2].mode",
+    "digio.line[3].mode",
+    "digio.line[4].mode",
+    "digio.line[5].mode",
+    "digio.line[6].mode",
+    "lan.ipconfig()",
+    "tsplink.node",
+];
+
+/// Settings of 3706A and 70xB instruments.
+const INSTRUMENT_3700_SETTINGS: &[&str] = &[
+    "dmm.func",
+    "dmm.range",
+    "dmm.autorange",
+    "dmm.nplc",
+    "dmm.autozero",
+    "dmm.autodelay",
+    "dmm.filter.enable",
+    "dmm.filter.count",
+    "dmm.filter.type",
+    "scan.bypass",
+    "scan.mode",
+    "scan.measurecount",
+    "scan.scancount",
+    "digio.writeprotect",
+    "lan.config.method",
+    "lan.config.ipaddress",
+    "lan.config.subnetmask",
+    "lan.config.gateway",
+    "tsplink.node",
+];
+
+/// Settings of modular platform mainframes.
+const MODULAR_PLATFORM_SETTINGS: &[&str] = &[
+    "slot[1].model",
+    "slot[2].model",
+    "slot[3].model",
+    "lan.ipconfig()",
+    "tsplink.node",
+];
+
+/// The settings read from an instrument of the given `family`, as TSP expressions.
+#[must_use]
+pub fn settings_for(family: Option<&Family>) -> Vec<String> {
+    let mut settings: Vec<String> = COMMON_SETTINGS.iter().map(ToString::to_string).collect();
+    match family {
+        Some(Family::_26xx) => {
+            for smu in ["smua", "smub"] {
+                settings.extend(SMU_26XX_SETTINGS.iter().map(|s| s.replace("{smu}", smu)));
+            }
+            settings.extend(INSTRUMENT_26XX_SETTINGS.iter().map(ToString::to_string));
+        }
+        Some(Family::Tti) => settings.extend(TTI_SETTINGS.iter().map(ToString::to_string)),
+        Some(Family::_3700) => {
+            settings.extend(INSTRUMENT_3700_SETTINGS.iter().map(ToString::to_string));
+        }
+        Some(Family::ModularPlatform) => {
+            settings.extend(MODULAR_PLATFORM_SETTINGS.iter().map(ToString::to_string));
+        }
+        None => {}
+    }
+    settings
+}
+
+/// The settings of an instrument at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// When the snapshot was taken (RFC 3339)
+    pub created: String,
+    /// The instrument the snapshot was taken from
+    pub instrument: BackupSource,
+    /// The settings of the instrument as a tree that follows the TSP command tree
+    pub settings: Value,
+}
+
+/// A setting that differs between two [`Snapshot`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    /// The path of the setting in the TSP command tree
+    pub path: String,
+    /// The value of the setting in the first snapshot, if it exists
+    pub left: Option<String>,
+    /// The value of the setting in the second snapshot, if it exists
+    pub right: Option<String>,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} != {}",
+            self.path,
+            self.left.as_deref().unwrap_or("<missing>"),
+            self.right.as_deref().unwrap_or("<missing>")
+        )
+    }
+}
+
+impl Difference {
+    /// Whether the setting is `prefix` or is within `prefix` in the TSP command tree.
+    /// Only whole path segments match, so `lan` matches `lan.ipconfig` but not
+    /// `lanx.enable`, and `digio.line` matches `digio.line[1].mode`.
+    #[must_use]
+    pub fn is_within(&self, prefix: &str) -> bool {
+        let prefix = prefix.trim_end_matches('.');
+        self.path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    }
+}
+
+impl Snapshot {
+    /// Get the settings of this snapshot as a map from the path of each setting to
+    /// its value.
+    #[must_use]
+    pub fn flatten(&self) -> BTreeMap<String, String> {
+        let mut flat = BTreeMap::new();
+        flatten_into(&mut flat, String::new(), &self.settings);
+        flat
+    }
+
+    /// Find all the settings that differ between this snapshot and `other`.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<Difference> {
+        let left = self.flatten();
+        let right = other.flatten();
+        let mut paths: Vec<&String> = left.keys().chain(right.keys()).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+            .into_iter()
+            .filter(|p| left.get(*p) != right.get(*p))
+            .map(|p| Difference {
+                path: p.clone(),
+                left: left.get(p).cloned(),
+                right: right.get(p).cloned(),
+            })
+            .collect()
+    }
+}
+
+fn flatten_into(flat: &mut BTreeMap<String, String>, prefix: String, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                let path = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+                flatten_into(flat, path, v);
+            }
+        }
+        Value::String(s) => {
+            flat.insert(prefix, s.clone());
+        }
+        v => {
+            flat.insert(prefix, v.to_string());
+        }
+    }
+}
+
+/// Build a tree of settings from a map of setting paths to their values.
+fn to_tree(flat: &BTreeMap<String, String>) -> Value {
+    let mut root = Map::new();
+    for (path, value) in flat {
+        let mut node = &mut root;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                node.insert(part.to_string(), Value::String(value.clone()));
+                break;
+            }
+            let entry = node
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            let Value::Object(next) = entry else {
+                unreachable!("entry was just made an object");
+            };
+            node = next;
+        }
+    }
+    Value::Object(root)
+}
+
+/// Parse the output of the settings query into a map of setting paths to values.
+fn parse_settings(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix(SNAPSHOT_TAG))
+        .filter_map(|l| l.split_once('\t'))
+        .map(|(path, value)| (path.trim_end_matches("()").to_string(), value.to_string()))
+        .collect()
+}
+
+/// Read the settings of the instrument into a [`Snapshot`].
+///
+/// Settings that do not exist on the instrument are left out of the snapshot.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the settings could not be written or read.
+pub fn take_snapshot<T: Info + ?Sized>(inst: &mut T) -> Result<Snapshot> {
+    let info = inst.info()?;
+    info!("Taking snapshot of {info}");
+    let settings = settings_for(info.model.family().as_ref());
+    debug!("Reading {} settings", settings.len());
+
+    for s in &settings {
+        inst.write_all(tagged_value(SNAPSHOT_TAG, s, s).as_bytes())?;
+    }
+    inst.write_all(format!("print(\"{SNAPSHOT_END}\")\n").as_bytes())?;
+    inst.flush()?;
+
+    let output = read_until(
+        inst,
+        &[SNAPSHOT_END.to_string()],
+        10_000,
+        Duration::from_millis(1),
+    )?;
+
+    Ok(Snapshot {
+        created: chrono::Local::now().to_rfc3339(),
+        instrument: BackupSource::from(&info),
+        settings: to_tree(&parse_settings(&output)),
+    })
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{parse_settings, settings_for, to_tree, Difference, Snapshot};
+    use crate::{instrument::backup::BackupSource, model::Family};
+
+    fn snapshot(output: &str) -> Snapshot {
+        Snapshot {
+            created: String::new(),
+            instrument: BackupSource {
+                vendor: String::new(),
+                model: "2450".to_string(),
+                serial_number: String::new(),
+                firmware_rev: None,
+            },
+            settings: to_tree(&parse_settings(output)),
+        }
+    }
+
+    #[test]
+    fn settings_tree() {
+        let s = snapshot(
+            "SNAP>smu.source.func\tsmu.FUNC_DC_VOLTAGE\nSNAP>smu.measure.nplc\t1\nSNAP>lan.ipconfig()\tlan.MODE_AUTO,1.2.3.4\nSNAP>END",
+        );
+        assert_eq!(
+            s.settings,
+            serde_json::json!({
+                "lan": {"ipconfig": "lan.MODE_AUTO,1.2.3.4"},
+                "smu": {
+                    "measure": {"nplc": "1"},
+                    "source": {"func": "smu.FUNC_DC_VOLTAGE"},
+                },
+            })
+        );
+        assert_eq!(
+            s.flatten().get("smu.measure.nplc").map(String::as_str),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let a =
+            snapshot("SNAP>smu.measure.nplc\t1\nSNAP>smu.source.func\tV\nSNAP>beeper.enable\t1");
+        let b =
+            snapshot("SNAP>smu.measure.nplc\t0.1\nSNAP>smu.source.func\tV\nSNAP>tsplink.node\t2");
+        let diff: Vec<String> = a.diff(&b).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diff,
+            vec![
+                "beeper.enable: 1 != <missing>",
+                "smu.measure.nplc: 1 != 0.1",
+                "tsplink.node: <missing> != 2",
+            ]
+        );
+        assert!(a.diff(&a).is_empty());
+    }
+
+    #[test]
+    fn difference_prefix() {
+        let difference = |path: &str| Difference {
+            path: path.to_string(),
+            left: None,
+            right: None,
+        };
+        assert!(difference("lan.ipconfig").is_within("lan"));
+        assert!(difference("lan.ipconfig").is_within("lan."));
+        assert!(difference("lan.ipconfig").is_within("lan.ipconfig"));
+        assert!(!difference("lanx.enable").is_within("lan"));
+        assert!(!difference("lan.ipconfig").is_within("lan.ip"));
+        assert!(difference("digio.line[1].mode").is_within("digio.line"));
+        assert!(difference("digio.line[1].mode").is_within("digio.line[1]"));
+        assert!(!difference("digio.line[10].mode").is_within("digio.line[1"));
+    }
+
+    #[test]
+    fn family_settings() {
+        let settings = settings_for(Some(&Family::_26xx));
+        assert!(settings.contains(&"smua.measure.nplc".to_string()));
+        assert!(settings.contains(&"smub.measure.nplc".to_string()));
+        assert!(!settings.iter().any(|s| s.contains("{smu}")));
+        assert!(settings_for(None).contains(&"beeper.enable".to_string()));
+    }
+}
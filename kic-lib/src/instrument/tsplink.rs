@@ -0,0 +1,328 @@
+//! Inspect and manage the TSP-Link network of an instrument: the state of the network,
+//! the nodes on it, firmware mismatches between them and pushing a script to every
+//! node.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{
+    error::Result,
+    instrument::{
+        query::{parse_integer, query_tagged},
+        script::{validate_name, Script},
+    },
+    InstrumentError,
+};
+
+const NODE_DETAILS_START: &str = "NODE>START";
+const NODE_DETAILS_END: &str = "NODE>END";
+
+/// Prints the state of the network and the identity of every node on it. If the
+/// network is offline, only the local node is listed.
+const STATUS_QUERY: &str = "do local st = \"offline\" pcall(function() st = tostring(tsplink.state) end) print(\"TSPLINK>state\\t\" .. st) pcall(function() print(\"TSPLINK>master\\t\" .. tostring(tsplink.master)) end) pcall(function() print(\"TSPLINK>local\\t\" .. tostring(tsplink.node)) end) local function show(i, n) local m, s, r = \"\", \"\", \"\" pcall(function() m = tostring(n.model) end) pcall(function() s = tostring(n.serialno) end) pcall(function() r = tostring(n.revision) end) print(\"TSPLINK>node\\t\" .. tostring(i) .. \"\\t\" .. m .. \"\\t\" .. s .. \"\\t\" .. r) end if st == \"online\" then for i = 1, 64 do local ok, n = pcall(function() return node[i] end) if ok and n ~= nil then show(i, n) end end else local i = 1 pcall(function() i = tsplink.node end) show(i, localnode) end print(\"TSPLINK>END\") end";
+
+/// An instrument on the TSP-Link network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TspLinkNode {
+    /// The TSP-Link node number
+    pub node: u16,
+    /// The model of the instrument
+    pub model: String,
+    /// The serial number of the instrument
+    pub serial_number: Option<String>,
+    /// The firmware revision of the instrument
+    pub firmware_rev: Option<String>,
+    /// The modules in the slots of the instrument, if it has slots
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slots: Vec<String>,
+}
+
+/// The state of the TSP-Link network of an instrument.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TspLinkStatus {
+    /// The state of the network, `online` or `offline`
+    pub state: String,
+    /// The node number of the instrument that was queried
+    pub local_node: Option<u16>,
+    /// The node number of the master of the network
+    pub master_node: Option<u16>,
+    /// The nodes on the network, including the local node
+    pub nodes: Vec<TspLinkNode>,
+}
+
+/// Nodes of the same model that are running different firmware revisions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FirmwareMismatch {
+    /// The model of the nodes
+    pub model: String,
+    /// The firmware revision of each node, by node number
+    pub revisions: BTreeMap<u16, String>,
+}
+
+impl TspLinkStatus {
+    /// Whether the TSP-Link network is online.
+    #[must_use]
+    pub fn is_online(&self) -> bool {
+        self.state.eq_ignore_ascii_case("online")
+    }
+
+    /// The nodes other than the local node.
+    pub fn remote_nodes(&self) -> impl Iterator<Item = &TspLinkNode> {
+        self.nodes
+            .iter()
+            .filter(move |n| Some(n.node) != self.local_node)
+    }
+
+    /// Find the models for which nodes are running different firmware revisions.
+    #[must_use]
+    pub fn firmware_mismatches(&self) -> Vec<FirmwareMismatch> {
+        let mut by_model: BTreeMap<&str, BTreeMap<u16, String>> = BTreeMap::new();
+        for n in &self.nodes {
+            if let Some(fw) = &n.firmware_rev {
+                by_model
+                    .entry(n.model.as_str())
+                    .or_default()
+                    .insert(n.node, fw.clone());
+            }
+        }
+        by_model
+            .into_iter()
+            .filter(|(_, revisions)| revisions.values().collect::<BTreeSet<_>>().len() > 1)
+            .map(|(model, revisions)| FirmwareMismatch {
+                model: model.to_string(),
+                revisions,
+            })
+            .collect()
+    }
+
+    /// Add the slot modules from the output of `TspLinkNodeDetails.tsp`, which prints
+    /// the topology of the network between `NODE>START` and `NODE>END`.
+    ///
+    /// # Errors
+    /// Returns an [`InstrumentError`] if the output does not contain the topology.
+    pub fn add_node_details(&mut self, output: &str) -> Result<()> {
+        let details = parse_node_details(output)?;
+        if let Some(local) = self.master_node.or(self.local_node) {
+            self.set_slots(local, &details.slots);
+        }
+        for n in &details.nodes {
+            if let Some(node) = parse_node_id(&n.node_id) {
+                self.set_slots(node, &n.slots);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_slots(&mut self, node: u16, slots: &[SlotDetails]) {
+        if let Some(n) = self.nodes.iter_mut().find(|n| n.node == node) {
+            n.slots = slots.iter().map(|s| s.module.clone()).collect();
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SlotDetails {
+    module: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteNodeDetails {
+    node_id: String,
+    #[serde(default)]
+    slots: Vec<SlotDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeDetails {
+    #[serde(default)]
+    slots: Vec<SlotDetails>,
+    #[serde(default)]
+    nodes: Vec<RemoteNodeDetails>,
+}
+
+fn parse_node_details(output: &str) -> Result<NodeDetails> {
+    let json = output
+        .split_once(NODE_DETAILS_START)
+        .and_then(|(_, rest)| rest.split_once(NODE_DETAILS_END))
+        .map(|(json, _)| json.trim())
+        .ok_or_else(|| {
+            InstrumentError::Other("TSP-Link node details were not found".to_string())
+        })?;
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Parse a node id of the form `node[2]`.
+fn parse_node_id(id: &str) -> Option<u16> {
+    id.strip_prefix("node[")?.strip_suffix(']')?.parse().ok()
+}
+
+/// Parse the response of [`STATUS_QUERY`].
+fn parse_status(lines: &[String]) -> TspLinkStatus {
+    let non_empty = |v: &str| (!v.is_empty() && v != "nil").then(|| v.to_string());
+    let mut status = TspLinkStatus::default();
+    for line in lines {
+        let mut fields = line.split('\t');
+        match (fields.next(), fields.next()) {
+            (Some("state"), Some(state)) => status.state = state.trim().to_string(),
+            (Some("master"), Some(n)) => status.master_node = parse_integer(n, 1..=64),
+            (Some("local"), Some(n)) => status.local_node = parse_integer(n, 1..=64),
+            (Some("node"), Some(n)) => {
+                let Some(node) = parse_integer(n, 1..=64) else {
+                    continue;
+                };
+                status.nodes.push(TspLinkNode {
+                    node,
+                    model: fields.next().unwrap_or_default().trim().to_string(),
+                    serial_number: fields.next().and_then(|s| non_empty(s.trim())),
+                    firmware_rev: fields.next().and_then(|s| non_empty(s.trim())),
+                    slots: Vec::new(),
+                });
+            }
+            _ => {}
+        }
+    }
+    status
+}
+
+/// Read the state of the TSP-Link network and the identity of every node on it,
+/// without resetting the network.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the query could not be written or read.
+pub fn query_status<T: Script + ?Sized>(inst: &mut T) -> Result<TspLinkStatus> {
+    info!("Reading TSP-Link status");
+    let status = parse_status(&query_tagged(inst, STATUS_QUERY, "TSPLINK")?);
+    debug!(
+        "TSP-Link is {} with {} node(s)",
+        status.state,
+        status.nodes.len()
+    );
+    Ok(status)
+}
+
+/// Load the script `name` with the given `source` on every node of the TSP-Link
+/// network, optionally saving it to non-volatile memory and running it.
+///
+/// Returns the node numbers the script was loaded on.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the name is invalid or the script could not be
+/// written.
+pub fn sync_script<T: Script + ?Sized>(
+    inst: &mut T,
+    name: &str,
+    source: &[u8],
+    save: bool,
+    run: bool,
+) -> Result<Vec<u16>> {
+    validate_name(name)?;
+    let status = query_status(inst)?;
+
+    info!("Loading script '{name}' on the local node");
+    inst.write_script(name.as_bytes(), source, save, false)?;
+
+    let mut nodes: Vec<u16> = status.local_node.into_iter().collect();
+    for n in status.remote_nodes().map(|n| n.node).collect::<Vec<_>>() {
+        info!("Loading script '{name}' on node {n}");
+        let mut remote = format!("{name} = script.new(_kic_sync, \\\"{name}\\\") _kic_sync = nil");
+        if save {
+            remote.push_str(&format!(" {name}.save()"));
+        }
+        inst.write_all(
+            format!(
+                "node[{n}].setglobal(\"_kic_sync\", {name}.source) node[{n}].execute(\"{remote}\")\n"
+            )
+            .as_bytes(),
+        )?;
+        nodes.push(n);
+    }
+
+    if run {
+        for n in status.remote_nodes() {
+            inst.write_all(format!("node[{}].execute(\"{name}()\")\n", n.node).as_bytes())?;
+        }
+        inst.write_all(format!("{name}.run()\n").as_bytes())?;
+    }
+
+    // Wait for the instrument to process everything that was written.
+    let _ = query_tagged(inst, "print(\"SYNC>END\")", "SYNC")?;
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{parse_node_id, parse_status, TspLinkNode, TspLinkStatus};
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn status_parsing() {
+        let status = parse_status(&lines(
+            "state\tonline\n\
+             master\t1.00000e+00\n\
+             local\t1\n\
+             node\t1\t2636B\t0123456\t4.0.5\n\
+             node\t2\t2636B\t0123457\t4.0.4\n\
+             node\t3\t2602B\tnil\t",
+        ));
+        assert!(status.is_online());
+        assert_eq!(status.local_node, Some(1));
+        assert_eq!(status.master_node, Some(1));
+        assert_eq!(status.nodes.len(), 3);
+        assert_eq!(status.nodes[2].serial_number, None);
+        assert_eq!(status.nodes[2].firmware_rev, None);
+        assert_eq!(
+            status.remote_nodes().map(|n| n.node).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let mismatches = status.firmware_mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].model, "2636B");
+        assert_eq!(
+            mismatches[0].revisions.values().collect::<Vec<_>>(),
+            vec!["4.0.5", "4.0.4"]
+        );
+    }
+
+    #[test]
+    fn node_details() {
+        let node = |node, model: &str| TspLinkNode {
+            node,
+            model: model.to_string(),
+            ..TspLinkNode::default()
+        };
+        let mut status = TspLinkStatus {
+            state: "online".to_string(),
+            local_node: Some(1),
+            master_node: Some(1),
+            nodes: vec![node(1, "MP5103"), node(2, "MP5103")],
+        };
+        status
+            .add_node_details(
+                "NODE>START\t{\"name\": \"\", \"localNode\": \"MP5103\", \"isActive\": false, \"slots\": [{\"slotId\": \"slot[1]\", \"module\": \"MSMU60-2\"}], \"nodes\": [{\"nodeId\": \"node[2]\", \"mainframe\": \"MP5103\", \"slots\": [{\"module\": \"Empty\", \"slotId\": \"slot[1]\"}]}]}\tNODE>END",
+            )
+            .unwrap();
+        assert_eq!(status.nodes[0].slots, vec!["MSMU60-2"]);
+        assert_eq!(status.nodes[1].slots, vec!["Empty"]);
+        assert!(status.firmware_mismatches().is_empty());
+        assert!(status.add_node_details("TSP>").is_err());
+        assert_eq!(parse_node_id("node[12]"), Some(12));
+        assert_eq!(parse_node_id("slot[1]"), None);
+    }
+}
@@ -0,0 +1,174 @@
+//! Browse the global environment of an instrument: the variables, functions, scripts
+//! and reading buffers that are defined, and the content of tables.
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Result,
+    instrument::{query::query_tagged, script::Script},
+    tsp::is_name,
+    InstrumentError,
+};
+
+const VARS_TAG: &str = "VARS";
+
+/// Prints a line for every entry of the table at `{path}` with the name, type and a
+/// preview of the value, or a single line for a value that is not a table. Scripts are
+/// recognized the way `getscripts` in `kiDebugger.tsp` does, and buffers by their `n`
+/// and `capacity` attributes.
+const VARS_QUERY: &str = r#"do
+local function clean(s) local r = string.gsub(tostring(s), "[\t\r\n]", " ") return r end
+local function kind(v)
+local t = type(v)
+if t == "table" or t == "userdata" then
+local isScript = false
+pcall(function() local m = getmetatable(v) isScript = m ~= nil and m.Objects ~= nil and m.Objects.source ~= nil and type(m.Objects.source) ~= "table" end)
+if isScript then return "script", "" end
+local n, c = nil, nil
+pcall(function() n = v.n c = v.capacity end)
+if type(n) == "number" and type(c) == "number" then return "buffer", tostring(n) .. "/" .. tostring(c) .. " readings" end
+if t == "table" then local count = 0 for _ in pairs(v) do count = count + 1 end return "table", tostring(count) .. " entries" end
+return t, ""
+end
+if t == "function" then return t, "" end
+local s = tostring(v)
+if t == "string" then s = string.format("%q", v) end
+s = clean(s)
+if string.len(s) > 60 then s = string.sub(s, 1, 57) .. "..." end
+return t, s
+end
+local ok, t = pcall(function() return {path} end)
+if not ok then print("VARS>!" .. clean(t))
+elseif type(t) ~= "table" then local k, p = kind(t) print("VARS>=" .. k .. "\t" .. p)
+else for name, v in pairs(t) do local k, p = kind(v) print("VARS>" .. clean(name) .. "\t" .. k .. "\t" .. p) end end
+print("VARS>END")
+end"#;
+
+/// An entry of the global environment, or of a table in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Variable {
+    /// The name of the variable, or the key of the table entry
+    pub name: String,
+    /// The type of the value: `script`, `buffer`, `table`, `function`, or a Lua type
+    pub kind: String,
+    /// A short preview of the value, e.g. the number of entries of a table
+    pub preview: String,
+}
+
+/// Ensure that `path` is a global name followed by field names or numeric indexes,
+/// e.g. `results.run[2]`, so that it can be evaluated on the instrument.
+fn validate_path(path: &str) -> Result<()> {
+    let valid = path.split('.').all(|part| {
+        let (name, indexes) = part.split_once('[').unwrap_or((part, ""));
+        let indexes_valid = indexes.is_empty()
+            || indexes.strip_suffix(']').is_some_and(|s| {
+                s.split("][")
+                    .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            });
+        is_name(name) && indexes_valid
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(InstrumentError::Other(format!(
+            "\"{path}\" is not a valid variable path"
+        )))
+    }
+}
+
+/// Parse the output of the variables query for `path`.
+fn parse_variables(path: &str, lines: &[String]) -> Result<Vec<Variable>> {
+    let mut variables = Vec::new();
+    for line in lines {
+        if let Some(error) = line.strip_prefix('!') {
+            return Err(InstrumentError::Other(format!(
+                "unable to read \"{path}\": {error}"
+            )));
+        }
+        if let Some(value) = line.strip_prefix('=') {
+            let (kind, preview) = value.split_once('\t').unwrap_or((value, ""));
+            return Ok(vec![Variable {
+                name: path.to_string(),
+                kind: kind.to_string(),
+                preview: preview.to_string(),
+            }]);
+        }
+        let mut fields = line.splitn(3, '\t');
+        if let (Some(name), Some(kind)) = (fields.next(), fields.next()) {
+            variables.push(Variable {
+                name: name.to_string(),
+                kind: kind.to_string(),
+                preview: fields.next().unwrap_or_default().to_string(),
+            });
+        }
+    }
+    variables.sort_by(|a, b| {
+        a.name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(variables)
+}
+
+/// List the entries of the table at `path` on the instrument, or of the global
+/// environment if `path` is `None`. If `path` is not a table, its value is returned as
+/// the only entry.
+///
+/// # Notes
+/// Prompts should be disabled and the output queue should be empty before this is
+/// called since the output of the instrument is parsed.
+///
+/// # Errors
+/// Returns an [`InstrumentError`] if the path is invalid or could not be evaluated, or
+/// the query could not be written or read.
+pub fn list_variables<T: Script + ?Sized>(
+    inst: &mut T,
+    path: Option<&str>,
+) -> Result<Vec<Variable>> {
+    let path = path.unwrap_or("_G");
+    validate_path(path)?;
+    info!("Reading variables of {path}");
+    let query = VARS_QUERY.replace("{path}", path).replace('\n', " ");
+    let variables = parse_variables(path, &query_tagged(inst, &query, VARS_TAG)?)?;
+    debug!("Read {} variable(s)", variables.len());
+    Ok(variables)
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{parse_variables, validate_path, VARS_QUERY};
+    use crate::tsp::check_syntax;
+
+    #[test]
+    fn variables() {
+        assert_eq!(check_syntax(&VARS_QUERY.replace("{path}", "_G")), Ok(()));
+        assert!(validate_path("_G").is_ok());
+        assert!(validate_path("results.run[2][10].x").is_ok());
+        assert!(validate_path("a..b").is_err());
+        assert!(validate_path("a[x]").is_err());
+        assert!(validate_path("print('x')").is_err());
+
+        let lines: Vec<String> = [
+            "x\tnumber\t5",
+            "kic_test\tscript\t",
+            "Results\ttable\t3 entries",
+            "buf\tbuffer\t2/100 readings",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let vars = parse_variables("_G", &lines).unwrap();
+        let names: Vec<_> = vars.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["buf", "kic_test", "Results", "x"]);
+        assert_eq!(vars[0].preview, "2/100 readings");
+
+        let value = parse_variables("x", &["=number\t5".to_string()]).unwrap();
+        assert_eq!(
+            (value[0].name.as_str(), value[0].kind.as_str()),
+            ("x", "number")
+        );
+        assert!(parse_variables("y.z", &["!attempt to index a nil value".to_string()]).is_err());
+    }
+}
@@ -440,7 +440,7 @@ pub mod unit {
                 ConnectionInfo::Usb {
                     string: "USB0::0x5e6::0x2461::12345678::INSTR".to_string(),
                     vendor: Vendor::Keithley,
-                    model: Model::_2461,
+                    model: "2461".parse::<Model>().unwrap(),
                     serial: "12345678".to_string(),
                     interface_number: None,
                 },
@@ -450,7 +450,7 @@ pub mod unit {
                 ConnectionInfo::Usb {
                     string: "USB0::0x699::0x5103::asdf::INSTR".to_string(),
                     vendor: Vendor::Tektronix,
-                    model: Model::MP5103,
+                    model: "MP5103".parse::<Model>().unwrap(),
                     serial: "asdf".to_string(),
                     interface_number: None,
                 },
@@ -460,7 +460,7 @@ pub mod unit {
                 ConnectionInfo::Usb {
                     string: "USB0::0x699::0x2636::asdf::1::INSTR".to_string(),
                     vendor: Vendor::Tektronix,
-                    model: Model::_2636B,
+                    model: "2636B".parse::<Model>().unwrap(),
                     serial: "asdf".to_string(),
                     interface_number: Some(1u16),
                 },
@@ -12,6 +12,7 @@ pub mod error;
 pub mod instrument;
 pub mod interface;
 pub mod model;
+pub mod tsp;
 
 #[cfg(test)]
 pub(crate) mod test_util;
@@ -23,11 +23,12 @@ pub struct Instrument {
     protocol: Protocol,
     auth: Authentication,
     fw_flash_in_progress: bool,
+    reset_on_drop: bool,
 }
 
 impl Instrument {
     #[must_use]
-    pub const fn is(info: &InstrumentInfo) -> bool {
+    pub fn is(info: &InstrumentInfo) -> bool {
         info.model.is_2600()
     }
 
@@ -54,6 +55,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         })
     }
 
@@ -64,6 +66,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         }
     }
 
@@ -134,6 +137,17 @@ impl Login for Instrument {
 
         Ok(())
     }
+
+    fn logout(&mut self) -> crate::error::Result<()> {
+        // Prompts are disabled first so that the instrument does not answer the bare
+        // `password` with a password prompt that would take the next line, then any
+        // pending entry is ended with `abort`.
+        self.write_all(b"localnode.prompts = 0\n")?;
+        self.write_all(b"password\n")?;
+        self.write_all(b"abort\n")?;
+        self.flush()?;
+        Ok(())
+    }
 }
 
 impl Script for Instrument {}
@@ -233,7 +247,9 @@ impl Drop for Instrument {
         let _ = self.write_all(b"abort\n");
         std::thread::sleep(Duration::from_millis(100));
 
-        let _ = self.reset();
+        if self.reset_on_drop {
+            let _ = self.reset();
+        }
 
         #[cfg(not(test))]
         //Allow reset to complete
@@ -261,6 +277,10 @@ impl Reset for Instrument {
         std::thread::sleep(Duration::from_millis(100));
         Ok(())
     }
+
+    fn set_reset_on_drop(&mut self, reset: bool) {
+        self.reset_on_drop = reset;
+    }
 }
 
 impl Abort for Instrument {
@@ -779,6 +799,43 @@ mod unit {
     //    assert_eq!(info.firmware_rev.unwrap(), exp_fw);
     //}
 
+    #[test]
+    fn logout() {
+        let mut interface = MockInterface::new();
+        let mut seq = Sequence::new();
+
+        for cmd in [&b"localnode.prompts = 0\n"[..], b"password\n", b"abort\n"] {
+            interface
+                .expect_write()
+                .times(1)
+                .in_sequence(&mut seq)
+                .withf(move |buf: &[u8]| buf == cmd)
+                .returning(|buf: &[u8]| Ok(buf.len()));
+        }
+        interface.expect_flush().times(..).returning(|| Ok(()));
+        // Dropping the instrument
+        interface
+            .expect_write()
+            .times(..)
+            .withf(|buf: &[u8]| {
+                [
+                    &b"*RST\n"[..],
+                    b"abort\n",
+                    b"password\n",
+                    b"localnode.prompts = 0\n",
+                ]
+                .contains(&buf)
+            })
+            .returning(|buf: &[u8]| Ok(buf.len()));
+
+        let mut instrument: Instrument = Instrument::new(
+            protocol::Protocol::Raw(Raw::new(interface)),
+            Authentication::NoAuth,
+        );
+
+        assert!(instrument.logout().is_ok());
+    }
+
     #[test]
     fn write_script() {
         let mut interface = MockInterface::new();
@@ -1,5 +1,6 @@
 use std::{fmt::Display, str::FromStr};
 
+use serde::{Deserialize, Serialize};
 use tracing::{instrument, trace};
 
 use crate::{
@@ -11,15 +12,18 @@ use crate::{
 
 pub mod ki2600;
 pub mod ki3700;
+pub mod registry;
 pub mod tti;
 pub mod versatest;
 
+pub use registry::{registry, ModelEntry};
+
 #[must_use]
 pub fn is_supported(model: impl AsRef<str>) -> bool {
-    self::ki2600::Instrument::model_is(&model)
-        || self::ki3700::Instrument::model_is(&model)
-        || self::tti::Instrument::model_is(&model)
-        || self::versatest::Instrument::model_is(&model)
+    model
+        .as_ref()
+        .parse::<Model>()
+        .is_ok_and(|m| m.family().is_some())
 }
 
 /// Connect to an instrument given the instrument's connection information and authentication
@@ -211,187 +215,106 @@ impl FromStr for Vendor {
     }
 }
 
-/// This macro is intended only to define instrument models. It should not be used for
-/// any other purpose and must not be made public in any way.
-///
-/// This macro generates an enum with a list of variants that represent all the models
-/// this library supports (as well as an `Other` variant which allows the use of unsupported
-/// instruments on a best-guess basis). It also implements [`std::str::FromStr`],
-/// [`std::fmt::Display`], and [`std::convert::From<u16>`] (for USB product IDs) for
-/// each model provided.
-///
-/// # Syntax
-/// The syntax is as follows:
-/// ```ignore
-/// define_models! {
-///     <VIS> enum Models[<ERROR-TYPE>, <FAMILY-TYPE>] {
-///         <VARIANT-NAME>[<u16-PID] <- [<STRING-REPRESENTATION>,<OTHER-STRING-REPS>...] #<FAMILY-GROUP,
-///         <VARIANT-NAME> <- [<STRING-REPRESENTATION>,<OTHER-STRING-REPS>...] #<FAMILY-GROUP,
-///     }
-/// }
-/// ```
-/// # Example
-///
-/// ```ignore
-/// # #[derive(Debug, Clone, thiserror::Error)]
-/// # pub enum CarError {
-/// #   #[error("Error: {0}")]
-/// #   A(String),
-/// # }
-///
-/// pub enum Family {
-///     A,
-///     B,
-///     C,
-/// }
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Family {
+    #[serde(rename = "26xx")]
+    _26xx,
+    #[serde(rename = "3700")]
+    _3700,
+    #[serde(rename = "tti")]
+    Tti,
+    #[serde(rename = "modular-platform")]
+    ModularPlatform,
+}
+
+/// An instrument model.
 ///
-/// define_models! {
-///     pub enum Cars[CarError, Family] {
-///         ModelT <- ["Ford Model T", "Model T", "Mr. T"] #Family::A,
-///         _300[0x0300] <- ["Chrysler 300", "300"] #Family::B,
-///         M3[0x0003] <- ["BMW M3"] #Family::C,
-///     }
-/// }
-/// ```
-macro_rules! define_models {
-    (
-        pub enum $name:ident[$error:path, $fam:path] {
-            $(
-                $variant:ident$([$pid:expr])? <- [$string_val:literal$(,$alt_string_val:expr),* $(,)?] #$family:path
-            ),+ $(,)?
-        }
-    ) => {
-        #[derive(Debug, Hash, PartialEq, Eq, Clone, serde::Serialize)]
-        pub enum $name {
-            $(#[serde(rename=$string_val)]$variant),+,
-            #[serde(rename="Unknown Model")]
-            Other(String),
+/// Models are looked up in the [`registry`], models that aren't in the registry are
+/// represented by [`Model::Other`] and are used on a best-guess basis.
+#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+pub enum Model {
+    /// A model in the registry, identified by its canonical name, along with its family
+    Known(String, Family),
+    /// A model that is not in the registry
+    Other(String),
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::Other(String::default())
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Known(name, _) | Self::Other(name) => write!(f, "{name}"),
         }
+    }
+}
 
-        impl Default for $name {
-            fn default() -> Self {Self::Other(String::default())}
+impl FromStr for Model {
+    type Err = InstrumentError;
+
+    fn from_str(val: &str) -> Result<Self, Self::Err> {
+        Ok(registry().get(val).map_or_else(
+            || Self::Other(val.to_string()),
+            |m| Self::Known(m.name.clone(), m.family),
+        ))
+    }
+}
+
+impl Serialize for Model {
+    fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Known(name, _) => ser.serialize_str(name),
+            Self::Other(name) => ser.serialize_newtype_variant("Model", 1, "Unknown Model", name),
         }
+    }
+}
 
-        impl std::fmt::Display for $name {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                write!(f, "{}", match self {
-                    $(
-                        $name::$variant => $string_val
-                    ),+,
-                    $name::Other(name) => name,
-                })
-            }
+impl Model {
+    /// The registry entry of this model, if it is a known model.
+    #[must_use]
+    pub fn entry(&self) -> Option<&'static ModelEntry> {
+        match self {
+            Self::Known(name, _) => registry().get(name),
+            Self::Other(_) => None,
         }
+    }
 
-        impl std::str::FromStr for $name {
-            type Err = $error;
-            fn from_str(val: &str) -> Result<Self, Self::Err> {
-                match val {
-                    $(
-                        $string_val$( | $alt_string_val)* => Ok($name::$variant)
-                    ),+,
-                    _ => Ok($name::Other(val.to_string()))
-                }
-            }
+    #[must_use]
+    pub const fn family(&self) -> Option<Family> {
+        match self {
+            Self::Known(_, family) => Some(*family),
+            Self::Other(_) => None,
         }
+    }
 
-        impl $name {
-            #[must_use]
-            pub const fn family(&self) -> Option<$fam> {
-                match self {
-                    $(
-                        $name::$variant => Some($family)
-                    ),+,
-                    $name::Other(_) => None,
-                }
-            }
+    #[must_use]
+    pub fn from_pid(pid: u16) -> Self {
+        registry().get_by_pid(pid).map_or_else(
+            || Self::Other(format!("PID: {pid:#X}")),
+            |m| Self::Known(m.name.clone(), m.family),
+        )
+    }
 
-            #[must_use]
-            pub fn from_pid(pid: u16) -> Self {
-                $(
-                    define_models!(@match_pid $name, $variant, pid, $($pid)?);
-                )*
-                $name::Other(format!("PID: {pid:#X}"))
-            }
-        }
-    };
-    (@match_pid $name:ident, $variant:ident, $input:ident, $pid:literal) => {
-        if $input == $pid {
-            return $name::$variant;
-        }
-    };
-    (@match_pid $name:ident, $variant:ident, $input:ident, ) => { };
-}
+    /// The number of module slots of this model.
+    #[must_use]
+    pub fn slots(&self) -> u16 {
+        self.entry().map_or(0, |m| m.slots)
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Family {
-    _26xx,
-    _3700,
-    Tti,
-    ModularPlatform,
-}
+    /// The number of bytes of script memory of this model, if it is known.
+    #[must_use]
+    pub fn script_memory(&self) -> Option<usize> {
+        self.entry().and_then(|m| m.script_memory)
+    }
 
-define_models! {
-    pub enum Model[InstrumentError, Family] {
-        //2600
-        _2601A <- ["2601"] #Family::_26xx,
-        _2602A <- ["2602"] #Family::_26xx,
-        _2611A <- ["2611"] #Family::_26xx,
-        _2612A <- ["2612"] #Family::_26xx,
-        _2635A <- ["2635"] #Family::_26xx,
-        _2636A <- ["2636"] #Family::_26xx,
-        _2651A <- ["2651"] #Family::_26xx,
-        _2657A <- ["2657"] #Family::_26xx,
-        _2601B[0x2601] <- ["2601B"] #Family::_26xx,
-        _2601BPulse[0x26F1] <- ["2601B-PULSE"] #Family::_26xx,
-        _2602B[0x2602] <- ["2602B"] #Family::_26xx,
-        _2606B[0x2606] <- ["2606B"] #Family::_26xx,
-        _2611B[0x2611] <- ["2611B"] #Family::_26xx,
-        _2612B[0x2612] <- ["2612B"] #Family::_26xx,
-        _2635B[0x2635] <- ["2635B"] #Family::_26xx,
-        _2636B[0x2636] <- ["2636B"] #Family::_26xx,
-        _2604B[0x2604] <- ["2604B"] #Family::_26xx,
-        _2614B[0x2614] <- ["2614B"] #Family::_26xx,
-        _2634B[0x2634] <- ["2634B"] #Family::_26xx,
-        _2601BL <- ["2601B-L"] #Family::_26xx,
-        _2602BL <- ["2602B-L"] #Family::_26xx,
-        _2611BL <- ["2611B-L"] #Family::_26xx,
-        _2612BL <- ["2612B-L"] #Family::_26xx,
-        _2635BL <- ["2635B-L"] #Family::_26xx,
-        _2636BL <- ["2636B-L"] #Family::_26xx,
-        _2604BL <- ["2604B-L"] #Family::_26xx,
-        _2614BL <- ["2614B-L"] #Family::_26xx,
-        _2634BL <- ["2634B-L"] #Family::_26xx,
-
-        // 3706 or 70xB
-        _3706 <- ["3706"] #Family::_3700,
-        _3706S <- ["3706-S"] #Family::_3700,
-        _3706SNFP <- ["3706-SNFP"] #Family::_3700,
-        _3706NFP <- ["3706-NFP"] #Family::_3700,
-        _3706A[0x3706] <- ["3706A"] #Family::_3700,
-        _3706AS <- ["3706A-S"] #Family::_3700,
-        _3706ASNFP <- ["3706A-SNFP"] #Family::_3700,
-        _3706ANFP <- ["3706A-NFP"] #Family::_3700,
-        _707B[0x707B] <- ["707B"] #Family::_3700,
-        _708B[0x708B] <- ["708B"] #Family::_3700,
-        _5880Sru <- ["5880_SRU"] #Family::_3700,
-        _5881Sru <- ["5881_SRU"] #Family::_3700,
-
-        // TTI
-        _2450[0x2450] <- ["2450"] #Family::Tti,
-        _2470[0x2470] <- ["2470"] #Family::Tti,
-        _2460[0x2460] <- ["2460"] #Family::Tti,
-        _2461[0x2461] <- ["2461"] #Family::Tti,
-        _2461Sys[0x1642] <- ["2461-SYS"] #Family::Tti,
-        DMM7500[0x7500] <- ["DMM7500"] #Family::Tti,
-        DMM7510[0x7510] <- ["DMM7510"] #Family::Tti,
-        DMM7512[0x7512] <- ["DMM7512"] #Family::Tti,
-        DMM6500[0x6500] <- ["DMM6500"] #Family::Tti,
-        DAQ6510[0x6510] <- ["DAQ6510"] #Family::Tti,
-
-        // Modular Platform
-        MP5103[0x5103] <- ["MP5103"] #Family::ModularPlatform,
-        TSPop <- ["TSPop", "TSP"] #Family::ModularPlatform,
+    /// Whether this model has the given capability, see [`ModelEntry::capabilities`].
+    #[must_use]
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.entry().is_some_and(|m| m.has_capability(capability))
     }
 }
 
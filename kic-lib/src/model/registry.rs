@@ -0,0 +1,239 @@
+//! The registry of instrument models known to this library.
+//!
+//! The registry is read from `models.json`, which is embedded in the library, and can
+//! be extended or overridden by a file of the same format given by
+//! [`MODELS_FILE_ENV`]. Adding support for a new model of an existing [`Family`] only
+//! requires a new entry in that file.
+
+use std::{path::Path, sync::LazyLock};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::{debug, warn};
+
+use crate::{model::Family, InstrumentError};
+
+/// If set, the models in the file at this path are added to the embedded registry,
+/// replacing any embedded model with the same name.
+pub const MODELS_FILE_ENV: &str = "TSP_TOOLKIT_MODELS_FILE";
+
+/// The capability of models that require a username, as well as a password, to log in.
+pub const CAPABILITY_USERNAME_LOGIN: &str = "username-login";
+
+/// The capability of models that support TSP-Link, either built in or, like the
+/// DMM6500 and DAQ6510, through an optional communication card.
+pub const CAPABILITY_TSPLINK: &str = "tsplink";
+
+const EMBEDDED_MODELS: &str = include_str!("./models.json");
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| {
+    let mut registry =
+        Registry::from_json(EMBEDDED_MODELS).expect("embedded model registry should be valid");
+    if let Some(path) = std::env::var_os(MODELS_FILE_ENV) {
+        match Registry::from_file(Path::new(&path)) {
+            Ok(user) => {
+                debug!("Adding models from {}", Path::new(&path).display());
+                registry.extend(user);
+            }
+            Err(e) => warn!(
+                "Unable to read models from {}, using the built-in models only: {e}",
+                Path::new(&path).display()
+            ),
+        }
+    }
+    registry
+});
+
+/// Get the model registry.
+#[must_use]
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Everything this library knows about an instrument model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelEntry {
+    /// The canonical name of the model, as reported by the instrument
+    pub name: String,
+    /// Other names the instrument may report
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// The USB product ID of the model
+    #[serde(
+        default,
+        deserialize_with = "deserialize_pid",
+        serialize_with = "serialize_pid",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pid: Option<u16>,
+    /// The family of the model, which determines how to communicate with it
+    pub family: Family,
+    /// The number of module slots of the model
+    #[serde(default)]
+    pub slots: u16,
+    /// The optional features of the model, such as [`CAPABILITY_TSPLINK`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    /// The number of bytes of script memory of the model, which limits the size of the
+    /// scripts that can be loaded, if it is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_memory: Option<usize>,
+}
+
+impl ModelEntry {
+    /// Whether `name` refers to this model.
+    #[must_use]
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| a == name)
+    }
+
+    /// Whether this model has the given capability.
+    #[must_use]
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+fn deserialize_pid<'de, D: Deserializer<'de>>(de: D) -> Result<Option<u16>, D::Error> {
+    let Some(pid) = Option::<String>::deserialize(de)? else {
+        return Ok(None);
+    };
+    let digits = pid
+        .strip_prefix("0x")
+        .or_else(|| pid.strip_prefix("0X"))
+        .unwrap_or(&pid);
+    u16::from_str_radix(digits, 16)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid USB product ID '{pid}': {e}")))
+}
+
+#[allow(clippy::ref_option, clippy::trivially_copy_pass_by_ref)]
+fn serialize_pid<S: serde::Serializer>(pid: &Option<u16>, ser: S) -> Result<S::Ok, S::Error> {
+    match pid {
+        Some(pid) => ser.serialize_str(&format!("0x{pid:04X}")),
+        None => ser.serialize_none(),
+    }
+}
+
+/// A collection of [`ModelEntry`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    models: Vec<ModelEntry>,
+}
+
+impl Registry {
+    /// Parse a registry from its JSON representation.
+    ///
+    /// # Errors
+    /// Returns an error if `json` is not a valid registry.
+    pub fn from_json(json: &str) -> Result<Self, InstrumentError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Read a registry from a JSON file.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be read or is not a valid registry.
+    pub fn from_file(path: &Path) -> Result<Self, InstrumentError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Add the models of `other`, replacing the models that have the same name.
+    pub fn extend(&mut self, other: Self) {
+        for model in other.models {
+            if let Some(existing) = self.models.iter_mut().find(|m| m.name == model.name) {
+                *existing = model;
+            } else {
+                self.models.push(model);
+            }
+        }
+    }
+
+    /// All the models in the registry.
+    #[must_use]
+    pub fn models(&self) -> &[ModelEntry] {
+        &self.models
+    }
+
+    /// Look up a model by its name or one of its aliases.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&ModelEntry> {
+        self.models.iter().find(|m| m.is_named(name))
+    }
+
+    /// Look up a model by its USB product ID.
+    #[must_use]
+    pub fn get_by_pid(&self, pid: u16) -> Option<&ModelEntry> {
+        self.models.iter().find(|m| m.pid == Some(pid))
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{registry, Registry, CAPABILITY_TSPLINK, CAPABILITY_USERNAME_LOGIN};
+    use crate::model::Family;
+
+    #[test]
+    fn embedded_registry() {
+        let r = registry();
+        assert_eq!(r.get("2450").map(|m| &m.family), Some(&Family::Tti));
+        assert_eq!(r.get("TSP").map(|m| m.name.as_str()), Some("TSPop"));
+        assert_eq!(
+            r.get_by_pid(0x26F1).map(|m| m.name.as_str()),
+            Some("2601B-PULSE")
+        );
+        assert!(r
+            .get("MP5103")
+            .is_some_and(|m| m.has_capability(CAPABILITY_USERNAME_LOGIN)));
+        assert!(r.get("not a model").is_none());
+    }
+
+    #[test]
+    fn tsplink_capability() {
+        let has_tsplink = |name: &str| {
+            registry()
+                .get(name)
+                .is_some_and(|m| m.has_capability(CAPABILITY_TSPLINK))
+        };
+        for name in [
+            "2636B", "2450", "DMM7510", "DMM6500", "DAQ6510", "3706A", "MP5103",
+        ] {
+            assert!(has_tsplink(name), "{name} should support TSP-Link");
+        }
+        for name in ["2604B", "2614B", "2634B"] {
+            assert!(!has_tsplink(name), "{name} should not support TSP-Link");
+        }
+    }
+
+    #[test]
+    fn extend_registry() {
+        let mut r = Registry::from_json(
+            r#"{"models": [{"name": "2450", "pid": "0x2450", "family": "tti"}]}"#,
+        )
+        .unwrap();
+        r.extend(
+            Registry::from_json(
+                r#"{"models": [
+                    {"name": "2450", "aliases": ["2450-NEW"], "family": "tti"},
+                    {"name": "MP5106", "pid": "0x5106", "family": "modular-platform", "slots": 6, "script_memory": 1048576}
+                ]}"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(r.models().len(), 2);
+        assert_eq!(r.get("2450-NEW").map(|m| m.pid), Some(None));
+        assert_eq!(r.get_by_pid(0x5106).map(|m| m.slots), Some(6));
+        assert_eq!(
+            r.get("MP5106").and_then(|m| m.script_memory),
+            Some(1_048_576)
+        );
+        assert_eq!(r.get("2450").and_then(|m| m.script_memory), None);
+    }
+
+    #[test]
+    fn invalid_pid() {
+        assert!(Registry::from_json(
+            r#"{"models": [{"name": "2450", "pid": "0xZZ", "family": "tti"}]}"#
+        )
+        .is_err());
+    }
+}
@@ -1,9 +1,8 @@
 use std::{
-    io::{BufRead, ErrorKind, Read, Write},
+    io::{ErrorKind, Read, Write},
     time::Duration,
 };
 
-use bytes::Buf;
 use indicatif::{ProgressBar, ProgressStyle};
 use tracing::{self, error, trace};
 
@@ -12,6 +11,7 @@ use crate::{
         self,
         authenticate::Authentication,
         clear_output_queue,
+        firmware::{FlashOptions, ProgressFormat, FW_CHUNK_SIZE},
         info::InstrumentInfo,
         language::{CmdLanguage, Language},
         Abort, Info, Login, Reset, Script,
@@ -27,11 +27,12 @@ pub struct Instrument {
     protocol: Protocol,
     auth: Authentication,
     fw_flash_in_progress: bool,
+    reset_on_drop: bool,
 }
 
 impl Instrument {
     #[must_use]
-    pub const fn is(info: &InstrumentInfo) -> bool {
+    pub fn is(info: &InstrumentInfo) -> bool {
         info.model.is_tti()
     }
 
@@ -58,6 +59,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         })
     }
 
@@ -68,6 +70,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         }
     }
 
@@ -175,16 +178,50 @@ impl Login for Instrument {
 
         Ok(())
     }
+
+    fn logout(&mut self) -> crate::error::Result<()> {
+        self.write_all(b"logout\n")?;
+        self.flush()?;
+        Ok(())
+    }
 }
 
 impl Script for Instrument {}
 
 impl Flash for Instrument {
-    fn flash_firmware(&mut self, image: &[u8], _: Option<u16>) -> crate::error::Result<()> {
-        #[allow(irrefutable_let_patterns)] //This is marked as irrefutable when building without
-        //visa
+    fn flash_firmware(&mut self, image: &[u8], info: Option<u16>) -> crate::error::Result<()> {
+        self.flash_firmware_with(image, info, &FlashOptions::default())
+    }
+
+    #[tracing::instrument(skip(self, image, options))]
+    fn flash_firmware_with(
+        &mut self,
+        image: &[u8],
+        _: Option<u16>,
+        options: &FlashOptions,
+    ) -> crate::error::Result<()> {
         let _ = self.set_nonblocking(false);
-        let spinner = if let Protocol::Raw(_) = self.protocol {
+
+        self.fw_flash_in_progress = true;
+
+        self.write_all(b"localnode.prompts=localnode.DISABLE\n")?;
+        self.write_all(b"if ki.upgrade ~= nil and ki.upgrade.noacklater ~= nil then ki.upgrade.noacklater() end\n")?;
+        self.write_all(b"prevflash\n")?;
+
+        if let Err(e) = instrument::firmware::transfer_image(self, image, FW_CHUNK_SIZE, options) {
+            // `endflash` starts programming the image, so flash mode is aborted instead
+            // and the partial image is discarded.
+            trace!("Firmware transfer stopped, aborting flash mode: {e}");
+            let _ = self.write_all(b"\n");
+            let _ = self.abort();
+            let _ = self.set_nonblocking(true);
+            self.fw_flash_in_progress = false;
+            return Err(e);
+        }
+
+        self.write_all(b"endflash\n")?;
+
+        let spinner = if options.progress == ProgressFormat::Bar {
             let pb = ProgressBar::new(1);
             #[allow(clippy::literal_string_with_formatting_args)]
             // This is a template for ProgressStyle that requires this syntax
@@ -193,25 +230,12 @@ impl Flash for Instrument {
                     .unwrap(),
             );
             pb.enable_steady_tick(Duration::from_millis(100));
-            pb.set_message("Loading Firmware...");
-
+            pb.set_message("Instrument processing firmware...");
             Some(pb)
         } else {
             None
         };
 
-        self.fw_flash_in_progress = true;
-
-        let mut image = image.reader();
-
-        self.write_all(b"localnode.prompts=localnode.DISABLE\n")?;
-        self.write_all(b"if ki.upgrade ~= nil and ki.upgrade.noacklater ~= nil then ki.upgrade.noacklater() end\n")?;
-        self.write_all(b"prevflash\n")?;
-
-        self.write_all(image.fill_buf().unwrap())?;
-
-        self.write_all(b"endflash\n")?;
-
         // Only sleep in non-test builds
         #[cfg(not(test))]
         std::thread::sleep(Duration::from_secs(180));
@@ -220,7 +244,7 @@ impl Flash for Instrument {
             pb.finish_with_message(
                 "Firmware file transferred successfully. Upgrade running on instrument.",
             );
-        } else {
+        } else if options.progress == ProgressFormat::None {
             eprintln!("Firmware file transferred successfully. Upgrade running on instrument.");
         }
         let _ = self.set_nonblocking(true);
@@ -281,7 +305,9 @@ impl Drop for Instrument {
         let _ = self.write_all(b"abort\n");
         std::thread::sleep(Duration::from_millis(100));
 
-        let _ = self.reset();
+        if self.reset_on_drop {
+            let _ = self.reset();
+        }
 
         #[cfg(not(test))]
         //Allow reset to complete
@@ -310,6 +336,10 @@ impl Reset for Instrument {
         std::thread::sleep(Duration::from_millis(100));
         Ok(())
     }
+
+    fn set_reset_on_drop(&mut self, reset: bool) {
+        self.reset_on_drop = reset;
+    }
 }
 
 impl Abort for Instrument {
@@ -324,13 +354,26 @@ impl Abort for Instrument {
 #[cfg(test)]
 mod unit {
     use assert_matches::assert_matches;
-    use std::io::{BufRead, Read, Write};
+    use std::{
+        io::{Read, Write},
+        sync::{Arc, Mutex},
+    };
 
     use bytes::Buf;
     use mockall::{mock, Sequence};
 
     use crate::{
-        instrument::{self, authenticate::Authentication, info::Info, Language, Login, Script},
+        instrument::{
+            self,
+            authenticate::Authentication,
+            backup::{
+                restore_backup, Backup, BackupScript, BackupSource, RestoreOptions,
+                BACKUP_FORMAT_VERSION,
+            },
+            firmware::{FlashOptions, ProgressFormat},
+            info::Info,
+            Language, Login, Reset, Script,
+        },
         interface::{self, NonBlock},
         protocol::{self, raw::Raw},
         test_util, Flash, InstrumentError,
@@ -1256,17 +1299,19 @@ mod unit {
             .withf(|buf: &[u8]| buf == b"prevflash\n")
             .returning(|buf: &[u8]| Ok(buf.len()));
 
-        interface
-            .expect_write()
-            .times(1)
-            .in_sequence(&mut seq)
-            .withf(move |buf: &[u8]| {
-                buf == test_util::SIMPLE_FAKE_BINARY_FW
-                    .reader()
-                    .fill_buf()
-                    .unwrap()
-            })
-            .returning(|buf: &[u8]| Ok(buf.len()));
+        for chunk in [
+            test_util::SIMPLE_FAKE_BINARY_CHUNK0,
+            test_util::SIMPLE_FAKE_BINARY_CHUNK1,
+            test_util::SIMPLE_FAKE_BINARY_CHUNK2,
+            test_util::SIMPLE_FAKE_BINARY_CHUNK3,
+        ] {
+            interface
+                .expect_write()
+                .times(1)
+                .in_sequence(&mut seq)
+                .withf(move |buf: &[u8]| buf == chunk)
+                .returning(|buf: &[u8]| Ok(buf.len()));
+        }
 
         interface
             .expect_write()
@@ -1312,6 +1357,152 @@ mod unit {
             .expect("instrument should have written fw to MockInterface");
     }
 
+    #[test]
+    fn flash_firmware_cancelled() {
+        let mut interface = MockInterface::new();
+        let mut seq = Sequence::new();
+
+        interface.expect_flush().times(..).returning(|| Ok(()));
+
+        interface
+            .expect_set_nonblocking()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|enable| !*enable)
+            .returning(|_| Ok(()));
+
+        interface
+            .expect_write()
+            .times(..)
+            .withf(|buf: &[u8]| String::from_utf8_lossy(buf).contains("localnode.prompts"))
+            .returning(|buf: &[u8]| Ok(buf.len()));
+
+        interface
+            .expect_write()
+            .times(1)
+            .withf(|buf: &[u8]| buf == b"if ki.upgrade ~= nil and ki.upgrade.noacklater ~= nil then ki.upgrade.noacklater() end\n")
+            .returning(|buf: &[u8]| Ok(buf.len()) );
+
+        interface
+            .expect_write()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|buf: &[u8]| buf == b"prevflash\n")
+            .returning(|buf: &[u8]| Ok(buf.len()));
+
+        // No part of the image is written, and flash mode is aborted without `endflash`,
+        // which would start programming the image.
+        interface
+            .expect_write()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|buf: &[u8]| buf == b"\n")
+            .returning(|buf: &[u8]| Ok(buf.len()));
+        interface
+            .expect_write()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|buf: &[u8]| buf == b"abort\n")
+            .returning(|buf: &[u8]| Ok(buf.len()));
+
+        interface
+            .expect_set_nonblocking()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|enable| *enable)
+            .returning(|_| Ok(()));
+
+        // The instrument is no longer in flash mode, so it is aborted and reset when it
+        // is dropped.
+        interface
+            .expect_write()
+            .times(1)
+            .in_sequence(&mut seq)
+            .withf(|buf: &[u8]| buf == b"abort\n")
+            .returning(|buf: &[u8]| Ok(buf.len()));
+        interface
+            .expect_write()
+            .times(..)
+            .withf(|buf: &[u8]| buf == b"*RST\n" || buf == b"logout\n" || buf == b"abort\n")
+            .returning(|buf: &[u8]| Ok(buf.len()));
+        interface
+            .expect_write()
+            .times(0)
+            .withf(|buf: &[u8]| String::from_utf8_lossy(buf).contains("endflash"))
+            .returning(|buf: &[u8]| Ok(buf.len()));
+
+        let mut instrument: Instrument = Instrument::new(
+            protocol::Protocol::Raw(Raw::new(interface)),
+            Authentication::NoAuth,
+        );
+
+        let options = FlashOptions {
+            progress: ProgressFormat::None,
+            ..FlashOptions::default()
+        };
+        options.cancel.cancel();
+        assert_matches!(
+            instrument.flash_firmware_with(test_util::SIMPLE_FAKE_BINARY_FW, Some(0), &options),
+            Err(InstrumentError::FwUpgradeCancelled)
+        );
+    }
+
+    #[test]
+    fn restore_keeps_setups() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut interface = MockInterface::new();
+        interface.expect_flush().times(..).returning(|| Ok(()));
+        interface
+            .expect_read()
+            .times(..)
+            .returning(|_| Err(std::io::ErrorKind::WouldBlock.into()));
+        let record = Arc::clone(&written);
+        interface
+            .expect_write()
+            .times(..)
+            .returning(move |buf: &[u8]| {
+                record.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            });
+
+        let mut instrument: Instrument = Instrument::new(
+            protocol::Protocol::Raw(Raw::new(interface)),
+            Authentication::NoAuth,
+        );
+        let setup = BackupScript {
+            name: "setup".to_string(),
+            autorun: false,
+            source: "smu.source.level = 1\n".to_string(),
+        };
+        let backup = Backup {
+            format_version: BACKUP_FORMAT_VERSION,
+            created: String::new(),
+            instrument: BackupSource {
+                vendor: "KEITHLEY INSTRUMENTS".to_string(),
+                model: "2450".to_string(),
+                serial_number: "0123456".to_string(),
+                firmware_rev: None,
+            },
+            scripts: Vec::new(),
+            setups: vec![setup],
+            tsplink: None,
+        };
+        restore_backup(
+            &mut instrument,
+            &backup,
+            RestoreOptions { apply_setups: true },
+        )
+        .unwrap();
+        instrument.set_reset_on_drop(false);
+        drop(instrument);
+
+        let written = String::from_utf8_lossy(&written.lock().unwrap()).to_string();
+        let applied = written
+            .find("smu.source.level = 1")
+            .expect("the setup should have been written");
+        assert!(!written[applied..].contains("*RST"), "{written}");
+    }
+
     // Define a mock interface to be used in the tests above.
     mock! {
         Interface {}
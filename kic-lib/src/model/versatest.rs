@@ -5,8 +5,13 @@ use std::{
 
 use crate::{
     instrument::{
-        self, authenticate::Authentication, clear_output_queue, info::InstrumentInfo,
-        language::Language, read_until, Abort, Info, Login, Reset, Script,
+        self,
+        authenticate::Authentication,
+        clear_output_queue,
+        firmware::{FlashOptions, ProgressFormat, FW_CHUNK_SIZE},
+        info::InstrumentInfo,
+        language::Language,
+        read_until, Abort, Info, Login, Reset, Script,
     },
     interface::{connection_addr::ConnectionInfo, NonBlock},
     model::Model,
@@ -22,11 +27,12 @@ pub struct Instrument {
     protocol: Protocol,
     auth: Authentication,
     fw_flash_in_progress: bool,
+    reset_on_drop: bool,
 }
 
 impl Instrument {
     #[must_use]
-    pub const fn is(info: &InstrumentInfo) -> bool {
+    pub fn is(info: &InstrumentInfo) -> bool {
         info.model.is_mp()
     }
 
@@ -53,6 +59,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         })
     }
 
@@ -63,6 +70,7 @@ impl Instrument {
             protocol,
             auth,
             fw_flash_in_progress: false,
+            reset_on_drop: true,
         }
     }
 
@@ -144,6 +152,12 @@ impl Login for Instrument {
 
         Ok(())
     }
+
+    fn logout(&mut self) -> crate::error::Result<()> {
+        self.write_all(b"logout\n")?;
+        self.flush()?;
+        Ok(())
+    }
 }
 
 impl Script for Instrument {}
@@ -173,12 +187,21 @@ impl Write for Instrument {
 }
 
 impl Flash for Instrument {
-    #[allow(clippy::too_many_lines)] //It is ok for this to be long for now.
-    #[tracing::instrument(skip(self, image))]
     fn flash_firmware(
         &mut self,
         image: &[u8],
         firmware_info: Option<u16>,
+    ) -> crate::error::Result<()> {
+        self.flash_firmware_with(image, firmware_info, &FlashOptions::default())
+    }
+
+    #[allow(clippy::too_many_lines)] //It is ok for this to be long for now.
+    #[tracing::instrument(skip(self, image, options))]
+    fn flash_firmware_with(
+        &mut self,
+        image: &[u8],
+        firmware_info: Option<u16>,
+        options: &FlashOptions,
     ) -> crate::error::Result<()> {
         trace!(
             "Starting flash_firmware: image size = {} bytes, firmware_info = {:?}",
@@ -233,29 +256,24 @@ impl Flash for Instrument {
             }
         }
 
-        #[allow(irrefutable_let_patterns)] //This is marked as irrefutable when building without
-        //visa
-        let mut spinner = if let Protocol::Raw(_) = self.protocol {
-            let pb = ProgressBar::new(1);
-            #[allow(clippy::literal_string_with_formatting_args)]
-            // This is a template for ProgressStyle that requires this syntax
-            pb.set_style(
-                ProgressStyle::with_template(" {spinner:.green} [{elapsed_precise}] {msg}")
-                    .unwrap(),
-            );
-            pb.enable_steady_tick(Duration::from_millis(100));
-            pb.set_message("Loading Firmware...");
-
-            Some(pb)
-        } else {
-            None
-        };
-
         trace!("Disabling prompts and starting flash command");
         self.write_all(b"localnode.prompts=0\n")?;
         self.write_all(b"flash\n")?;
         trace!("Writing firmware image ({} bytes)", image.len());
-        self.write_all(image)?;
+
+        // Nothing but image data may be written while the instrument is in flash mode,
+        // so make sure a failed transfer doesn't cause `drop` to write anything.
+        self.fw_flash_in_progress = true;
+        if let Err(e) = instrument::firmware::transfer_image(self, image, FW_CHUNK_SIZE, options) {
+            // `endflash` starts the update, so flash mode is aborted instead and the
+            // partial image is discarded.
+            trace!("Firmware transfer stopped, aborting flash mode: {e}");
+            let _ = self.write_all(b"\n");
+            let _ = self.abort();
+            self.fw_flash_in_progress = false;
+            return Err(e);
+        }
+        self.fw_flash_in_progress = false;
 
         let mut loop_count = 0;
         loop {
@@ -282,7 +300,7 @@ impl Flash for Instrument {
             }
         }
 
-        if spinner.is_none() {
+        let spinner = if options.progress == ProgressFormat::Bar {
             trace!("Creating progress bar for firmware processing");
             let pb = ProgressBar::new(1);
             #[allow(clippy::literal_string_with_formatting_args)]
@@ -290,18 +308,11 @@ impl Flash for Instrument {
                 ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] {msg}").unwrap(),
             );
             pb.enable_steady_tick(Duration::from_millis(100));
-            spinner = Some(pb);
-        }
-
-        if let Some(pb) = &spinner {
             pb.set_message("Mainframe processing firmware...");
-        }
-
-        //let end_time = Instant::now();
-        //let duration = end_time.duration_since(start_time);
-        //let rate = std::convert::Into::<f64>::into(
-        //    TryInto::<u32>::try_into(image.len()).unwrap_or_default(),
-        //) / duration.as_secs_f64();
+            Some(pb)
+        } else {
+            None
+        };
 
         // Wait for 6 seconds before trying to communicate with the instrument because
         // we will get an IO timeout if we don't (during module update)
@@ -436,7 +447,9 @@ impl Drop for Instrument {
         let _ = self.write_all(b"abort\n");
         std::thread::sleep(Duration::from_millis(100));
 
-        let _ = self.reset();
+        if self.reset_on_drop {
+            let _ = self.reset();
+        }
 
         #[cfg(not(test))]
         //Allow reset to complete
@@ -466,6 +479,10 @@ impl Reset for Instrument {
         std::thread::sleep(Duration::from_millis(100));
         Ok(())
     }
+
+    fn set_reset_on_drop(&mut self, reset: bool) {
+        self.reset_on_drop = reset;
+    }
 }
 
 impl Abort for Instrument {
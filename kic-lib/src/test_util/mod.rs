@@ -1,6 +1,6 @@
 pub const SIMPLE_FAKE_BINARY_FW: &[u8] = include_bytes!("./simple_fake_binary_fw.test");
-pub const _SIMPLE_FAKE_BINARY_CHUNK0: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk0");
-pub const _SIMPLE_FAKE_BINARY_CHUNK1: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk1");
-pub const _SIMPLE_FAKE_BINARY_CHUNK2: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk2");
-pub const _SIMPLE_FAKE_BINARY_CHUNK3: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk3");
+pub const SIMPLE_FAKE_BINARY_CHUNK0: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk0");
+pub const SIMPLE_FAKE_BINARY_CHUNK1: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk1");
+pub const SIMPLE_FAKE_BINARY_CHUNK2: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk2");
+pub const SIMPLE_FAKE_BINARY_CHUNK3: &[u8] = include_bytes!("./simple_fake_binary_fw.chunk3");
 pub const SIMPLE_FAKE_TEXTUAL_FW: &[u8] = include_bytes!("./simple_fake_textual_fw.test");
@@ -0,0 +1,310 @@
+//! Split TSP source into tokens.
+
+use std::ops::Range;
+
+use crate::tsp::SyntaxError;
+
+pub(crate) const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Symbols, longest first so that e.g. `...` is not read as `..` followed by `.`. Lua
+/// 5.0 has no `#` or `%` operators.
+const SYMBOLS: &[&str] = &[
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "^", "<", ">", "=", "(", ")", "{",
+    "}", "[", "]", ";", ":", ",", ".",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Tok {
+    Name(String),
+    Keyword(&'static str),
+    Symbol(&'static str),
+    Number,
+    String,
+    /// A comment, only produced by [`tokenize_with_comments`]
+    Comment,
+    Eof,
+}
+
+impl Tok {
+    /// How the token is shown in error messages.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Name(n) => n.clone(),
+            Self::Keyword(k) | Self::Symbol(k) => (*k).to_string(),
+            Self::Number => "<number>".to_string(),
+            Self::String => "<string>".to_string(),
+            Self::Comment => "<comment>".to_string(),
+            Self::Eof => "<eof>".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub(crate) tok: Tok,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    /// The characters of the source that the token was read from
+    pub(crate) span: Range<usize>,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    /// Whether comments are returned as [`Tok::Comment`] instead of being skipped
+    comments: bool,
+}
+
+impl Lexer {
+    fn new(source: &str, comments: bool) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            comments,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos.saturating_add(offset)).copied()
+    }
+
+    fn column(&self) -> usize {
+        self.pos.saturating_sub(self.line_start).saturating_add(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos = self.pos.saturating_add(1);
+        if c == '\n' {
+            self.line = self.line.saturating_add(1);
+            self.line_start = self.pos;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError {
+            line: self.line,
+            column: self.column(),
+            message: message.into(),
+        }
+    }
+
+    /// Whether a long bracket `[[` starts at the current position. Lua 5.0 has no
+    /// levelled brackets like `[==[`.
+    fn at_long_bracket(&self) -> bool {
+        self.peek(0) == Some('[') && self.peek(1) == Some('[')
+    }
+
+    /// Skip a long string or comment, including its brackets. Long brackets nest in
+    /// Lua 5.0, so `[[a [[b]] c]]` is a single string.
+    fn skip_long_bracket(&mut self, what: &str) -> Result<(), SyntaxError> {
+        let start_line = self.line;
+        self.bump();
+        self.bump();
+        let mut depth = 1usize;
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (None, _) => {
+                    return Err(self.error(format!(
+                        "unfinished long {what} (starting at line {start_line}) near '<eof>'"
+                    )))
+                }
+                (Some('['), Some('[')) => {
+                    self.bump();
+                    self.bump();
+                    depth = depth.saturating_add(1);
+                }
+                (Some(']'), Some(']')) => {
+                    self.bump();
+                    self.bump();
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    /// Skip whitespace and comments, or only whitespace if comments are kept, in which
+    /// case the comment after it is returned.
+    fn skip_whitespace_and_comments(&mut self) -> Result<Option<Token>, SyntaxError> {
+        loop {
+            let (start, line, column) = (self.pos, self.line, self.column());
+            match self.peek(0) {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                Some('-') if self.peek(1) == Some('-') => {
+                    self.bump();
+                    self.bump();
+                    if self.at_long_bracket() {
+                        self.skip_long_bracket("comment")?;
+                    } else {
+                        while self.peek(0).is_some_and(|c| c != '\n') {
+                            self.bump();
+                        }
+                    }
+                }
+                // A first line starting with `#` (e.g. `#!`) is skipped by Lua.
+                Some('#') if self.pos == 0 => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => return Ok(None),
+            }
+            if self.comments {
+                return Ok(Some(Token {
+                    tok: Tok::Comment,
+                    line,
+                    column,
+                    span: start..self.pos,
+                }));
+            }
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<(), SyntaxError> {
+        let (start, column) = (self.pos, self.column());
+        self.bump();
+        loop {
+            match self.peek(0) {
+                None | Some('\n') => {
+                    let near: String = self.chars[start..self.pos].iter().collect();
+                    return Err(SyntaxError {
+                        line: self.line,
+                        column,
+                        message: format!("unfinished string near '{near}'"),
+                    });
+                }
+                Some('\\') => {
+                    self.bump();
+                    self.bump();
+                }
+                Some(c) => {
+                    self.bump();
+                    if c == quote {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) {
+        let hex = self.peek(0) == Some('0') && matches!(self.peek(1), Some('x' | 'X'));
+        if hex {
+            self.bump();
+            self.bump();
+        }
+        while let Some(c) = self.peek(0) {
+            let exponent = if hex {
+                matches!(c, 'p' | 'P')
+            } else {
+                matches!(c, 'e' | 'E')
+            };
+            if exponent && matches!(self.peek(1), Some('+' | '-')) {
+                self.bump();
+                self.bump();
+            } else if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, SyntaxError> {
+        if let Some(comment) = self.skip_whitespace_and_comments()? {
+            return Ok(comment);
+        }
+        let (start, line, column) = (self.pos, self.line, self.column());
+        let token = |tok, end| Token {
+            tok,
+            line,
+            column,
+            span: start..end,
+        };
+
+        let Some(c) = self.peek(0) else {
+            return Ok(token(Tok::Eof, start));
+        };
+
+        if c.is_alphabetic() || c == '_' {
+            let start = self.pos;
+            while self
+                .peek(0)
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+            {
+                self.bump();
+            }
+            let word: String = self.chars[start..self.pos].iter().collect();
+            return Ok(token(
+                KEYWORDS
+                    .iter()
+                    .find(|k| **k == word)
+                    .map_or(Tok::Name(word), |k| Tok::Keyword(k)),
+                self.pos,
+            ));
+        }
+        if c.is_ascii_digit() || (c == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit())) {
+            self.number();
+            return Ok(token(Tok::Number, self.pos));
+        }
+        if c == '"' || c == '\'' {
+            self.string(c)?;
+            return Ok(token(Tok::String, self.pos));
+        }
+        if self.at_long_bracket() {
+            self.skip_long_bracket("string")?;
+            return Ok(token(Tok::String, self.pos));
+        }
+        for s in SYMBOLS {
+            if s.chars()
+                .enumerate()
+                .all(|(i, sc)| self.peek(i) == Some(sc))
+            {
+                for _ in 0..s.len() {
+                    self.bump();
+                }
+                return Ok(token(Tok::Symbol(s), self.pos));
+            }
+        }
+        Err(self.error(format!("unexpected symbol near '{c}'")))
+    }
+
+    fn tokens(mut self) -> Result<Vec<Token>, SyntaxError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let eof = token.tok == Tok::Eof;
+            tokens.push(token);
+            if eof {
+                return Ok(tokens);
+            }
+        }
+    }
+}
+
+/// Split `source` into tokens, ending with [`Tok::Eof`].
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Token>, SyntaxError> {
+    Lexer::new(source, false).tokens()
+}
+
+/// Split `source` into tokens like [`tokenize`], including its comments.
+pub(crate) fn tokenize_with_comments(source: &str) -> Result<Vec<Token>, SyntaxError> {
+    Lexer::new(source, true).tokens()
+}
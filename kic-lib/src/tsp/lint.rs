@@ -0,0 +1,232 @@
+//! Look for names in a TSP script that will not resolve on an instrument.
+//!
+//! The globals that instruments define are read from `globals.json`, which lists the
+//! globals that all instruments have and those that only the models of a [`Family`]
+//! have.
+
+use std::{collections::HashSet, fmt::Display, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{model::Family, tsp::parser::parse};
+
+const EMBEDDED_GLOBALS: &str = include_str!("./globals.json");
+
+static GLOBALS: LazyLock<Globals> = LazyLock::new(|| {
+    serde_json::from_str(EMBEDDED_GLOBALS).expect("embedded TSP globals should be valid")
+});
+
+/// The rule reported for syntax errors.
+pub const RULE_SYNTAX: &str = "syntax";
+
+/// The rule reported for globals that are read, but never assigned or defined by an
+/// instrument.
+pub const RULE_UNDEFINED_GLOBAL: &str = "undefined-global";
+
+/// The rule reported for command paths that exist on instruments of another family, but
+/// not on the instrument the script is for.
+pub const RULE_UNKNOWN_COMMAND_PATH: &str = "unknown-command-path";
+
+#[derive(Debug, Deserialize)]
+struct FamilyGlobals {
+    family: Family,
+    globals: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Globals {
+    common: Vec<String>,
+    families: Vec<FamilyGlobals>,
+}
+
+impl Globals {
+    fn is_common(&self, name: &str) -> bool {
+        self.common.iter().any(|g| g == name)
+    }
+
+    /// Whether `name` is defined on `family`, or on any family if `family` is `None`.
+    fn is_defined(&self, name: &str, family: Option<&Family>) -> bool {
+        self.is_common(name)
+            || self
+                .families
+                .iter()
+                .filter(|f| family.is_none_or(|family| f.family == *family))
+                .any(|f| f.globals.iter().any(|g| g == name))
+    }
+
+    /// The families `name` is defined on, other than through the common globals.
+    fn families_of(&self, name: &str) -> Vec<&Family> {
+        self.families
+            .iter()
+            .filter(|f| f.globals.iter().any(|g| g == name))
+            .map(|f| &f.family)
+            .collect()
+    }
+}
+
+/// Whether `name` is a global that instruments of `family` define, or that instruments
+/// of any family define if `family` is `None`.
+#[must_use]
+pub fn is_instrument_global(name: &str, family: Option<&Family>) -> bool {
+    GLOBALS.is_defined(name, family)
+}
+
+/// How serious a [`LintMessage`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    Warning,
+    Error,
+}
+
+impl Display for LintSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a TSP script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintMessage {
+    /// The line of the problem, starting at 1
+    pub line: usize,
+    /// The column of the problem, starting at 1
+    pub column: usize,
+    /// The name of the rule that found the problem
+    pub rule: &'static str,
+    /// How serious the problem is
+    pub severity: LintSeverity,
+    /// What is wrong
+    pub message: String,
+}
+
+impl Display for LintMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {} [{}] {}",
+            self.line, self.column, self.severity, self.rule, self.message
+        )
+    }
+}
+
+/// Check `source` for syntax errors and for globals that will not resolve on an
+/// instrument of `family`. If `family` is `None`, globals of any family are accepted.
+#[must_use]
+pub fn lint(source: &str, family: Option<&Family>) -> Vec<LintMessage> {
+    let chunk = match parse(source) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            return vec![LintMessage {
+                line: e.line,
+                column: e.column,
+                rule: RULE_SYNTAX,
+                severity: LintSeverity::Error,
+                message: e.message,
+            }]
+        }
+    };
+
+    let assigned: HashSet<&str> = chunk
+        .globals
+        .iter()
+        .filter(|g| g.write)
+        .map(|g| g.path[0].as_str())
+        .collect();
+
+    let mut reported = HashSet::new();
+    let mut messages = Vec::new();
+    for global in chunk.globals.iter().filter(|g| !g.write) {
+        let name = global.path[0].as_str();
+        if assigned.contains(name) || GLOBALS.is_defined(name, family) || !reported.insert(name) {
+            continue;
+        }
+        let others = GLOBALS.families_of(name);
+        let message = match family {
+            Some(family) if !others.is_empty() => LintMessage {
+                line: global.line,
+                column: global.column,
+                rule: RULE_UNKNOWN_COMMAND_PATH,
+                severity: LintSeverity::Error,
+                message: format!(
+                    "`{}` is not available on {} instruments",
+                    global.path.join("."),
+                    family_name(family)
+                ),
+            },
+            _ => LintMessage {
+                line: global.line,
+                column: global.column,
+                rule: RULE_UNDEFINED_GLOBAL,
+                severity: LintSeverity::Warning,
+                message: format!("`{name}` is used but never assigned"),
+            },
+        };
+        messages.push(message);
+    }
+    messages
+}
+
+pub(crate) fn family_name(family: &Family) -> String {
+    serde_json::to_value(family)
+        .ok()
+        .and_then(|v| v.as_str().map(ToString::to_string))
+        .unwrap_or_else(|| format!("{family:?}"))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{lint, RULE_SYNTAX, RULE_UNDEFINED_GLOBAL, RULE_UNKNOWN_COMMAND_PATH};
+    use crate::model::Family;
+
+    #[test]
+    fn rules() {
+        let source = "\
+count = 0
+function measure()
+    count = count + 1
+    return smua.measure.i()
+end
+print(measure(), undefined_value, undefined_value)
+smu.source.level = 1
+";
+        let rules = |family| -> Vec<_> {
+            lint(source, family)
+                .into_iter()
+                .map(|m| (m.line, m.rule, m.message))
+                .collect()
+        };
+
+        assert_eq!(
+            rules(Some(&Family::_26xx)),
+            vec![
+                (
+                    6,
+                    RULE_UNDEFINED_GLOBAL,
+                    "`undefined_value` is used but never assigned".to_string()
+                ),
+                (
+                    7,
+                    RULE_UNKNOWN_COMMAND_PATH,
+                    "`smu.source.level` is not available on 26xx instruments".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            rules(Some(&Family::Tti))[0],
+            (
+                4,
+                RULE_UNKNOWN_COMMAND_PATH,
+                "`smua.measure.i` is not available on tti instruments".to_string()
+            )
+        );
+        assert_eq!(rules(None).len(), 1);
+
+        let syntax = lint("if true then", None);
+        assert_eq!(syntax.len(), 1);
+        assert_eq!(syntax[0].rule, RULE_SYNTAX);
+    }
+}
@@ -0,0 +1,176 @@
+//! Make TSP scripts smaller before they are uploaded, by removing comments and
+//! whitespace that the instrument does not need.
+//!
+//! The lines of a script are kept, so that the line numbers in the errors reported by
+//! the instrument still match the local file.
+
+use std::ops::Range;
+
+use crate::tsp::lexer::{tokenize_with_comments, Tok};
+
+/// What is removed from a script by [`minify`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Minify {
+    /// Remove comments
+    pub comments: bool,
+    /// Remove indentation and trailing whitespace, and collapse the whitespace between
+    /// tokens to a single space
+    pub whitespace: bool,
+}
+
+impl Minify {
+    /// Remove comments and whitespace.
+    pub const ALL: Self = Self {
+        comments: true,
+        whitespace: true,
+    };
+
+    /// Whether nothing is removed.
+    #[must_use]
+    pub const fn is_none(self) -> bool {
+        !self.comments && !self.whitespace
+    }
+}
+
+struct Minifier {
+    options: Minify,
+    output: String,
+    /// Whether whitespace, or a comment, was removed since the last code that was kept
+    pending_space: bool,
+}
+
+impl Minifier {
+    /// Keep the code `text`, with a space before it if something that separated it
+    /// from the code before was removed.
+    fn keep(&mut self, text: &str) {
+        if self.pending_space
+            && !self
+                .output
+                .chars()
+                .last()
+                .is_none_or(|l| l == '\n' || l.is_whitespace())
+        {
+            self.output.push(' ');
+        }
+        self.pending_space = false;
+        self.output.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.pending_space = false;
+        self.output.push('\n');
+    }
+
+    /// The whitespace between two tokens.
+    fn whitespace(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.newline();
+            } else if self.options.whitespace {
+                self.pending_space = true;
+            } else {
+                self.output.push(c);
+            }
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        if self.options.comments {
+            // The lines of a long comment are kept as empty lines.
+            for _ in text.matches('\n') {
+                self.newline();
+            }
+            self.pending_space = true;
+        } else {
+            self.keep(text);
+        }
+    }
+}
+
+/// Remove the comments and whitespace selected by `options` from `source`. Strings are
+/// kept as they are, and so are the lines of the script.
+///
+/// A script that can't be split into tokens is returned as it is, its syntax error is
+/// reported when it is checked.
+#[must_use]
+pub fn minify(source: &str, options: Minify) -> String {
+    if options.is_none() {
+        return source.to_string();
+    }
+    let Ok(tokens) = tokenize_with_comments(source) else {
+        return source.to_string();
+    };
+    let chars: Vec<char> = source.chars().collect();
+    let text = |span: Range<usize>| {
+        chars
+            .get(span)
+            .unwrap_or_default()
+            .iter()
+            .collect::<String>()
+    };
+
+    let mut minifier = Minifier {
+        options,
+        output: String::with_capacity(source.len()),
+        pending_space: false,
+    };
+    let mut end = 0;
+    for token in tokens {
+        minifier.whitespace(&text(end..token.span.start));
+        match token.tok {
+            Tok::Comment => minifier.comment(&text(token.span.clone())),
+            Tok::Eof => {}
+            _ => minifier.keep(&text(token.span.clone())),
+        }
+        end = token.span.end;
+    }
+    minifier.output
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{minify, Minify};
+    use crate::tsp::check_syntax;
+
+    #[test]
+    fn minification() {
+        let source = "\
+-- Set the level
+function  set(level)   -- in volts
+    smu.source.level = level
+    print(\"a  -- b\", [[
+    kept   as is]])
+end--[[ a
+long comment ]]set(1)
+local x = 1 - -1
+";
+        let comments = minify(
+            source,
+            Minify {
+                comments: true,
+                whitespace: false,
+            },
+        );
+        assert_eq!(
+            comments,
+            "\nfunction  set(level)   \n    smu.source.level = level\n    print(\"a  -- b\", [[\n    kept   as is]])\nend\nset(1)\nlocal x = 1 - -1\n"
+        );
+
+        let all = minify(source, Minify::ALL);
+        assert_eq!(
+            all,
+            "\nfunction set(level)\nsmu.source.level = level\nprint(\"a  -- b\", [[\n    kept   as is]])\nend\nset(1)\nlocal x = 1 - -1\n"
+        );
+        assert_eq!(all.lines().count(), source.lines().count());
+        assert_eq!(check_syntax(&all), Ok(()));
+
+        assert_eq!(minify("a--[[x]]b", Minify::ALL), "a b");
+        assert_eq!(
+            minify("s = [[a [[b]]  -- c]] --[[d [[e]] ]] -- f", Minify::ALL),
+            "s = [[a [[b]]  -- c]]"
+        );
+        // Not TSP, so it is left for the syntax check to report.
+        assert_eq!(minify("x = 10 % 3 -- c", Minify::ALL), "x = 10 % 3 -- c");
+        assert_eq!(minify(source, Minify::default()), source);
+    }
+}
@@ -0,0 +1,59 @@
+//! Check TSP scripts locally, before they are uploaded to an instrument.
+//!
+//! TSP is based on Lua 5.0. [`check_syntax`] reports the first syntax error in a script
+//! the way the instrument would, and [`lint::lint`] looks for names that will not
+//! resolve on the instrument. [`preprocess::preprocess`] expands the `--#include`,
+//! `--#define` and `--#if` directives of a script before it is checked, and
+//! [`minify::minify`] removes its comments and whitespace before it is uploaded.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+mod lexer;
+pub mod lint;
+pub mod minify;
+mod parser;
+pub mod preprocess;
+
+pub use lint::{lint, LintMessage, LintSeverity};
+pub use minify::{minify, Minify};
+pub use preprocess::{parse_define, preprocess, PreprocessError, Preprocessed};
+
+/// A syntax error in a TSP script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyntaxError {
+    /// The line of the error, starting at 1
+    pub line: usize,
+    /// The column of the error, starting at 1
+    pub column: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Whether `name` is a Lua name, e.g. of a variable or function, and not a keyword.
+#[must_use]
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !lexer::KEYWORDS.contains(&name)
+}
+
+/// Check that `source` is a syntactically valid TSP script.
+///
+/// # Errors
+/// Returns the first [`SyntaxError`] in `source`.
+pub fn check_syntax(source: &str) -> Result<(), SyntaxError> {
+    parser::parse(source).map(|_| ())
+}
@@ -0,0 +1,694 @@
+//! A recursive descent parser for the Lua 5.0 grammar that TSP is based on.
+//!
+//! The parser does not build a syntax tree, it only checks the syntax and records where
+//! global names are used, which is all the checks in this module need.
+
+use crate::tsp::{
+    lexer::{tokenize, Tok, Token},
+    SyntaxError,
+};
+
+/// A use of a global name, e.g. `smua.source.levelv` in
+/// `smua.source.levelv = 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GlobalRef {
+    /// The global name followed by the names of the fields indexed on it
+    pub(crate) path: Vec<String>,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    /// Whether the global itself is assigned, as opposed to read or indexed
+    pub(crate) write: bool,
+}
+
+/// The result of parsing a script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub(crate) globals: Vec<GlobalRef>,
+}
+
+/// How an expression ended, which decides whether it can be assigned to or used as a
+/// statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExpKind {
+    /// A name, with the index of its [`GlobalRef`] if it is a global
+    Name(Option<usize>),
+    /// An indexed field
+    Field,
+    /// A function or method call
+    Call,
+    /// Anything else
+    Other,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    scopes: Vec<Vec<String>>,
+    globals: Vec<GlobalRef>,
+}
+
+/// Parse `source` and record the globals it uses.
+pub(crate) fn parse(source: &str) -> Result<Chunk, SyntaxError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        scopes: vec![Vec::new()],
+        globals: Vec::new(),
+    };
+    parser.block()?;
+    if parser.tok() != &Tok::Eof {
+        return Err(parser.error_near("'<eof>' expected"));
+    }
+    Ok(Chunk {
+        globals: parser.globals,
+    })
+}
+
+impl Parser {
+    fn token(&self) -> &Token {
+        // The last token is always `Eof`, which is never consumed.
+        &self.tokens[self.pos.min(self.tokens.len().saturating_sub(1))]
+    }
+
+    fn tok(&self) -> &Tok {
+        &self.token().tok
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.token().clone();
+        if token.tok != Tok::Eof {
+            self.pos = self.pos.saturating_add(1);
+        }
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.tok(), Tok::Symbol(s) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tok(), Tok::Keyword(k) if *k == keyword)
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn error_near(&self, message: &str) -> SyntaxError {
+        let token = self.token();
+        SyntaxError {
+            line: token.line,
+            column: token.column,
+            message: format!("{message} near '{}'", token.tok.describe()),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), SyntaxError> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error_near(&format!("'{symbol}' expected")))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        if self.accept_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error_near(&format!("'{keyword}' expected")))
+        }
+    }
+
+    /// Expect the keyword that closes a block that was opened by `opener` at `line`.
+    fn expect_match(
+        &mut self,
+        keyword: &str,
+        opener: &str,
+        line: usize,
+    ) -> Result<(), SyntaxError> {
+        if self.accept_keyword(keyword) {
+            Ok(())
+        } else if self.token().line == line {
+            Err(self.error_near(&format!("'{keyword}' expected")))
+        } else {
+            Err(self.error_near(&format!(
+                "'{keyword}' expected (to close '{opener}' at line {line})"
+            )))
+        }
+    }
+
+    fn name(&mut self) -> Result<Token, SyntaxError> {
+        if matches!(self.tok(), Tok::Name(_)) {
+            Ok(self.advance())
+        } else {
+            Err(self.error_near("<name> expected"))
+        }
+    }
+
+    fn name_string(&mut self) -> Result<String, SyntaxError> {
+        match self.name()?.tok {
+            Tok::Name(n) => Ok(n),
+            _ => unreachable!("name() only returns names"),
+        }
+    }
+
+    fn open_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn close_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: String) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(name);
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|s| s.iter().any(|n| n == name))
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(self.tok(), Tok::Eof)
+            || ["else", "elseif", "end", "until"]
+                .iter()
+                .any(|k| self.is_keyword(k))
+    }
+
+    fn block(&mut self) -> Result<(), SyntaxError> {
+        while !self.block_follows() {
+            if self.is_keyword("return") || self.is_keyword("break") {
+                let is_return = self.accept_keyword("return");
+                if !is_return {
+                    self.advance();
+                }
+                if is_return && !self.block_follows() && !self.is_symbol(";") {
+                    self.exp_list()?;
+                }
+                // Whatever follows must close the block, which the caller checks.
+                self.accept_symbol(";");
+                return Ok(());
+            }
+            self.statement()?;
+            self.accept_symbol(";");
+        }
+        Ok(())
+    }
+
+    /// A block with its own scope, e.g. the body of a `do ... end`.
+    fn scoped_block(&mut self) -> Result<(), SyntaxError> {
+        self.open_scope();
+        let result = self.block();
+        self.close_scope();
+        result
+    }
+
+    fn statement(&mut self) -> Result<(), SyntaxError> {
+        let line = self.token().line;
+        match self.tok().clone() {
+            Tok::Keyword("do") => {
+                self.advance();
+                self.scoped_block()?;
+                self.expect_match("end", "do", line)
+            }
+            Tok::Keyword("while") => {
+                self.advance();
+                self.exp()?;
+                self.expect_keyword("do")?;
+                self.scoped_block()?;
+                self.expect_match("end", "while", line)
+            }
+            Tok::Keyword("repeat") => {
+                self.advance();
+                // The condition can see the locals of the body.
+                self.open_scope();
+                let result = self.block().and_then(|()| {
+                    self.expect_match("until", "repeat", line)?;
+                    self.exp().map(|_| ())
+                });
+                self.close_scope();
+                result
+            }
+            Tok::Keyword("if") => {
+                self.advance();
+                self.exp()?;
+                self.expect_keyword("then")?;
+                self.scoped_block()?;
+                while self.accept_keyword("elseif") {
+                    self.exp()?;
+                    self.expect_keyword("then")?;
+                    self.scoped_block()?;
+                }
+                if self.accept_keyword("else") {
+                    self.scoped_block()?;
+                }
+                self.expect_match("end", "if", line)
+            }
+            Tok::Keyword("for") => self.for_statement(line),
+            Tok::Keyword("function") => {
+                self.advance();
+                let first = self.name()?;
+                let mut path = vec![token_name(&first)];
+                let mut method = false;
+                while self.is_symbol(".") || self.is_symbol(":") {
+                    method = self.is_symbol(":");
+                    self.advance();
+                    path.push(self.name_string()?);
+                    if method {
+                        break;
+                    }
+                }
+                if !self.is_local(&path[0]) {
+                    let write = path.len() == 1;
+                    self.globals.push(GlobalRef {
+                        path,
+                        line: first.line,
+                        column: first.column,
+                        write,
+                    });
+                }
+                self.function_body(method, line)
+            }
+            Tok::Keyword("local") => {
+                self.advance();
+                if self.accept_keyword("function") {
+                    let name = self.name_string()?;
+                    self.declare(name);
+                    self.function_body(false, line)
+                } else {
+                    let mut names = vec![self.name_string()?];
+                    while self.accept_symbol(",") {
+                        names.push(self.name_string()?);
+                    }
+                    if self.accept_symbol("=") {
+                        self.exp_list()?;
+                    }
+                    for name in names {
+                        self.declare(name);
+                    }
+                    Ok(())
+                }
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn for_statement(&mut self, line: usize) -> Result<(), SyntaxError> {
+        self.advance();
+        let mut names = vec![self.name_string()?];
+        if self.accept_symbol("=") {
+            self.exp()?;
+            self.expect_symbol(",")?;
+            self.exp()?;
+            if self.accept_symbol(",") {
+                self.exp()?;
+            }
+        } else {
+            while self.accept_symbol(",") {
+                names.push(self.name_string()?);
+            }
+            if !self.accept_keyword("in") {
+                return Err(self.error_near("'=' or 'in' expected"));
+            }
+            self.exp_list()?;
+        }
+        self.expect_keyword("do")?;
+        self.open_scope();
+        for name in names {
+            self.declare(name);
+        }
+        let result = self.block();
+        self.close_scope();
+        result?;
+        self.expect_match("end", "for", line)
+    }
+
+    fn expression_statement(&mut self) -> Result<(), SyntaxError> {
+        let kind = self.suffixed_exp()?;
+        if self.is_symbol("=") || self.is_symbol(",") {
+            let mut targets = vec![kind];
+            while self.accept_symbol(",") {
+                targets.push(self.suffixed_exp()?);
+            }
+            if targets
+                .iter()
+                .any(|k| matches!(k, ExpKind::Call | ExpKind::Other))
+            {
+                return Err(self.error_near("syntax error"));
+            }
+            self.expect_symbol("=")?;
+            for target in targets {
+                if let ExpKind::Name(Some(index)) = target {
+                    if let Some(global) = self.globals.get_mut(index) {
+                        global.write = true;
+                    }
+                }
+            }
+            self.exp_list()?;
+            Ok(())
+        } else if kind == ExpKind::Call {
+            Ok(())
+        } else {
+            Err(self.error_near("syntax error"))
+        }
+    }
+
+    fn function_body(&mut self, method: bool, line: usize) -> Result<(), SyntaxError> {
+        self.open_scope();
+        if method {
+            self.declare("self".to_string());
+        }
+        let result = self.parameters().and_then(|()| self.block());
+        self.close_scope();
+        result?;
+        self.expect_match("end", "function", line)
+    }
+
+    fn parameters(&mut self) -> Result<(), SyntaxError> {
+        self.expect_symbol("(")?;
+        if !self.is_symbol(")") {
+            loop {
+                if self.accept_symbol("...") {
+                    // Lua 5.0 passes the extra arguments in the local `arg`.
+                    self.declare("arg".to_string());
+                    break;
+                }
+                let name = self.name_string()?;
+                self.declare(name);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(")")
+    }
+
+    fn exp_list(&mut self) -> Result<(), SyntaxError> {
+        self.exp()?;
+        while self.accept_symbol(",") {
+            self.exp()?;
+        }
+        Ok(())
+    }
+
+    fn is_unary_operator(&self) -> bool {
+        self.is_keyword("not") || self.is_symbol("-")
+    }
+
+    fn is_binary_operator(&self) -> bool {
+        self.is_keyword("and")
+            || self.is_keyword("or")
+            || [
+                "+", "-", "*", "/", "^", "..", "==", "~=", "<", "<=", ">", ">=",
+            ]
+            .iter()
+            .any(|s| self.is_symbol(s))
+    }
+
+    fn exp(&mut self) -> Result<ExpKind, SyntaxError> {
+        let mut kind = self.unary_exp()?;
+        while self.is_binary_operator() {
+            self.advance();
+            self.unary_exp()?;
+            kind = ExpKind::Other;
+        }
+        Ok(kind)
+    }
+
+    fn unary_exp(&mut self) -> Result<ExpKind, SyntaxError> {
+        if self.is_unary_operator() {
+            self.advance();
+            self.unary_exp()?;
+            return Ok(ExpKind::Other);
+        }
+        self.simple_exp()
+    }
+
+    fn simple_exp(&mut self) -> Result<ExpKind, SyntaxError> {
+        let line = self.token().line;
+        match self.tok() {
+            Tok::Number
+            | Tok::String
+            | Tok::Keyword("nil" | "true" | "false")
+            | Tok::Symbol("...") => {
+                self.advance();
+                Ok(ExpKind::Other)
+            }
+            Tok::Symbol("{") => {
+                self.table()?;
+                Ok(ExpKind::Other)
+            }
+            Tok::Keyword("function") => {
+                self.advance();
+                self.function_body(false, line)?;
+                Ok(ExpKind::Other)
+            }
+            _ => self.suffixed_exp(),
+        }
+    }
+
+    fn primary_exp(&mut self) -> Result<ExpKind, SyntaxError> {
+        let line = self.token().line;
+        match self.tok().clone() {
+            Tok::Name(name) => {
+                let token = self.advance();
+                if self.is_local(&name) {
+                    Ok(ExpKind::Name(None))
+                } else {
+                    let mut path = vec![name];
+                    // Record the names of the fields that are indexed on the global.
+                    let mut offset = 0usize;
+                    while matches!(
+                        self.tokens
+                            .get(self.pos.saturating_add(offset))
+                            .map(|t| &t.tok),
+                        Some(Tok::Symbol("." | ":"))
+                    ) {
+                        match self
+                            .tokens
+                            .get(self.pos.saturating_add(offset).saturating_add(1))
+                            .map(|t| &t.tok)
+                        {
+                            Some(Tok::Name(field)) => path.push(field.clone()),
+                            _ => break,
+                        }
+                        offset = offset.saturating_add(2);
+                    }
+                    self.globals.push(GlobalRef {
+                        path,
+                        line: token.line,
+                        column: token.column,
+                        write: false,
+                    });
+                    Ok(ExpKind::Name(Some(self.globals.len().saturating_sub(1))))
+                }
+            }
+            Tok::Symbol("(") => {
+                self.advance();
+                self.exp()?;
+                self.expect_match_symbol(")", "(", line)?;
+                Ok(ExpKind::Other)
+            }
+            _ => Err(self.error_near("unexpected symbol")),
+        }
+    }
+
+    fn expect_match_symbol(
+        &mut self,
+        symbol: &str,
+        opener: &str,
+        line: usize,
+    ) -> Result<(), SyntaxError> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else if self.token().line == line {
+            Err(self.error_near(&format!("'{symbol}' expected")))
+        } else {
+            Err(self.error_near(&format!(
+                "'{symbol}' expected (to close '{opener}' at line {line})"
+            )))
+        }
+    }
+
+    fn suffixed_exp(&mut self) -> Result<ExpKind, SyntaxError> {
+        let mut kind = self.primary_exp()?;
+        loop {
+            match self.tok() {
+                Tok::Symbol(".") => {
+                    self.advance();
+                    self.name()?;
+                    kind = ExpKind::Field;
+                }
+                Tok::Symbol("[") => {
+                    let line = self.token().line;
+                    self.advance();
+                    self.exp()?;
+                    self.expect_match_symbol("]", "[", line)?;
+                    kind = ExpKind::Field;
+                }
+                Tok::Symbol(":") => {
+                    self.advance();
+                    self.name()?;
+                    self.call_arguments()?;
+                    kind = ExpKind::Call;
+                }
+                Tok::Symbol("(" | "{") | Tok::String => {
+                    self.call_arguments()?;
+                    kind = ExpKind::Call;
+                }
+                _ => return Ok(kind),
+            }
+        }
+    }
+
+    fn call_arguments(&mut self) -> Result<(), SyntaxError> {
+        let line = self.token().line;
+        match self.tok() {
+            Tok::String => {
+                self.advance();
+                Ok(())
+            }
+            Tok::Symbol("{") => self.table(),
+            Tok::Symbol("(") => {
+                self.advance();
+                if !self.is_symbol(")") {
+                    self.exp_list()?;
+                }
+                self.expect_match_symbol(")", "(", line)
+            }
+            _ => Err(self.error_near("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<(), SyntaxError> {
+        let line = self.token().line;
+        self.expect_symbol("{")?;
+        while !self.is_symbol("}") {
+            if self.accept_symbol("[") {
+                self.exp()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol("=")?;
+                self.exp()?;
+            } else if matches!(self.tok(), Tok::Name(_))
+                && matches!(
+                    self.tokens.get(self.pos.saturating_add(1)).map(|t| &t.tok),
+                    Some(Tok::Symbol("="))
+                )
+            {
+                self.advance();
+                self.advance();
+                self.exp()?;
+            } else {
+                self.exp()?;
+            }
+            if !self.accept_symbol(",") && !self.accept_symbol(";") {
+                break;
+            }
+        }
+        self.expect_match_symbol("}", "{", line)
+    }
+}
+
+fn token_name(token: &Token) -> String {
+    match &token.tok {
+        Tok::Name(n) => n.clone(),
+        other => other.describe(),
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::parse;
+
+    #[test]
+    fn valid_syntax() {
+        let source = r#"#!/usr/bin/env tsp
+            local function sweep(smu, levels, ...)
+                for i, v in ipairs(levels) do
+                    smu.source.levelv = v
+                    readings[i] = smu.measure.i()
+                end
+                return table.getn(arg)
+            end
+            local t = { 1, 2; x = [[long [[nested]]
+            string]], ["y"] = function(self) return self end, }
+            repeat local done = true until done
+            if not t.x then print("a" .. 'b') elseif table.getn(t) > 1 then error() else end
+            smua.source.output = smua.OUTPUT_ON; print(string.format("%d", math.mod(10, 3)))
+            obj:method "arg"
+            --[[ a long [[nested]]
+            comment ]]
+        "#;
+        let chunk = parse(source).unwrap();
+        let globals: Vec<_> = chunk
+            .globals
+            .iter()
+            .map(|g| (g.path.join("."), g.write))
+            .collect();
+        assert!(globals.contains(&("ipairs".to_string(), false)));
+        assert!(globals.contains(&("readings".to_string(), false)));
+        assert!(globals.contains(&("smua.source.output".to_string(), false)));
+        assert!(globals.contains(&("string.format".to_string(), false)));
+        assert!(globals.contains(&("obj.method".to_string(), false)));
+        assert!(!globals.iter().any(|(g, _)| g == "smu.source.levelv"));
+        assert!(!globals.iter().any(|(g, _)| g == "arg" || g == "done"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |source: &str| {
+            let e = parse(source).unwrap_err();
+            (e.line, e.column, e.message)
+        };
+        assert_eq!(
+            error("if x then\n  print(x)\n"),
+            (
+                3,
+                1,
+                "'end' expected (to close 'if' at line 1) near '<eof>'".to_string()
+            )
+        );
+        assert_eq!(
+            error("local x = = 1"),
+            (1, 11, "unexpected symbol near '='".to_string())
+        );
+        assert_eq!(
+            error("print(\"abc)"),
+            (1, 7, "unfinished string near '\"abc)'".to_string())
+        );
+        assert_eq!(error("x + 1").2, "syntax error near '+'");
+        assert_eq!(error("f() = 1").2, "syntax error near '='");
+        assert_eq!(error("for i = 1 do end").2, "',' expected near 'do'");
+        assert_eq!(
+            error("return 1\nprint(2)").2,
+            "'<eof>' expected near 'print'"
+        );
+        assert_eq!(
+            error("function f()\n  return 1\n  print(2)\nend").2,
+            "'end' expected (to close 'function' at line 1) near 'print'"
+        );
+
+        // Lua 5.0 has no length or modulo operators, nor levelled long brackets.
+        assert_eq!(error("n = #t").2, "unexpected symbol near '#'");
+        assert_eq!(error("n = 10 % 3").2, "unexpected symbol near '%'");
+        assert_eq!(error("s = [==[a]==]").2, "unexpected symbol near '['");
+        assert_eq!(
+            error("s = [[a [[b]] c").2,
+            "unfinished long string (starting at line 1) near '<eof>'"
+        );
+    }
+}
@@ -0,0 +1,646 @@
+//! Expand the directives in a TSP script before it is checked and uploaded, so that one
+//! script can be shared by instruments with small differences in their commands.
+//!
+//! Directives are comments that start with `--#`, so a script with directives is still
+//! a valid TSP script:
+//!
+//! - `--#include "common.tsp"` inserts a file, found relative to the including file
+//! - `--#define NAME value` replaces the name `NAME` in the code that follows with
+//!   `value`. A name that is defined without a value can only be used in conditions.
+//! - `--#undef NAME` removes a definition
+//! - `--#if`, `--#elif`, `--#else` and `--#endif` keep the lines between them only if
+//!   their condition is true
+//!
+//! Conditions compare names and literals with `==` and `~=` and combine them with `and`,
+//! `or`, `not` and parentheses, e.g. `--#if family == "tti" and not LEGACY`. `family` is
+//! the family of the instrument the script is loaded on. A name is true if it is defined
+//! and is not `false`, `0` or `nil`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    model::Family,
+    tsp::{
+        is_name,
+        lexer::{tokenize, Tok},
+        lint::family_name,
+    },
+};
+
+const DIRECTIVES: &[&str] = &["include", "define", "undef", "if", "elif", "else", "endif"];
+
+/// An error in the directives of a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreprocessError {
+    /// The file with the error
+    pub file: PathBuf,
+    /// The line of the error, starting at 1
+    pub line: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl Display for PreprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// A script with its directives expanded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preprocessed {
+    /// The expanded script
+    pub source: String,
+    /// The file and line, starting at 1, that each line of `source` came from
+    pub origins: Vec<(PathBuf, usize)>,
+    /// The files that were included, in the order they were first included
+    pub includes: Vec<PathBuf>,
+}
+
+impl Preprocessed {
+    /// The script at `path` as it is, without expanding its directives.
+    #[must_use]
+    pub fn unchanged(path: &Path, source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            origins: (1..=source.lines().count())
+                .map(|l| (path.to_path_buf(), l))
+                .collect(),
+            includes: Vec::new(),
+        }
+    }
+
+    /// The file and line that `line` of the expanded script came from.
+    #[must_use]
+    pub fn origin(&self, line: usize) -> Option<(&Path, usize)> {
+        self.origins
+            .get(line.checked_sub(1)?)
+            .map(|(f, l)| (f.as_path(), *l))
+    }
+}
+
+/// Expand the directives in `source`, the content of the script at `path`, for an
+/// instrument of `family`.
+///
+/// The names in `defines`, e.g. from the command line, are defined for the whole
+/// script and take precedence over `--#define` and `--#undef` in the script.
+///
+/// # Errors
+/// Returns the first [`PreprocessError`] in the script or the files it includes.
+pub fn preprocess(
+    path: &Path,
+    source: &str,
+    defines: &BTreeMap<String, String>,
+    family: Option<&Family>,
+) -> Result<Preprocessed, PreprocessError> {
+    let mut preprocessor = Preprocessor {
+        overrides: defines,
+        family: family.map(family_name),
+        defined: BTreeMap::new(),
+        history: defines
+            .iter()
+            .map(|(n, v)| (n.clone(), vec![(0, Some(v.clone()))]))
+            .collect(),
+        conditions: Vec::new(),
+        lines: Vec::new(),
+        output: Preprocessed::default(),
+        stack: vec![path.to_path_buf()],
+    };
+    preprocessor.file(path, source)?;
+    let Preprocessor {
+        lines,
+        history,
+        mut output,
+        ..
+    } = preprocessor;
+    output.source = substitute(&lines, &history);
+    Ok(output)
+}
+
+/// Parse a `NAME=VALUE` definition, e.g. given with `-D` on the command line. A name
+/// without a value is defined without one, which can only be used in `--#if` conditions.
+///
+/// # Errors
+/// Returns a description of the problem if `NAME` is not a Lua name.
+pub fn parse_define(define: &str) -> Result<(String, String), String> {
+    let (name, value) = define.split_once('=').unwrap_or((define, ""));
+    let name = name.trim();
+    if !is_name(name) {
+        return Err(format!("'{name}' is not a valid name"));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// An `--#if` block that is open.
+struct Condition {
+    /// The line of the `--#if`
+    line: usize,
+    /// Whether the lines around the block are kept
+    parent: bool,
+    /// Whether a branch of the block was kept
+    taken: bool,
+    /// Whether the lines of the current branch are kept
+    active: bool,
+    /// Whether the `--#else` was seen
+    seen_else: bool,
+}
+
+struct Preprocessor<'a> {
+    overrides: &'a BTreeMap<String, String>,
+    family: Option<String>,
+    defined: BTreeMap<String, String>,
+    /// The line of the expanded script after which a name has a value, in order
+    history: BTreeMap<String, Vec<(usize, Option<String>)>>,
+    conditions: Vec<Condition>,
+    lines: Vec<String>,
+    output: Preprocessed,
+    /// The files that are being expanded, to find files that include themselves
+    stack: Vec<PathBuf>,
+}
+
+impl Preprocessor<'_> {
+    fn push(&mut self, text: &str, path: &Path, line: usize) {
+        self.lines.push(text.to_string());
+        self.output.origins.push((path.to_path_buf(), line));
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.overrides
+            .get(name)
+            .or_else(|| self.defined.get(name))
+            .cloned()
+            .or_else(|| (name == "family").then(|| self.family.clone()).flatten())
+    }
+
+    fn file(&mut self, path: &Path, source: &str) -> Result<(), PreprocessError> {
+        let depth = self.conditions.len();
+        for (i, text) in source.lines().enumerate() {
+            let line = i.saturating_add(1);
+            let error = |message: String| PreprocessError {
+                file: path.to_path_buf(),
+                line,
+                message,
+            };
+            let active = self.conditions.last().is_none_or(|c| c.active);
+            let Some((directive, args)) = directive(text) else {
+                self.push(if active { text } else { "" }, path, line);
+                continue;
+            };
+            // Directives are kept as empty lines, so that the lines of the script
+            // around them stay close to where they are in the file.
+            self.push("", path, line);
+            match directive {
+                "if" => {
+                    let value = active && evaluate(args, &|n| self.lookup(n)).map_err(error)?;
+                    self.conditions.push(Condition {
+                        line,
+                        parent: active,
+                        taken: value,
+                        active: value,
+                        seen_else: false,
+                    });
+                }
+                "elif" | "else" | "endif" => {
+                    if self.conditions.len() <= depth {
+                        return Err(error(format!("`--#{directive}` without `--#if`")));
+                    }
+                    if directive == "endif" {
+                        self.conditions.pop();
+                        continue;
+                    }
+                    let (parent, taken, seen_else) = self
+                        .conditions
+                        .last()
+                        .map(|c| (c.parent, c.taken, c.seen_else))
+                        .unwrap_or_default();
+                    if seen_else {
+                        return Err(error(format!("`--#{directive}` after `--#else`")));
+                    }
+                    let value = parent
+                        && !taken
+                        && (directive == "else"
+                            || evaluate(args, &|n| self.lookup(n)).map_err(error)?);
+                    if let Some(c) = self.conditions.last_mut() {
+                        c.active = value;
+                        c.taken |= value;
+                        c.seen_else = directive == "else";
+                    }
+                }
+                _ if !active => {}
+                "define" | "undef" => {
+                    let (name, value) = args
+                        .split_once(char::is_whitespace)
+                        .map_or((args, ""), |(n, v)| (n, v.trim()));
+                    if !is_name(name) {
+                        return Err(error(format!("`{name}` is not a valid name")));
+                    }
+                    if directive == "undef" && !value.is_empty() {
+                        return Err(error("`--#undef` only takes a name".to_string()));
+                    }
+                    if self.overrides.contains_key(name) {
+                        continue;
+                    }
+                    let value = (directive == "define").then(|| value.to_string());
+                    match &value {
+                        Some(v) => self.defined.insert(name.to_string(), v.clone()),
+                        None => self.defined.remove(name),
+                    };
+                    self.history
+                        .entry(name.to_string())
+                        .or_default()
+                        .push((self.lines.len(), value));
+                }
+                "include" => {
+                    let Some(file) = literal(args) else {
+                        return Err(error("expected the file to include in quotes".to_string()));
+                    };
+                    let included = path
+                        .parent()
+                        .map_or_else(|| PathBuf::from(&file), |p| p.join(&file));
+                    let key = std::fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+                    if self
+                        .stack
+                        .iter()
+                        .any(|f| std::fs::canonicalize(f).unwrap_or_else(|_| f.clone()) == key)
+                    {
+                        return Err(error(format!("`{file}` includes itself")));
+                    }
+                    let contents = std::fs::read_to_string(&included).map_err(|e| {
+                        error(format!("unable to read {}: {e}", included.display()))
+                    })?;
+                    if !self.output.includes.contains(&included) {
+                        self.output.includes.push(included.clone());
+                    }
+                    self.stack.push(included.clone());
+                    self.file(&included, &contents)?;
+                    self.stack.pop();
+                }
+                _ => {}
+            }
+        }
+        if let Some(open) = self.conditions.get(depth) {
+            return Err(PreprocessError {
+                file: path.to_path_buf(),
+                line: open.line,
+                message: "`--#if` without `--#endif`".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The directive on `line` and its arguments, if the line is a directive.
+fn directive(line: &str) -> Option<(&'static str, &str)> {
+    let rest = line.trim_start().strip_prefix("--#")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (name, args) = rest.split_at(end);
+    DIRECTIVES
+        .iter()
+        .find(|d| **d == name)
+        .map(|d| (*d, args.trim()))
+}
+
+/// The content of a string literal in quotes.
+fn literal(text: &str) -> Option<String> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    text.strip_prefix(quote)?
+        .strip_suffix(quote)
+        .map(ToString::to_string)
+}
+
+/// The value of a name or literal, so that `"tti"`, `'tti'` and `tti` are the same.
+fn unquote(value: &str) -> String {
+    literal(value).unwrap_or_else(|| value.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Literal(String),
+    Operator(&'static str),
+}
+
+/// Evaluate the condition `text`, looking up the values of names with `lookup`.
+fn evaluate(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<bool, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some(end) if end == c => break,
+                    Some(v) => value.push(v),
+                    None => return Err("unfinished string in condition".to_string()),
+                }
+            }
+            tokens.push(Token::Literal(value));
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            let mut word = String::new();
+            while let Some(&w) = chars.peek() {
+                if !(w.is_ascii_alphanumeric() || w == '_' || w == '.') {
+                    break;
+                }
+                word.push(w);
+                chars.next();
+            }
+            tokens.push(match word.as_str() {
+                "and" => Token::Operator("and"),
+                "or" => Token::Operator("or"),
+                "not" => Token::Operator("not"),
+                _ if c.is_ascii_digit() || c == '.' => Token::Literal(word),
+                _ => Token::Name(word),
+            });
+        } else {
+            chars.next();
+            let operator = match (c, chars.peek()) {
+                ('(', _) => "(",
+                (')', _) => ")",
+                ('=', Some('=')) => "==",
+                ('~' | '!', Some('=')) => "~=",
+                _ => return Err(format!("unexpected `{c}` in condition")),
+            };
+            if operator.len() > 1 {
+                chars.next();
+            }
+            tokens.push(Token::Operator(operator));
+        }
+    }
+    if tokens.is_empty() {
+        return Err("expected a condition".to_string());
+    }
+
+    let mut parser = ConditionParser {
+        tokens,
+        pos: 0,
+        lookup,
+    };
+    let value = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(t) => Err(format!("unexpected {} in condition", describe(t))),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(n) => format!("`{n}`"),
+        Token::Literal(l) => format!("\"{l}\""),
+        Token::Operator(o) => format!("`{o}`"),
+    }
+}
+
+struct ConditionParser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl ConditionParser<'_> {
+    fn next_if(&mut self, operator: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Operator(o)) if *o == operator);
+        if found {
+            self.pos = self.pos.saturating_add(1);
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.next_if("or") {
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.unary()?;
+        while self.next_if("and") {
+            value &= self.unary()?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<bool, String> {
+        if self.next_if("not") {
+            return Ok(!self.unary()?);
+        }
+        if self.next_if("(") {
+            let value = self.or()?;
+            if !self.next_if(")") {
+                return Err("expected `)` in condition".to_string());
+            }
+            return Ok(value);
+        }
+        let left = self.operand()?;
+        if self.next_if("==") {
+            return Ok(left == self.operand()?);
+        }
+        if self.next_if("~=") {
+            return Ok(left != self.operand()?);
+        }
+        Ok(left.is_some_and(|v| !matches!(v.as_str(), "false" | "0" | "nil")))
+    }
+
+    /// The value of a name or literal, or `None` for a name that is not defined.
+    fn operand(&mut self) -> Result<Option<String>, String> {
+        let value = match self.tokens.get(self.pos) {
+            Some(Token::Name(n)) => (self.lookup)(n).map(|v| unquote(&v)),
+            Some(Token::Literal(l)) => Some(l.clone()),
+            Some(t) => return Err(format!("unexpected {} in condition", describe(t))),
+            None => return Err("unfinished condition".to_string()),
+        };
+        self.pos = self.pos.saturating_add(1);
+        Ok(value)
+    }
+}
+
+/// Join `lines` and replace the defined names in their code with their values. Names
+/// in strings and comments, and fields like `smu.NAME`, are not replaced.
+fn substitute(
+    lines: &[String],
+    history: &BTreeMap<String, Vec<(usize, Option<String>)>>,
+) -> String {
+    let mut source: String = lines.iter().flat_map(|l| [l.as_str(), "\n"]).collect();
+    if history.is_empty() {
+        return source;
+    }
+    // A script that can't be split into tokens is left as is, its syntax error is
+    // reported when it is checked.
+    let Ok(tokens) = tokenize(&source) else {
+        return source;
+    };
+
+    let mut replacements: BTreeMap<usize, Vec<(usize, usize, &str)>> = BTreeMap::new();
+    let mut previous = None;
+    for token in &tokens {
+        if let Tok::Name(name) = &token.tok {
+            let field = matches!(previous, Some(&Tok::Symbol("." | ":")));
+            let value = history
+                .get(name)
+                .and_then(|h| h.iter().rev().find(|(after, _)| *after < token.line))
+                .and_then(|(_, v)| v.as_deref())
+                .filter(|v| !v.is_empty());
+            if let Some(value) = value.filter(|_| !field) {
+                replacements.entry(token.line).or_default().push((
+                    token.column.saturating_sub(1),
+                    name.chars().count(),
+                    value,
+                ));
+            }
+        }
+        previous = Some(&token.tok);
+    }
+    if replacements.is_empty() {
+        return source;
+    }
+
+    source.clear();
+    for (i, line) in lines.iter().enumerate() {
+        match replacements.get(&i.saturating_add(1)) {
+            Some(found) => {
+                let mut chars: Vec<char> = line.chars().collect();
+                for (start, len, value) in found.iter().rev() {
+                    let end = start.saturating_add(*len);
+                    if end <= chars.len() {
+                        chars.splice(*start..end, value.chars());
+                    }
+                }
+                source.extend(chars);
+            }
+            None => source.push_str(line),
+        }
+        source.push('\n');
+    }
+    source
+}
+
+#[cfg(test)]
+mod unit {
+    use std::collections::BTreeMap;
+
+    use super::{evaluate, parse_define, preprocess};
+    use crate::model::Family;
+
+    #[test]
+    fn conditions() {
+        let lookup = |n: &str| match n {
+            "family" => Some("tti".to_string()),
+            "VARIANT" => Some("\"b\"".to_string()),
+            "LEGACY" => Some("0".to_string()),
+            "DEBUG" => Some(String::new()),
+            _ => None,
+        };
+        let eval = |text| evaluate(text, &lookup);
+        assert_eq!(eval(r#"family == "tti""#), Ok(true));
+        assert_eq!(eval(r"family ~= '26xx'"), Ok(true));
+        assert_eq!(eval(r#"VARIANT == "b" and not LEGACY"#), Ok(true));
+        assert_eq!(eval("DEBUG and (UNDEFINED or LEGACY)"), Ok(false));
+        assert_eq!(eval("not (UNDEFINED) or 1 != 1"), Ok(true));
+        assert!(eval("family ==").is_err());
+        assert!(eval("family = 'tti'").is_err());
+        assert!(eval("(DEBUG").is_err());
+    }
+
+    #[test]
+    fn define_arguments() {
+        let define = |d| parse_define(d);
+        assert_eq!(define("LIMIT=105"), Ok(("LIMIT".into(), "105".into())));
+        assert_eq!(define(" DEBUG "), Ok(("DEBUG".into(), String::new())));
+        assert_eq!(define("EXPR=a == b"), Ok(("EXPR".into(), "a == b".into())));
+        assert!(define("2FAST=1").is_err());
+        assert!(define("end=1").is_err());
+        assert!(define("=1").is_err());
+    }
+
+    #[test]
+    fn directives() {
+        let dir = std::env::temp_dir().join(format!("kic-preprocess-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("main.tsp");
+        std::fs::write(
+            dir.join("common.tsp"),
+            "--#define LIMIT 105\nfunction limit() return LIMIT end\n",
+        )
+        .unwrap();
+        std::fs::write(&script, "--#include \"loop.tsp\"\n").unwrap();
+        std::fs::write(dir.join("loop.tsp"), "--#include \"main.tsp\"\n").unwrap();
+
+        let source = "\
+--#include \"common.tsp\"
+--#if family == \"tti\"
+smu.source.level = LIMIT
+--#elif family == \"26xx\"
+smua.source.levelv = LIMIT
+--#else
+error(\"unsupported\")
+--#endif
+print(\"LIMIT\", smu.LIMIT)
+--#undef LIMIT
+print(LIMIT)
+";
+        let expand = |defines: &BTreeMap<String, String>, family| {
+            preprocess(&script, source, defines, family)
+        };
+
+        let tti = expand(&BTreeMap::new(), Some(&Family::Tti)).unwrap();
+        let code: Vec<&str> = tti.source.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            code,
+            vec![
+                "function limit() return 105 end",
+                "smu.source.level = 105",
+                "print(\"LIMIT\", smu.LIMIT)",
+                "print(LIMIT)",
+            ]
+        );
+        assert_eq!(tti.includes, vec![dir.join("common.tsp")]);
+        let line = tti
+            .source
+            .lines()
+            .position(|l| l.starts_with("function"))
+            .unwrap();
+        assert_eq!(
+            tti.origin(line + 1),
+            Some((dir.join("common.tsp").as_path(), 2))
+        );
+        let line = tti
+            .source
+            .lines()
+            .position(|l| l.starts_with("smu."))
+            .unwrap();
+        assert_eq!(tti.origin(line + 1), Some((script.as_path(), 3)));
+
+        let defines = BTreeMap::from([("LIMIT".to_string(), "21".to_string())]);
+        let ki2600 = expand(&defines, Some(&Family::_26xx)).unwrap();
+        assert!(ki2600.source.contains("smua.source.levelv = 21\n"));
+        assert!(ki2600.source.contains("\nprint(21)\n"));
+        assert!(!ki2600.source.contains("smu.source.level"));
+
+        let unknown = expand(&BTreeMap::new(), None).unwrap();
+        assert!(unknown.source.contains("error(\"unsupported\")"));
+
+        let error =
+            preprocess(&script, "--#if DEBUG\nprint(1)\n", &BTreeMap::new(), None).unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (1, "`--#if` without `--#endif`")
+        );
+        let error =
+            preprocess(&script, "--#include \"loop.tsp\"\n", &BTreeMap::new(), None).unwrap_err();
+        assert_eq!(error.file, dir.join("loop.tsp"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
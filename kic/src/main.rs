@@ -16,26 +16,49 @@ use clap::{
     Command, Subcommand,
 };
 use colored::Colorize;
-use instrument_repl::repl::{self};
+use instrument_repl::{
+    diagnostics::{read_errors, write_json, Diagnostic, SourceMap},
+    repl::{self},
+    transcript,
+    tui::Status,
+    TSP_LINK_NODES_TSP,
+};
 use regex::Regex;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env::set_var,
     fs::OpenOptions,
-    io::{stdin, Read, Write},
+    io::{stdin, IsTerminal, Read, Write},
     net::{IpAddr, SocketAddr, TcpStream},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use tracing::{debug, error, info, instrument, level_filters::LevelFilter, trace, warn};
 use tracing_subscriber::{layer::SubscriberExt, Layer, Registry};
 
 use kic_lib::{
-    instrument::{authenticate::Authentication, read_until, Instrument, State},
-    model::connect_to,
+    instrument::{
+        authenticate::Authentication,
+        backup::{create_backup, restore_backup, Backup, Compatibility, RestoreOptions},
+        clear_output_queue,
+        credentials::{
+            credential_id, Credential, CredentialError, CredentialStore, CREDENTIALS_FILE_ENV,
+            CREDENTIALS_KEY_ENV,
+        },
+        firmware::{FlashOptions, ProgressFormat},
+        read_until,
+        snapshot::{take_snapshot, Snapshot},
+        tsplink::{query_status, sync_script},
+        Instrument, ScriptOptions, State,
+    },
+    model::{connect_to, Model},
+    tsp::{self, check_syntax, parse_define, preprocess, LintSeverity, Minify},
     ConnectionInfo,
 };
 
@@ -92,6 +115,19 @@ fn add_connection_subcommands(
             .required(false)
             .long("username")
             .value_parser(value_parser!(String)),
+    ).arg(
+        Arg::new("takeover")
+            .help("If another session holds the instrument, terminate it and log in without asking. Only supported over LAN.")
+            .required(false)
+            .long("takeover")
+            .action(ArgAction::SetTrue),
+    ).arg(
+        Arg::new("no-takeover")
+            .help("If another session holds the instrument, fail without asking to terminate it.")
+            .required(false)
+            .long("no-takeover")
+            .conflicts_with("takeover")
+            .action(ArgAction::SetTrue),
     );
 
     for arg in additional_args {
@@ -149,6 +185,10 @@ fn cmds() -> Command {
             let cmd = Command::new("connect")
                 .about("Connect to an instrument over one of the provided interfaces");
             add_connection_subcommands(cmd, [
+                Arg::new("tui")
+                    .long("tui")
+                    .help("Use a full-screen interface with panes for the output, the errors and the state of the instrument")
+                    .action(ArgAction::SetTrue),
                 Arg::new("dump-output")
                     .short('o')
                     .long("dump-output")
@@ -182,14 +222,25 @@ fn cmds() -> Command {
                     .help("Print the instrument information in JSON format.")
                     .long("json")
                     .short('j')
-                    .action(ArgAction::SetTrue)
+                    .action(ArgAction::SetTrue),
+                Arg::new("detailed")
+                    .help("Also get the installed options, modules, LAN identity, TSP-Link nodes and calibration dates. This requires connecting to the instrument.")
+                    .long("detailed")
+                    .short('d')
+                    .action(ArgAction::SetTrue),
             ])
         })
         .subcommand({
             let cmd = Command::new("check-login")
                 .about("Check if a login is required for the given instrument.");
 
-            add_connection_subcommands(cmd, [])
+            add_connection_subcommands(cmd, [
+                Arg::new("json")
+                    .help("Print the login state, and the session holding the instrument if it is known, in JSON format.")
+                    .long("json")
+                    .short('j')
+                    .action(ArgAction::SetTrue),
+            ])
         })
         .subcommand({
             let cmd = Command::new("login")
@@ -197,6 +248,23 @@ fn cmds() -> Command {
 
             add_connection_subcommands(cmd, [])
         })
+        .subcommand(
+            Command::new("logout")
+                .about("Log out of the given instrument so that it is locked again.")
+                .arg(
+                    Arg::new("addr")
+                        .help("The IP address or VISA resource string (requires VISA driver) to connect to")
+                        .required(true)
+                        .value_parser(value_parser!(ConnectionInfo)),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("If another session holds the instrument, terminate it. Only supported over LAN.")
+                        .long("force")
+                        .short('f')
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand({
             let cmd = Command::new("dump")
                 .about("Dump the contents of the instrument output and error queue without any initial setup.");
@@ -226,6 +294,13 @@ fn cmds() -> Command {
                         .help("[VersaTest only] Update a module in given slot number instead of the VersaTest mainframe")
                         .required(false)
                         .value_parser(value_parser!(u16).range(1..=3)),
+
+                    Arg::new("progress")
+                        .long("progress")
+                        .help("How transfer progress is reported: an interactive progress bar or one JSON object per line on stdout, while messages go to stderr. Writing `cancel` to stdin stops the transfer before it is complete.")
+                        .required(false)
+                        .default_value("bar")
+                        .value_parser(["bar", "json"]),
             ])
         })
         .subcommand({
@@ -251,8 +326,368 @@ fn cmds() -> Command {
                         .long("save")
                         .action(ArgAction::SetTrue)
                         .help("Save the script to the non-volatile memory of the instrument"),
+
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .value_parser(PathBufValueParser::new())
+                        .help("Write the errors located in the script file to this path as JSON, or to stdout if `-`"),
+
+                    Arg::new("no-check")
+                        .long("no-check")
+                        .action(ArgAction::SetTrue)
+                        .help("Load the script without checking its syntax first"),
+
+                    Arg::new("watch")
+                        .short('w')
+                        .long("watch")
+                        .action(ArgAction::SetTrue)
+                        .help("Stay connected and load the script again whenever it changes, until Ctrl+C is pressed"),
+
+                    Arg::new("depends")
+                        .long("depends")
+                        .requires("watch")
+                        .action(ArgAction::Append)
+                        .value_parser(PathBufValueParser::new())
+                        .help("Also load the script again when this file changes (only when using `--watch`). Can be given multiple times."),
+
+                    Arg::new("define")
+                        .short('D')
+                        .long("define")
+                        .value_name("NAME=VALUE")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_define)
+                        .help("Define NAME for the `--#define` and `--#if` directives of the script, overriding its definition in the script. Can be given multiple times."),
+
+                    Arg::new("minify")
+                        .long("minify")
+                        .num_args(0..=1)
+                        .default_missing_value("all")
+                        .value_parser(["comments", "whitespace", "all"])
+                        .help("Remove comments, whitespace or both from the script before it is loaded, to make the transfer smaller. The lines of the script are kept so errors still refer to the right line."),
+
+                    Arg::new("progress")
+                        .long("progress")
+                        .help("How the progress of loading the script is reported: not at all, an interactive progress bar or one JSON object per line on stdout")
+                        .default_value("none")
+                        .value_parser(["none", "bar", "json"]),
+            ])
+        })
+        .subcommand(
+            Command::new("lint")
+                .about("Check TSP scripts for syntax errors and names that are not defined on the instrument. Exits with status 1 if problems are found.")
+                .arg(
+                    Arg::new("files")
+                        .help("The script files to check")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(PathBufValueParser::new()),
+                )
+                .arg(
+                    Arg::new("model")
+                        .help("The model the scripts are written for, e.g. `2450`. Names of all models are accepted if not given.")
+                        .short('m')
+                        .long("model")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the problems in JSON format.")
+                        .long("json")
+                        .short('j')
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand({
+            let script_name = Arg::new("name")
+                .help("The name of the script on the instrument")
+                .required(true)
+                .value_parser(value_parser!(String));
+
+            Command::new("scripts")
+                .about("Manage the scripts saved in the non-volatile memory of an instrument.")
+                .subcommand_required(true)
+                .subcommand(add_connection_subcommands(
+                    Command::new("list").about("List the scripts saved on the instrument."),
+                    [Arg::new("json")
+                        .help("Print the list of scripts in JSON format.")
+                        .long("json")
+                        .short('j')
+                        .action(ArgAction::SetTrue)],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("show").about("Print the source of a script saved on the instrument."),
+                    [script_name.clone()],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("pull").about("Save the source of scripts on the instrument to local files named `<name>.tsp`."),
+                    [
+                        Arg::new("name")
+                            .help("The names of the scripts to retrieve (defaults to all saved scripts)")
+                            .required(false)
+                            .num_args(0..)
+                            .value_parser(value_parser!(String)),
+                        Arg::new("output")
+                            .short('o')
+                            .long("output")
+                            .help("The directory to which the scripts should be written")
+                            .default_value(".")
+                            .value_parser(PathBufValueParser::new()),
+                    ],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("delete").about("Delete a script from the instrument."),
+                    [script_name.clone()],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("autorun").about("Set whether a script runs when the instrument powers on."),
+                    [
+                        script_name.clone(),
+                        Arg::new("state")
+                            .help("Whether autorun should be enabled")
+                            .required(true)
+                            .value_parser(["on", "off"]),
+                    ],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("run").about("Run a script saved on the instrument and print its output."),
+                    [script_name],
+                ))
+        })
+        .subcommand({
+            let cmd = Command::new("backup")
+                .about("Save the user scripts, setups, TSP-Link configuration and identity of an instrument to a single archive file.");
+            add_connection_subcommands(cmd, [
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .help("The archive file to write (defaults to `<model>_<serial>_<date>.json`)")
+                    .required(false)
+                    .value_parser(PathBufValueParser::new()),
+            ])
+        })
+        .subcommand({
+            let cmd = Command::new("restore")
+                .about("Restore an archive created with `backup` onto the same or a replacement instrument.");
+            add_connection_subcommands(cmd, [
+                Arg::new("archive")
+                    .help("The archive file created by `backup`")
+                    .required(true)
+                    .value_parser(PathBufValueParser::new()),
+                Arg::new("force")
+                    .long("force")
+                    .help("Restore even if the archive was taken from an incompatible model")
+                    .action(ArgAction::SetTrue),
+                Arg::new("apply-setups")
+                    .long("apply-setups")
+                    .help("Apply the setups in the archive to the instrument (setups are not restored otherwise)")
+                    .action(ArgAction::SetTrue),
+            ])
+        })
+        .subcommand({
+            let cmd = Command::new("snapshot")
+                .about("Save a canonical tree of the settings of an instrument in JSON format.");
+            add_connection_subcommands(cmd, [
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .help("The file to which the snapshot should be written (defaults to stdout)")
+                    .required(false)
+                    .value_parser(PathBufValueParser::new()),
+            ])
+        })
+        .subcommand({
+            let cmd = Command::new("replay")
+                .about("Run the commands of a transcript recorded with `.save --transcript` and compare the output and errors with the transcript. Exits with status 1 if they differ. Transcripts with REPL commands that run TSP, such as `.script`, can't be replayed.");
+            add_connection_subcommands(cmd, [
+                Arg::new("transcript")
+                    .help("The transcript to replay")
+                    .required(true)
+                    .value_parser(PathBufValueParser::new()),
+                Arg::new("timeout")
+                    .short('t')
+                    .long("timeout")
+                    .help("The longest time in seconds that a command may take")
+                    .default_value("30")
+                    .value_parser(value_parser!(u64)),
             ])
         })
+        .subcommand(
+            Command::new("diff")
+                .about("Compare the settings of two instruments or snapshots. Exits with status 1 if they differ.")
+                .arg(
+                    Arg::new("a")
+                        .help("The IP address, VISA resource string or snapshot file of the first instrument")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("b")
+                        .help("The IP address, VISA resource string or snapshot file of the second instrument")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("ignore")
+                        .short('i')
+                        .long("ignore")
+                        .help("Ignore the settings under the given path (e.g. `lan` or `digio.line[1]`). Can be given multiple times.")
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the differences in JSON format.")
+                        .long("json")
+                        .short('j')
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("keyring")
+                        .help("Attempt to look up the credentials for the instruments using the provided id in the system keyring")
+                        .required(false)
+                        .long("keyring")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("password")
+                        .help("Use the provided password to authenticate with the instruments.")
+                        .required(false)
+                        .long("password")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("username")
+                        .help("Use the provided username to authenticate with the instruments.")
+                        .required(false)
+                        .long("username")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand({
+            let id = Arg::new("id")
+                .help("The id of the credential, by convention `<model>#<serial>` as printed by `login`")
+                .required(true)
+                .value_parser(value_parser!(String));
+
+            Command::new("credentials")
+                .about(format!("Manage the credentials used to log in to instruments. These are stored in the system keyring unless `{CREDENTIALS_FILE_ENV}` and `{CREDENTIALS_KEY_ENV}` select an encrypted credentials file."))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List the ids and usernames of the stored credentials.")
+                        .arg(
+                            Arg::new("json")
+                                .help("Print the list of credentials in JSON format.")
+                                .long("json")
+                                .short('j')
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("Store a credential, replacing any existing one with the same id. The password is prompted for if not given.")
+                        .arg(id.clone())
+                        .arg(
+                            Arg::new("username")
+                                .help("The username to store (only required by some instruments)")
+                                .long("username")
+                                .value_parser(value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("password")
+                                .help("The password to store")
+                                .long("password")
+                                .value_parser(value_parser!(String)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a stored credential.")
+                        .arg(id),
+                )
+                .subcommand(
+                    Command::new("migrate")
+                        .about("Move the credentials stored for an instrument to its new serial number, e.g. after a repair.")
+                        .arg(
+                            Arg::new("old-serial")
+                                .help("The serial number the credentials are stored under")
+                                .required(true)
+                                .value_parser(value_parser!(String)),
+                        )
+                        .arg(
+                            Arg::new("new-serial")
+                                .help("The new serial number of the instrument")
+                                .required(true)
+                                .value_parser(value_parser!(String)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("test")
+                        .about("Log in to an instrument with a stored credential to check that it is still valid.")
+                        .arg(
+                            Arg::new("addr")
+                                .help("The IP address or VISA resource string (requires VISA driver) to connect to")
+                                .required(true)
+                                .value_parser(value_parser!(ConnectionInfo)),
+                        )
+                        .arg(
+                            Arg::new("keyring")
+                                .help("The id of the credential to use (defaults to `<model>#<serial>` of the instrument)")
+                                .long("keyring")
+                                .value_parser(value_parser!(String)),
+                        ),
+                )
+        })
+        .subcommand({
+            let json = Arg::new("json")
+                .help("Print the TSP-Link network in JSON format.")
+                .long("json")
+                .short('j')
+                .action(ArgAction::SetTrue);
+            Command::new("tsplink")
+                .about("Inspect and manage the TSP-Link™ network of an instrument.")
+                .subcommand_required(true)
+                .subcommand(add_connection_subcommands(
+                    Command::new("status")
+                        .about("Show the state of the TSP-Link network and report nodes of the same model running different firmware."),
+                    [json.clone()],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("nodes")
+                        .about("List the node number, model, serial number and firmware of every node on the TSP-Link network."),
+                    [json.clone()],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("reset")
+                        .about("Reset the TSP-Link network and list the nodes that were found."),
+                    [json],
+                ))
+                .subcommand(add_connection_subcommands(
+                    Command::new("sync")
+                        .about("Load a TSP script on every node of the TSP-Link network."),
+                    [
+                        Arg::new("file")
+                            .help("The TSP script to load")
+                            .required(true)
+                            .value_parser(value_parser!(PathBuf)),
+                        Arg::new("name")
+                            .help("The name of the script on the instruments (defaults to the file name)")
+                            .long("name")
+                            .short('n')
+                            .value_parser(value_parser!(String)),
+                        Arg::new("save")
+                            .help("Save the script to the non-volatile memory of each node.")
+                            .long("save")
+                            .short('s')
+                            .action(ArgAction::SetTrue),
+                        Arg::new("run")
+                            .help("Run the script on every node after it is loaded.")
+                            .long("run")
+                            .short('r')
+                            .action(ArgAction::SetTrue),
+                    ],
+                ))
+        })
         .subcommand({
             let cmd = Command::new("terminate")
                 .about("Terminate all the connections on the given instrument. Only supports LAN.");
@@ -461,12 +896,42 @@ fn main() -> anyhow::Result<()> {
         Some(("script", sub_matches)) => {
             return script(sub_matches);
         }
+        Some(("scripts", sub_matches)) => {
+            return scripts(sub_matches);
+        }
+        Some(("backup", sub_matches)) => {
+            return backup(sub_matches);
+        }
+        Some(("restore", sub_matches)) => {
+            return restore(sub_matches);
+        }
+        Some(("snapshot", sub_matches)) => {
+            return snapshot(sub_matches);
+        }
+        Some(("diff", sub_matches)) => {
+            return diff(sub_matches);
+        }
+        Some(("replay", sub_matches)) => {
+            return replay(sub_matches);
+        }
+        Some(("lint", sub_matches)) => {
+            return lint(sub_matches);
+        }
+        Some(("credentials", sub_matches)) => {
+            return credentials(sub_matches);
+        }
+        Some(("tsplink", sub_matches)) => {
+            return tsplink(sub_matches);
+        }
         Some(("check-login", sub_matches)) => {
             return check_login(sub_matches);
         }
         Some(("login", sub_matches)) => {
             return login(sub_matches);
         }
+        Some(("logout", sub_matches)) => {
+            return logout(sub_matches);
+        }
         Some(("info", sub_matches)) => {
             return info(sub_matches);
         }
@@ -532,6 +997,17 @@ fn main() -> anyhow::Result<()> {
 /// from the instrument.
 #[instrument(skip(conn))]
 fn check_connection_login_status(conn: &ConnectionInfo) -> Result<(), KicError> {
+    match connection_login_state(conn)?.0 {
+        State::Needed => Err(KicError::InstrumentPasswordProtected),
+        State::NotNeeded => Ok(()),
+        State::LogoutNeeded => Err(KicError::InstrumentLogoutRequired),
+    }
+}
+
+/// Get the login state of the instrument along with a description of the session
+/// holding it, if another session holds it and the instrument reports who that is.
+#[instrument(skip(conn))]
+fn connection_login_state(conn: &ConnectionInfo) -> Result<(State, Option<String>), KicError> {
     // We can check instrument login with Authentication::NoAuth because we aren't trying to log
     // in but simply check whether the instrument is password protected.
     let mut instrument: Box<dyn Instrument> =
@@ -544,11 +1020,13 @@ fn check_connection_login_status(conn: &ConnectionInfo) -> Result<(), KicError>
         };
 
     //TODO: Add call to not reset the instrument after disconnecting.
-    match instrument.check_login()? {
-        State::Needed => Err(KicError::InstrumentPasswordProtected),
-        State::NotNeeded => Ok(()),
-        State::LogoutNeeded => Err(KicError::InstrumentLogoutRequired),
-    }
+    let state = instrument.check_login()?;
+    let holder = if state == State::LogoutNeeded {
+        instrument.lock_holder()?
+    } else {
+        None
+    };
+    Ok((state, holder))
 }
 
 #[instrument(skip(args))]
@@ -562,6 +1040,9 @@ fn check_login(args: &ArgMatches) -> anyhow::Result<()> {
         .into());
     };
     let info = conn.get_info()?;
+    if *args.get_one::<bool>("json").unwrap_or(&false) {
+        return check_login_json(conn, &info.model, &info.serial_number);
+    }
     match check_connection_login_status(conn) {
         Ok(()) => {
             println!("NOT PROTECTED");
@@ -572,7 +1053,7 @@ fn check_login(args: &ArgMatches) -> anyhow::Result<()> {
             exit(2);
         }
         Err(KicError::InstrumentPasswordProtected) => {
-            let keyring_id = format!("{}#{}", info.model, info.serial_number);
+            let keyring_id = credential_id(&info.model, &info.serial_number);
             let keyring = Authentication::Keyring {
                 id: keyring_id.clone(),
             };
@@ -595,6 +1076,33 @@ fn check_login(args: &ArgMatches) -> anyhow::Result<()> {
     }
 }
 
+/// Print the login state of the instrument as a JSON object and exit with the same
+/// status as the plain `check-login` output.
+fn check_login_json(conn: &ConnectionInfo, model: &Model, serial: &str) -> anyhow::Result<()> {
+    let (state, holder) = connection_login_state(conn)?;
+    let keyring_id = credential_id(model, serial);
+    let keyring_id = Authentication::Keyring {
+        id: keyring_id.clone(),
+    }
+    .keyring_entry_exists()?
+    .then_some(keyring_id);
+    let (status, code) = match state {
+        State::NotNeeded => ("not-protected", 0),
+        State::LogoutNeeded => ("in-use", 2),
+        State::Needed if model.is_mp() => ("protected", 3),
+        State::Needed => ("protected", 4),
+    };
+    let output = serde_json::json!({
+        "state": status,
+        "username_required": state == State::Needed && model.is_mp(),
+        "keyring_id": keyring_id,
+        "holder": holder,
+    });
+    trace!("{output}");
+    println!("{output}");
+    exit(code);
+}
+
 #[instrument(skip(args))]
 fn login(args: &ArgMatches) -> anyhow::Result<()> {
     info!("Login to instrument");
@@ -610,10 +1118,233 @@ fn login(args: &ArgMatches) -> anyhow::Result<()> {
 
     let mut inst = connect_async_instrument(conn, auth)?;
 
+    if inst.check_login()? == State::LogoutNeeded {
+        take_over(&mut inst, conn, args)?;
+    }
     inst.login()?;
 
     let info = inst.info()?;
-    println!("{}#{}", info.model, info.serial_number);
+    println!("{}", credential_id(&info.model, &info.serial_number));
+
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn logout(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Logout from instrument");
+    let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
+        error!("No IP address or VISA resource string given");
+        return Err(KicError::ArgParseError {
+            details: "No IP address or VISA resource string given".to_string(),
+        }
+        .into());
+    };
+
+    let mut inst = connect_async_instrument(conn, Authentication::NoAuth)?;
+    let mut state = inst.check_login()?;
+    if state == State::LogoutNeeded {
+        if !*args.get_one::<bool>("force").unwrap_or(&false) {
+            let holder = inst.lock_holder()?;
+            error!("Another session holds the instrument: {holder:?}");
+            eprintln!(
+                "Another session{} holds the instrument. Use `--force` to terminate it.",
+                holder.map(|h| format!(" ({h})")).unwrap_or_default()
+            );
+            return Err(KicError::InstrumentLogoutRequired.into());
+        }
+        drop(inst);
+        terminate_sessions(conn)?;
+        eprintln!("Terminated the session holding the instrument.");
+        // Terminating the sessions also closes ours, so log out on a new one.
+        thread::sleep(Duration::from_millis(1000));
+        inst = connect_async_instrument(conn, Authentication::NoAuth)?;
+        state = inst.check_login()?;
+    }
+    match state {
+        State::LogoutNeeded => {
+            error!("Instrument still in use after terminating other sessions");
+            return Err(KicError::InstrumentLogoutRequired.into());
+        }
+        State::Needed => {
+            eprintln!("The instrument is already locked.");
+        }
+        State::NotNeeded => {
+            inst.logout()?;
+            eprintln!("Logged out of the instrument.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the flag `id` is given. Not all commands have the connection flags.
+fn flag_set(args: &ArgMatches, id: &str) -> bool {
+    args.try_get_one::<bool>(id)
+        .ok()
+        .flatten()
+        .is_some_and(|t| *t)
+}
+
+/// Whether the user wants to terminate the session holding the instrument, either
+/// through `--takeover` or, when interactive, by answering a prompt. The user is never
+/// asked with `--no-takeover` or when stdin is not a terminal.
+fn takeover_allowed(args: &ArgMatches) -> anyhow::Result<bool> {
+    if flag_set(args, "takeover") {
+        return Ok(true);
+    }
+    if flag_set(args, "no-takeover") || !stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprintln!("Another session holds the instrument. Would you like to terminate it and take control? (y/N)");
+    let mut buf = String::new();
+    stdin().read_line(&mut buf)?;
+    Ok(buf.trim().contains(['Y', 'y']))
+}
+
+/// Terminate the session holding the instrument, if the user allows it, and replace
+/// `inst` with a new connection. Login is left to the caller.
+#[instrument(skip(inst, args))]
+fn take_over(
+    inst: &mut Box<dyn Instrument>,
+    conn: &ConnectionInfo,
+    args: &ArgMatches,
+) -> anyhow::Result<()> {
+    if !takeover_allowed(args)? {
+        return Err(KicError::InstrumentLogoutRequired.into());
+    }
+    info!("Taking over instrument from another session");
+    // Terminating the sessions also closes ours.
+    terminate_sessions(conn)?;
+    thread::sleep(Duration::from_millis(1000));
+    *inst = connect_async_instrument(conn, auth_type(conn, args))?;
+    if inst.check_login()? == State::LogoutNeeded {
+        error!("Instrument still in use after terminating other sessions");
+        return Err(KicError::InstrumentLogoutRequired.into());
+    }
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn credentials(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Managing stored credentials");
+
+    let store = CredentialStore::from_env()?;
+    let keyring_hint = |e: CredentialError| -> anyhow::Error {
+        if matches!(store, CredentialStore::Keyring) {
+            anyhow::Error::from(e).context(format!(
+                "unable to access the system keyring, set `{CREDENTIALS_FILE_ENV}` and `{CREDENTIALS_KEY_ENV}` to use an encrypted credentials file instead"
+            ))
+        } else {
+            e.into()
+        }
+    };
+
+    match args.subcommand() {
+        Some(("list", args)) => {
+            let mut entries = Vec::new();
+            for id in store.list().map_err(keyring_hint)? {
+                let username = store
+                    .get(&id)
+                    .map_err(keyring_hint)?
+                    .map(|c| c.username)
+                    .unwrap_or_default();
+                entries.push(serde_json::json!({ "id": id, "username": username }));
+            }
+            if *args.get_one::<bool>("json").unwrap_or(&false) {
+                println!("{}", serde_json::to_string(&entries)?);
+            } else if entries.is_empty() {
+                eprintln!("No credentials are stored.");
+            } else {
+                for e in entries {
+                    match e["username"].as_str() {
+                        Some(u) if !u.is_empty() => {
+                            println!("{} ({u})", e["id"].as_str().unwrap_or_default())
+                        }
+                        _ => println!("{}", e["id"].as_str().unwrap_or_default()),
+                    }
+                }
+            }
+        }
+        Some(("add", args)) => {
+            let id = args
+                .get_one::<String>("id")
+                .expect("id should be a required argument");
+            let username = args
+                .get_one::<String>("username")
+                .cloned()
+                .unwrap_or_default();
+            let password = if let Some(password) = args.get_one::<String>("password") {
+                password.clone()
+            } else {
+                eprintln!("Enter Password (characters hidden):");
+                rpassword::read_password()?
+            };
+            store
+                .set(id, &Credential { username, password })
+                .map_err(keyring_hint)?;
+            eprintln!("Stored credential '{id}'.");
+        }
+        Some(("remove", args)) => {
+            let id = args
+                .get_one::<String>("id")
+                .expect("id should be a required argument");
+            if !store.remove(id).map_err(keyring_hint)? {
+                return Err(KicError::ArgParseError {
+                    details: format!("no credential is stored under '{id}'"),
+                }
+                .into());
+            }
+            eprintln!("Removed credential '{id}'.");
+        }
+        Some(("migrate", args)) => {
+            let (Some(old), Some(new)) = (
+                args.get_one::<String>("old-serial"),
+                args.get_one::<String>("new-serial"),
+            ) else {
+                unreachable!("serial numbers should be required arguments");
+            };
+            let moved = store.migrate_serial(old, new).map_err(keyring_hint)?;
+            if moved.is_empty() {
+                eprintln!("No credentials are stored for serial number '{old}'.");
+            }
+            for (from, to) in moved {
+                println!("{from} -> {to}");
+            }
+        }
+        Some(("test", args)) => {
+            let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
+                unreachable!("addr should be a required argument");
+            };
+            let id = if let Some(id) = args.get_one::<String>("keyring") {
+                id.clone()
+            } else {
+                let info = conn.get_info()?;
+                credential_id(info.model, &info.serial_number)
+            };
+            if store.get(&id).map_err(keyring_hint)?.is_none() {
+                return Err(KicError::ArgParseError {
+                    details: format!("no credential is stored under '{id}'"),
+                }
+                .into());
+            }
+
+            let mut inst =
+                connect_async_instrument(conn, Authentication::Keyring { id: id.clone() })?;
+            match inst.check_login()? {
+                State::NotNeeded => {
+                    println!("Instrument is not password protected.");
+                }
+                State::LogoutNeeded => {
+                    return Err(KicError::InstrumentLogoutRequired.into());
+                }
+                State::Needed => {
+                    inst.login()?;
+                    println!("Credential '{id}' is valid.");
+                }
+            }
+        }
+        _ => unreachable!("a credentials action is required"),
+    }
 
     Ok(())
 }
@@ -629,11 +1360,30 @@ fn connect_async_instrument(
     Ok(instrument)
 }
 
-#[instrument(skip(inst))]
-fn get_instrument_access(inst: &mut Box<dyn Instrument>) -> anyhow::Result<()> {
+/// How access to an instrument was gained by [`get_instrument_access`].
+#[derive(Debug)]
+struct Access {
+    /// The login state, after the instrument was taken over if it was
+    state: State,
+    /// Whether the session holding the instrument was terminated
+    taken_over: bool,
+}
+
+#[instrument(skip(inst, args))]
+fn get_instrument_access(
+    inst: &mut Box<dyn Instrument>,
+    conn: &ConnectionInfo,
+    args: &ArgMatches,
+) -> anyhow::Result<Access> {
     info!("Configuring instrument for usage.");
     debug!("Checking login");
-    match inst.as_mut().check_login()? {
+    let mut state = inst.as_mut().check_login()?;
+    let taken_over = state == State::LogoutNeeded;
+    if taken_over {
+        take_over(inst, conn, args)?;
+        state = inst.as_mut().check_login()?;
+    }
+    match state {
         State::Needed => {
             trace!("Login required");
             inst.as_mut().login()?;
@@ -676,7 +1426,7 @@ fn get_instrument_access(inst: &mut Box<dyn Instrument>) -> anyhow::Result<()> {
 
     info!("Instrument configured for usage");
 
-    Ok(())
+    Ok(Access { state, taken_over })
 }
 
 #[instrument(skip(conn, args))]
@@ -785,15 +1535,19 @@ fn connect(args: &ArgMatches) -> anyhow::Result<()> {
     };
 
     trace!("Configuring instrument");
-    if let Err(e) = get_instrument_access(&mut instrument) {
-        error!("Error setting up instrument: {e}");
-        eprintln!(
-            "{}",
-            format!("\nError setting up instrument: {e}\n\nUnrecoverable error. Closing.").red()
-        );
-        pause_exit_on_error();
-        return Err(e);
-    }
+    let login = match get_instrument_access(&mut instrument, conn, args) {
+        Ok(access) => access,
+        Err(e) => {
+            error!("Error setting up instrument: {e}");
+            eprintln!(
+                "{}",
+                format!("\nError setting up instrument: {e}\n\nUnrecoverable error. Closing.")
+                    .red()
+            );
+            pause_exit_on_error();
+            return Err(e);
+        }
+    };
 
     trace!("Getting instrument information");
     let info = match instrument.info() {
@@ -813,6 +1567,27 @@ fn connect(args: &ArgMatches) -> anyhow::Result<()> {
     eprintln!("{info}");
 
     let mut repl = repl::Repl::new(instrument);
+    repl.set_model(&info.model);
+    if args.get_flag("tui") {
+        repl.enable_tui(Status {
+            model: info.model.to_string(),
+            serial: info.serial_number.clone(),
+            login: format!(
+                "{}{}",
+                match login.state {
+                    State::Needed => "logged in",
+                    State::NotNeeded => "not required",
+                    State::LogoutNeeded => "held by another session",
+                },
+                if login.taken_over {
+                    " (taken over)"
+                } else {
+                    ""
+                }
+            ),
+            ..Status::default()
+        });
+    }
 
     info!("Starting instrument REPL");
     if let Err(e) = repl.start() {
@@ -907,7 +1682,7 @@ fn upgrade(args: &ArgMatches) -> anyhow::Result<()> {
         }
     };
 
-    if let Err(e) = get_instrument_access(&mut instrument) {
+    if let Err(e) = get_instrument_access(&mut instrument, conn, args) {
         error!("Error setting up instrument: {e}");
         return Err(e);
     }
@@ -950,8 +1725,32 @@ fn upgrade(args: &ArgMatches) -> anyhow::Result<()> {
         return Err(e.into());
     }
 
+    let options = FlashOptions {
+        progress: match args.get_one::<String>("progress").map(String::as_str) {
+            Some("json") => ProgressFormat::Json,
+            _ => ProgressFormat::Bar,
+        },
+        ..FlashOptions::default()
+    };
+
+    let cancel = options.cancel.clone();
+    thread::Builder::new()
+        .name("upgrade_cancel".to_string())
+        .spawn(move || {
+            for line in stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim() == "cancel" {
+                    info!("Firmware transfer cancellation requested");
+                    cancel.cancel();
+                    break;
+                }
+            }
+        })?;
+
     eprintln!("Flashing instrument firmware. Please do NOT power off or disconnect.");
-    if let Err(e) = instrument.flash_firmware(&image, slot) {
+    if let Err(e) = instrument.flash_firmware_with(&image, slot, &options) {
         error!("Error upgrading instrument: {e}");
         return Err(e.into());
     }
@@ -988,7 +1787,7 @@ fn script(args: &ArgMatches) -> anyhow::Result<()> {
         }
     };
 
-    if let Err(e) = get_instrument_access(&mut instrument) {
+    if let Err(e) = get_instrument_access(&mut instrument, conn, args) {
         error!("Error setting up instrument: {e}");
         return Err(e);
     }
@@ -1003,9 +1802,6 @@ fn script(args: &ArgMatches) -> anyhow::Result<()> {
     info!("IDN: {info}");
     eprintln!("{info}");
 
-    let run: bool = *args.get_one::<bool>("run").unwrap_or(&true);
-    let save: bool = *args.get_one::<bool>("save").unwrap_or(&false);
-
     let Some(path) = args.get_one::<PathBuf>("file").cloned() else {
         let e = KicError::ArgParseError {
             details: "script file path was not provided".to_string(),
@@ -1033,72 +1829,10 @@ fn script(args: &ArgMatches) -> anyhow::Result<()> {
 
             let script_name = format!("kic_{result}");
 
-            let mut script_content: Vec<u8> = Vec::new();
-
-            let mut file = match std::fs::File::open(path) {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("Error opening script file: {e}");
-                    return Err(e.into());
-                }
-            };
-            if let Err(e) = file.read_to_end(&mut script_content) {
-                error!("Error reading script file: {e}");
-                return Err(e.into());
-            }
-
-            eprintln!("Loading script to instrument.");
-
-            match instrument.write_all(b"localnode.prompts=1\n") {
-                Ok(()) => {}
-                Err(e) => {
-                    error!("Error file: {e}");
-                    return Err(e.into());
-                }
-            }
-            if let Err(e) = read_until(
-                &mut instrument,
-                &["TSP>".to_string()],
-                20,
-                Duration::from_millis(50),
-            ) {
-                return Err(e.into());
-            };
-            match instrument.write_script(script_name.as_bytes(), &script_content, save, run) {
-                Ok(_) => {}
-                Err(e) => return Err(e.into()),
-            }
-
-            eprintln!("Script loading completed.");
-            info!("Script loading completed.");
-
-            let mut accumulate = String::new();
-            let _ = instrument.set_nonblocking(true);
-            loop {
-                let mut buf: Vec<u8> = vec![0u8; 512];
-                match instrument.read(&mut buf) {
-                    Ok(_) => {}
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        std::thread::sleep(Duration::from_millis(1));
-                        continue;
-                    }
-                    Err(e) => return Err(e.into()),
-                };
-                let first_null = buf.iter().position(|&x| x == b'\0').unwrap_or(buf.len());
-                let buf = &buf[..first_null];
-                let buf = String::from_utf8_lossy(buf);
-                if !buf.is_empty() {
-                    accumulate = format!("{accumulate}{}", &buf);
-                }
-                let buf = buf
-                    .split("TSP>")
-                    .next()
-                    .expect("should have had one element in the buffer");
-
-                print!("{buf}");
-                if accumulate.contains("TSP>\n") {
-                    return Ok(());
-                }
+            if args.get_flag("watch") {
+                watch_script(&mut instrument, &path, &script_name, &info.model, args)
+            } else {
+                load_script(&mut instrument, &path, &script_name, &info.model, args).map(|_| ())
             }
         }
         Err(err_msg) => {
@@ -1107,34 +1841,819 @@ fn script(args: &ArgMatches) -> anyhow::Result<()> {
     }
 }
 
-#[instrument(skip(args))]
-fn reset(args: &ArgMatches) -> anyhow::Result<()> {
-    info!("Resetting instrument");
-    let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
-        error!("No IP address or VISA resource string given");
-        eprintln!(
-                "{}",
-                "\nUnable to parse connection information: no connection information given\n\nUnrecoverable error. Closing.".red()
-            );
-        pause_exit_on_error();
-        return Err(KicError::ArgParseError {
-            details: "No IP address or VISA resource string given".to_string(),
-        }
-        .into());
-    };
+/// Check the script at `path`, load it onto the instrument as `script_name`, and print
+/// its output and errors. The directives of the script are expanded for the family of
+/// `model`, and the script must fit in its script memory if the model registry gives it.
+///
+/// Returns the files that the script includes.
+fn load_script(
+    instrument: &mut Box<dyn Instrument>,
+    path: &Path,
+    script_name: &str,
+    model: &Model,
+    args: &ArgMatches,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let run: bool = *args.get_one::<bool>("run").unwrap_or(&true);
+    let save: bool = *args.get_one::<bool>("save").unwrap_or(&false);
 
-    let auth = auth_type(conn, args);
+    let mut script_content: Vec<u8> = Vec::new();
 
-    let instrument: Box<dyn Instrument> = match connect_async_instrument(conn, auth) {
-        Ok(i) => i,
+    let mut file = match std::fs::File::open(path) {
+        Ok(f) => f,
         Err(e) => {
-            error!("Error connecting to sync instrument: {e}");
+            error!("Error opening script file: {e}");
             return Err(e.into());
         }
     };
+    if let Err(e) = file.read_to_end(&mut script_content) {
+        error!("Error reading script file: {e}");
+        return Err(e.into());
+    }
 
-    // dropping the instrument will reset it appropriately.
-    drop(instrument);
+    let defines: BTreeMap<String, String> = args
+        .get_many::<(String, String)>("define")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let script = match preprocess(
+        path,
+        &String::from_utf8_lossy(&script_content),
+        &defines,
+        model.family().as_ref(),
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error preprocessing script: {e}");
+            return Err(KicError::Other(format!("unable to preprocess script: {e}")).into());
+        }
+    };
+
+    if !args.get_flag("no-check") {
+        if let Err(e) = check_syntax(&script.source) {
+            error!("TSP syntax error: {e}");
+            eprintln!("{}", format!("TSP syntax error: {}", e.message).red());
+            let d = Diagnostic::preprocessed_syntax_error(path, &script, &e);
+            eprintln!("{d}");
+            if let Some(output) = args.get_one::<PathBuf>("diagnostics") {
+                write_json(std::slice::from_ref(&d), output)?;
+            }
+            return Err(KicError::Other(format!(
+                "syntax error in {}:{}: {}",
+                d.file.display(),
+                d.line,
+                e.message
+            ))
+            .into());
+        }
+    }
+
+    eprintln!("Loading script to instrument.");
+
+    match instrument.write_all(b"localnode.prompts=1\n") {
+        Ok(()) => {}
+        Err(e) => {
+            error!("Error file: {e}");
+            return Err(e.into());
+        }
+    }
+    if let Err(e) = read_until(
+        instrument,
+        &["TSP>".to_string()],
+        20,
+        Duration::from_millis(50),
+    ) {
+        return Err(e.into());
+    };
+    let options = ScriptOptions {
+        minify: match args.get_one::<String>("minify").map(String::as_str) {
+            Some("comments") => Minify {
+                comments: true,
+                whitespace: false,
+            },
+            Some("whitespace") => Minify {
+                comments: false,
+                whitespace: true,
+            },
+            Some(_) => Minify::ALL,
+            None => Minify::default(),
+        },
+        max_size: model.script_memory(),
+        progress: match args.get_one::<String>("progress").map(String::as_str) {
+            Some("bar") => ProgressFormat::Bar,
+            Some("json") => ProgressFormat::Json,
+            _ => ProgressFormat::None,
+        },
+    };
+    match instrument.write_script_with(
+        script_name.as_bytes(),
+        script.source.as_bytes(),
+        save,
+        run,
+        &options,
+    ) {
+        Ok(_) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    eprintln!("Script loading completed.");
+    info!("Script loading completed.");
+
+    print_until_prompt(instrument)?;
+
+    let map = SourceMap::preprocessed(script_name, path, &script);
+    report_script_errors(instrument, &map, args.get_one::<PathBuf>("diagnostics"))?;
+    Ok(script.includes)
+}
+
+/// The time between checks of whether a watched script changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Load the script at `path` and load it again whenever it, a file it includes, or one
+/// of the files given with `--depends` changes, until Ctrl+C is pressed.
+fn watch_script(
+    instrument: &mut Box<dyn Instrument>,
+    path: &Path,
+    script_name: &str,
+    model: &Model,
+    args: &ArgMatches,
+) -> anyhow::Result<()> {
+    let mut watched = vec![path.to_path_buf()];
+    watched.extend(
+        args.get_many::<PathBuf>("depends")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    // Ctrl+C stops watching instead of ending the process, so that the instrument is
+    // released when this returns.
+    static STOP: AtomicBool = AtomicBool::new(false);
+    if let Err(e) = ctrlc::set_handler(|| STOP.store(true, Ordering::SeqCst)) {
+        warn!("Unable to handle Ctrl+C, it will end the process: {e}");
+    }
+    let mut includes = Vec::new();
+    let modified = |files: &[PathBuf]| -> Vec<Option<SystemTime>> {
+        files
+            .iter()
+            .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+
+    loop {
+        // A broken script should not end the session, it is loaded again once fixed.
+        // Until then, the files it included when it was last loaded are watched.
+        match load_script(instrument, path, script_name, model, args) {
+            Ok(i) => includes = i,
+            Err(e) => {
+                error!("Error loading script: {e}");
+                eprintln!("{}", e.to_string().red());
+            }
+        }
+        let mut files = watched.clone();
+        files.extend(includes.iter().filter(|i| !watched.contains(i)).cloned());
+        let names: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        let last = modified(&files);
+        eprintln!(
+            "{}",
+            format!(
+                "\nWatching {} for changes, press Ctrl+C to stop.",
+                names.join(", ")
+            )
+            .yellow()
+        );
+        loop {
+            if STOP.load(Ordering::SeqCst) {
+                info!("Stopped watching script");
+                eprintln!("{}", "\nStopped watching.".yellow());
+                return Ok(());
+            }
+            std::thread::sleep(WATCH_INTERVAL);
+            let now = modified(&files);
+            if now != last {
+                // Editors may write a file in several steps, so wait for them to finish.
+                std::thread::sleep(WATCH_INTERVAL);
+                break;
+            }
+        }
+        info!("Watched files changed, loading script again");
+        eprintln!("{}", "\nChanges detected, loading script again.".yellow());
+        // Discard the prompts that are left from the previous load.
+        instrument.write_all(b"localnode.prompts = 0\n")?;
+        clear_output_queue(instrument, 1000, Duration::from_millis(1))?;
+    }
+}
+
+/// Check the script files in `args` without connecting to an instrument.
+fn lint(args: &ArgMatches) -> anyhow::Result<()> {
+    let family = match args.get_one::<String>("model") {
+        Some(model) => {
+            let model: Model = model.parse()?;
+            let Some(family) = model.family() else {
+                return Err(KicError::ArgParseError {
+                    details: format!("model '{model}' is not supported"),
+                }
+                .into());
+            };
+            Some(family)
+        }
+        None => None,
+    };
+
+    let mut problems = Vec::new();
+    for file in args.get_many::<PathBuf>("files").into_iter().flatten() {
+        let source = std::fs::read_to_string(file)?;
+        for message in tsp::lint(&source, family.as_ref()) {
+            problems.push((file, message));
+        }
+    }
+
+    if args.get_flag("json") {
+        let problems: Vec<_> = problems
+            .iter()
+            .map(|(file, m)| serde_json::json!({"file": file, "problem": m}))
+            .collect();
+        println!("{}", serde_json::to_string(&problems)?);
+    } else {
+        for (file, m) in &problems {
+            let line = format!("{}:{m}", file.display());
+            match m.severity {
+                LintSeverity::Error => println!("{}", line.red()),
+                LintSeverity::Warning => println!("{}", line.yellow()),
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Read the errors of the instrument after a script was loaded and print them, with an
+/// excerpt of the script file for the errors that are located in it. The located errors
+/// are also written to `diagnostics` as JSON if given.
+fn report_script_errors(
+    instrument: &mut Box<dyn Instrument>,
+    map: &SourceMap,
+    diagnostics: Option<&PathBuf>,
+) -> anyhow::Result<()> {
+    let errors = read_errors(instrument)?;
+    let mut located = Vec::new();
+    for e in errors {
+        error!("TSP error: {e}");
+        eprintln!("{}", e.to_string().red());
+        if let Some(d) = map.diagnostic(&e) {
+            eprintln!("{d}");
+            located.push(d);
+        }
+    }
+    if let Some(output) = diagnostics {
+        write_json(&located, output)?;
+    }
+    Ok(())
+}
+
+/// Connect to the instrument given in `args`, log in and disable prompts so that the
+/// responses to queries can be parsed.
+fn connect_for_queries(args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
+    let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
+        error!("No IP address or VISA resource string given");
+        eprintln!(
+                "{}",
+                "\nUnable to parse connection information: no connection information given\n\nUnrecoverable error. Closing.".red()
+            );
+        pause_exit_on_error();
+        return Err(KicError::ArgParseError {
+            details: "No IP address or VISA resource string given".to_string(),
+        }
+        .into());
+    };
+
+    connect_quiet(conn, args)
+}
+
+/// Connect to the instrument at `conn`, log in and disable prompts so that the
+/// responses to queries can be parsed.
+fn connect_quiet(conn: &ConnectionInfo, args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
+    let auth = auth_type(conn, args);
+    let mut instrument: Box<dyn Instrument> = match connect_async_instrument(conn, auth) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Error connecting to sync instrument: {e}");
+            return Err(e.into());
+        }
+    };
+
+    if let Err(e) = get_instrument_access(&mut instrument, conn, args) {
+        error!("Error setting up instrument: {e}");
+        return Err(e);
+    }
+
+    // Prompts would be interleaved with the responses we need to parse.
+    instrument.write_all(b"localnode.prompts = 0\n")?;
+    clear_output_queue(&mut instrument, 1000, Duration::from_millis(1))?;
+
+    Ok(instrument)
+}
+
+#[instrument(skip(args))]
+fn backup(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Backing up instrument");
+    trace!("args: {args:?}");
+
+    let mut instrument = connect_for_queries(args)?;
+
+    eprintln!("Reading scripts, setups and TSP-Link configuration from the instrument...");
+    let backup = create_backup(instrument.as_mut())?;
+
+    let output = args
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| {
+            let date = chrono::Local::now().format("%Y%m%d");
+            PathBuf::from(format!(
+                "{}_{}_{date}.json",
+                backup.instrument.model, backup.instrument.serial_number
+            ))
+        });
+    std::fs::write(&output, serde_json::to_string_pretty(&backup)?)?;
+
+    eprintln!(
+        "Saved {} script(s) and {} setup(s) to {}",
+        backup.scripts.len(),
+        backup.setups.len(),
+        output.display()
+    );
+    info!("Backup saved to {}", output.display());
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn restore(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Restoring instrument from backup");
+    trace!("args: {args:?}");
+
+    let Some(archive) = args.get_one::<PathBuf>("archive") else {
+        return Err(KicError::ArgParseError {
+            details: "backup archive path was not provided".to_string(),
+        }
+        .into());
+    };
+    let backup: Backup = serde_json::from_str(&std::fs::read_to_string(archive)?)?;
+
+    let mut instrument = connect_for_queries(args)?;
+    let info = instrument.info()?;
+    info!("IDN: {info}");
+
+    let force = args.get_flag("force");
+    match backup.compatibility(&info) {
+        Compatibility::SameModel => {}
+        Compatibility::SameFamily => {
+            warn!(
+                "Restoring {} backup to {}",
+                backup.instrument.model, info.model
+            );
+            eprintln!(
+                "{}",
+                format!(
+                    "Warning: the backup was taken from a {}, but the instrument is a {}.",
+                    backup.instrument.model, info.model
+                )
+                .yellow()
+            );
+        }
+        Compatibility::Incompatible if force => {
+            warn!(
+                "Forcing restore of {} backup to {}",
+                backup.instrument.model, info.model
+            );
+        }
+        Compatibility::Incompatible => {
+            return Err(KicError::UnsupportedAction(format!(
+                "a backup of a {} cannot be restored to a {} (use --force to override)",
+                backup.instrument.model, info.model
+            ))
+            .into());
+        }
+    }
+    if backup.instrument.serial_number != info.serial_number {
+        eprintln!(
+            "Restoring backup of serial number {} to serial number {}.",
+            backup.instrument.serial_number, info.serial_number
+        );
+    }
+
+    let apply_setups = args.get_flag("apply-setups");
+    restore_backup(
+        instrument.as_mut(),
+        &backup,
+        RestoreOptions { apply_setups },
+    )?;
+    if apply_setups {
+        // Resetting the instrument when it is disconnected would undo the setups.
+        instrument.set_reset_on_drop(false);
+    }
+
+    eprintln!(
+        "Restored {} script(s) and applied {} setup(s) from {}",
+        backup.scripts.len(),
+        if apply_setups { backup.setups.len() } else { 0 },
+        archive.display()
+    );
+    if !apply_setups && !backup.setups.is_empty() {
+        eprintln!("Use --apply-setups to apply the setups in the archive.");
+    }
+    info!("Restore complete");
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn snapshot(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Taking snapshot of instrument settings");
+    trace!("args: {args:?}");
+
+    let mut instrument = connect_for_queries(args)?;
+    let snapshot = take_snapshot(instrument.as_mut())?;
+    let snapshot = serde_json::to_string_pretty(&snapshot)?;
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => {
+            std::fs::write(output, snapshot)?;
+            eprintln!("Saved snapshot to {}", output.display());
+        }
+        None => println!("{snapshot}"),
+    }
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn tsplink(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Managing TSP-Link network");
+    trace!("args: {args:?}");
+
+    let Some((action, args)) = args.subcommand() else {
+        unreachable!("a tsplink subcommand is required");
+    };
+    let mut instrument = connect_for_queries(args)?;
+
+    if action == "sync" {
+        let Some(file) = args.get_one::<PathBuf>("file") else {
+            return Err(KicError::ArgParseError {
+                details: "script file was not provided".to_string(),
+            }
+            .into());
+        };
+        let name = args.get_one::<String>("name").cloned().unwrap_or_else(|| {
+            file.file_stem()
+                .map(|s| {
+                    s.to_string_lossy()
+                        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+                })
+                .unwrap_or_default()
+        });
+        let source = std::fs::read(file)?;
+        let nodes = sync_script(
+            instrument.as_mut(),
+            &name,
+            &source,
+            args.get_flag("save"),
+            args.get_flag("run"),
+        )?;
+        eprintln!(
+            "Loaded script '{name}' on node(s) {}",
+            nodes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return Ok(());
+    }
+
+    let mut status = if action == "reset" {
+        eprintln!("Resetting TSP-Link network...");
+        instrument.write_script(
+            b"TSP_LINK_NODES",
+            TSP_LINK_NODES_TSP.to_string().as_bytes(),
+            false,
+            true,
+        )?;
+        let details = read_until(
+            instrument.as_mut(),
+            &["NODE>END".to_string()],
+            30_000,
+            Duration::from_millis(1),
+        )?;
+        let mut status = query_status(instrument.as_mut())?;
+        status.add_node_details(&details)?;
+        status
+    } else {
+        query_status(instrument.as_mut())?
+    };
+    status.nodes.sort_by_key(|n| n.node);
+    let mismatches = status.firmware_mismatches();
+
+    if args.get_flag("json") {
+        let mut json = serde_json::to_value(&status)?;
+        json["firmware_mismatches"] = serde_json::to_value(&mismatches)?;
+        println!("{json}");
+    } else {
+        println!(
+            "TSP-Link is {} ({} node(s))",
+            status.state,
+            status.nodes.len()
+        );
+        if let Some(local) = status.local_node {
+            println!("Local node: {local}");
+        }
+        if let Some(master) = status.master_node.filter(|_| status.is_online()) {
+            println!("Master node: {master}");
+        }
+        if action != "status" {
+            for n in &status.nodes {
+                let mut line = format!("node[{}]: {}", n.node, n.model);
+                if let Some(sn) = &n.serial_number {
+                    line.push_str(&format!(", serial {sn}"));
+                }
+                if let Some(fw) = &n.firmware_rev {
+                    line.push_str(&format!(", firmware {fw}"));
+                }
+                if !n.slots.is_empty() {
+                    line.push_str(&format!(", slots [{}]", n.slots.join(", ")));
+                }
+                println!("{line}");
+            }
+        }
+    }
+
+    for m in &mismatches {
+        eprintln!(
+            "{}",
+            format!(
+                "Firmware mismatch between {} nodes: {}",
+                m.model,
+                m.revisions
+                    .iter()
+                    .map(|(node, fw)| format!("node[{node}] {fw}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Load the snapshot from `target` if it is a file, otherwise connect to the
+/// instrument at `target`, take a snapshot of it and release it.
+fn load_or_take_snapshot(target: &str, args: &ArgMatches) -> anyhow::Result<Snapshot> {
+    let path = PathBuf::from(target);
+    if path.is_file() {
+        debug!("Loading snapshot from {}", path.display());
+        return Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?);
+    }
+    let conn: ConnectionInfo = target.parse()?;
+    let mut instrument = connect_quiet(&conn, args)?;
+    Ok(take_snapshot(instrument.as_mut())?)
+}
+
+#[instrument(skip(args))]
+fn diff(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Comparing instrument settings");
+    trace!("args: {args:?}");
+
+    let (Some(left), Some(right)) = (args.get_one::<String>("a"), args.get_one::<String>("b"))
+    else {
+        return Err(KicError::ArgParseError {
+            details: "two instruments or snapshot files must be given".to_string(),
+        }
+        .into());
+    };
+
+    // The instruments are released as soon as their snapshots are taken, so nothing is
+    // left to clean up when this exits because they differ.
+    let left = load_or_take_snapshot(left, args)?;
+    let right = load_or_take_snapshot(right, args)?;
+
+    let ignore: Vec<&String> = args
+        .get_many::<String>("ignore")
+        .into_iter()
+        .flatten()
+        .collect();
+    let differences: Vec<_> = left
+        .diff(&right)
+        .into_iter()
+        .filter(|d| !ignore.iter().any(|i| d.is_within(i)))
+        .collect();
+
+    if args.get_flag("json") {
+        println!("{}", serde_json::to_string(&differences)?);
+    } else if differences.is_empty() {
+        eprintln!("No differences found.");
+    } else {
+        println!(
+            "--- {} {}\n+++ {} {}",
+            left.instrument.model,
+            left.instrument.serial_number,
+            right.instrument.model,
+            right.instrument.serial_number
+        );
+        for d in &differences {
+            println!("{d}");
+        }
+    }
+
+    if !differences.is_empty() {
+        info!("{} settings differ", differences.len());
+        exit(1);
+    }
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn replay(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Replaying transcript");
+    trace!("args: {args:?}");
+
+    let Some(path) = args.get_one::<PathBuf>("transcript") else {
+        return Err(KicError::ArgParseError {
+            details: "transcript was not provided".to_string(),
+        }
+        .into());
+    };
+    let steps = transcript::steps(&transcript::read(path)?)?;
+    if steps.is_empty() {
+        eprintln!("{} has no commands to replay.", path.display());
+        return Ok(());
+    }
+    let timeout = Duration::from_secs(*args.get_one::<u64>("timeout").unwrap_or(&30));
+
+    let instrument = connect_for_queries(args)?;
+    let mut repl = repl::Repl::new(instrument);
+    let differences = repl.replay(&steps, timeout)?;
+    // Release the instrument (abort, logout) before `exit`, which skips `Drop`.
+    drop(repl);
+
+    if differences.is_empty() {
+        eprintln!("Replayed {} command(s), no differences found.", steps.len());
+        return Ok(());
+    }
+    for d in &differences {
+        println!("{d}");
+    }
+    info!(
+        "{} of {} replayed commands differ",
+        differences.len(),
+        steps.len()
+    );
+    exit(1);
+}
+
+/// Print everything the instrument outputs until the next TSP prompt is read.
+fn print_until_prompt(instrument: &mut Box<dyn Instrument>) -> anyhow::Result<()> {
+    let mut accumulate = String::new();
+    let _ = instrument.set_nonblocking(true);
+    loop {
+        let mut buf: Vec<u8> = vec![0u8; 512];
+        match instrument.read(&mut buf) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let first_null = buf.iter().position(|&x| x == b'\0').unwrap_or(buf.len());
+        let buf = &buf[..first_null];
+        let buf = String::from_utf8_lossy(buf);
+        if !buf.is_empty() {
+            accumulate = format!("{accumulate}{}", &buf);
+        }
+        let buf = buf
+            .split("TSP>")
+            .next()
+            .expect("should have had one element in the buffer");
+
+        print!("{buf}");
+        if accumulate.contains("TSP>\n") || accumulate.contains("TSP?\n") {
+            return Ok(());
+        }
+    }
+}
+
+#[instrument(skip(args))]
+fn scripts(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Managing scripts saved on instrument");
+    trace!("args: {args:?}");
+
+    let Some((action, args)) = args.subcommand() else {
+        return Err(KicError::ArgParseError {
+            details: "no scripts action was given".to_string(),
+        }
+        .into());
+    };
+
+    let mut instrument = connect_for_queries(args)?;
+
+    let name = args.get_one::<String>("name");
+    match (action, name) {
+        ("list", _) => {
+            let scripts = instrument.list_scripts()?;
+            if *args.get_one::<bool>("json").unwrap_or(&false) {
+                println!("{}", serde_json::to_string(&scripts)?);
+            } else if scripts.is_empty() {
+                eprintln!("No scripts are saved on the instrument.");
+            } else {
+                for s in scripts {
+                    if s.autorun {
+                        println!("{} (autorun)", s.name);
+                    } else {
+                        println!("{}", s.name);
+                    }
+                }
+            }
+        }
+        ("show", Some(name)) => {
+            println!("{}", instrument.script_source(name)?);
+        }
+        ("pull", _) => {
+            let names: Vec<String> = args
+                .get_many::<String>("name")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            let dir = args
+                .get_one::<PathBuf>("output")
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("."));
+            for path in instrument.pull_scripts(&names, &dir)? {
+                info!("Saved script to {}", path.display());
+                println!("{}", path.display());
+            }
+        }
+        ("delete", Some(name)) => {
+            instrument.delete_script(name)?;
+            eprintln!("Deleted script '{name}'.");
+        }
+        ("autorun", Some(name)) => {
+            let enable = args.get_one::<String>("state").is_some_and(|s| s == "on");
+            instrument.set_script_autorun(name, enable)?;
+            eprintln!(
+                "Autorun {} for script '{name}'.",
+                if enable { "enabled" } else { "disabled" }
+            );
+        }
+        ("run", Some(name)) => {
+            instrument.write_all(b"localnode.prompts = 1\n")?;
+            read_until(
+                &mut instrument,
+                &["TSP>".to_string()],
+                20,
+                Duration::from_millis(50),
+            )?;
+            instrument.run_script(name)?;
+            print_until_prompt(&mut instrument)?;
+        }
+        (action, _) => {
+            return Err(KicError::ArgParseError {
+                details: format!("unknown scripts action '{action}'"),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(args))]
+fn reset(args: &ArgMatches) -> anyhow::Result<()> {
+    info!("Resetting instrument");
+    let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
+        error!("No IP address or VISA resource string given");
+        eprintln!(
+                "{}",
+                "\nUnable to parse connection information: no connection information given\n\nUnrecoverable error. Closing.".red()
+            );
+        pause_exit_on_error();
+        return Err(KicError::ArgParseError {
+            details: "No IP address or VISA resource string given".to_string(),
+        }
+        .into());
+    };
+
+    let auth = auth_type(conn, args);
+
+    let instrument: Box<dyn Instrument> = match connect_async_instrument(conn, auth) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Error connecting to sync instrument: {e}");
+            return Err(e.into());
+        }
+    };
+
+    // dropping the instrument will reset it appropriately.
+    drop(instrument);
 
     info!("Instrument reset");
 
@@ -1159,6 +2678,15 @@ fn info(args: &ArgMatches) -> anyhow::Result<()> {
         }
         .into());
     };
+
+    let json: bool = *args.get_one::<bool>("json").unwrap_or(&true);
+
+    trace!("print as json?: {json:?}");
+
+    if args.get_flag("detailed") {
+        return detailed_info(args, json);
+    }
+
     let info = match conn.get_info() {
         Ok(i) => i,
         Err(e) => {
@@ -1167,10 +2695,6 @@ fn info(args: &ArgMatches) -> anyhow::Result<()> {
         }
     };
 
-    let json: bool = *args.get_one::<bool>("json").unwrap_or(&true);
-
-    trace!("print as json?: {json:?}");
-
     let info: String = if json {
         serde_json::to_string(&info)?
     } else {
@@ -1183,6 +2707,28 @@ fn info(args: &ArgMatches) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn detailed_info(args: &ArgMatches, json: bool) -> anyhow::Result<()> {
+    let mut instrument = connect_for_queries(args)?;
+    let details = match instrument.detailed_info() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Error getting detailed instrument info: {e}");
+            return Err(e.into());
+        }
+    };
+
+    let details: String = if json {
+        serde_json::to_string(&details)?
+    } else {
+        details.to_string()
+    };
+
+    info!("Information to print: {details}");
+    println!("{}", details.trim_end());
+
+    Ok(())
+}
+
 #[instrument(skip(args))]
 fn terminate(args: &ArgMatches) -> anyhow::Result<()> {
     info!("Terminating existing operations");
@@ -1201,7 +2747,17 @@ fn terminate(args: &ArgMatches) -> anyhow::Result<()> {
         }
         .into());
     };
-    let mut conn = match conn {
+    terminate_sessions(conn)?;
+
+    info!("Operations terminated");
+
+    Ok(())
+}
+
+/// Terminate all sessions on the instrument, including any held by this process, by
+/// writing `ABORT` to its LAN abort port (5030).
+fn terminate_sessions(conn: &ConnectionInfo) -> anyhow::Result<()> {
+    let mut stream = match conn {
         ConnectionInfo::VisaSocket { addr, .. } | ConnectionInfo::Lan { addr } => {
             let addr = addr.ip();
             let socket = SocketAddr::new(addr, 5030);
@@ -1223,13 +2779,11 @@ fn terminate(args: &ArgMatches) -> anyhow::Result<()> {
         }
     };
 
-    if let Err(e) = conn.write_all(b"ABORT\n") {
+    if let Err(e) = stream.write_all(b"ABORT\n") {
         error!("Unable to write 'ABORT': {e}");
         return Err(e.into());
     }
 
-    info!("Operations terminated");
-
     Ok(())
 }
 